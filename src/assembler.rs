@@ -0,0 +1,548 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::PROGRAM_START;
+
+// A two pass assembler for the same mnemonics the disassembler prints (Cowgod's, plus the
+// SUPER-CHIP and XO-CHIP additions). The first pass works out where every label lands, the
+// second encodes the instructions now that every label has an address.
+//
+//     ; comments start with a semicolon
+//     include "sprites.asm"   ; pastes another file in, relative to this one
+//     speed = 2               ; a constant
+//     start:
+//         LD V0, speed
+//         LD I, digits + 5    ; operands can add and subtract numbers, constants and labels
+//         CALL draw
+//         JP start
+//     draw: DRW V0, V1, 5
+//         RET
+//     digits:
+//         db 0xF0, 0x90, 0x90, 0x90, 0xF0
+//         dw 0x1234
+//
+// Mnemonics and registers are case insensitive, labels and constants aren't.
+// Numbers are decimal, 0x hex or 0b binary
+
+// where a line of source came from
+#[derive(Clone, PartialEq, Debug)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug)]
+pub struct AssembleError {
+    pub location: SourceLocation,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+pub struct Assembly {
+    // load this with Emulator::load_rom, or write it out as a .ch8
+    pub rom: Vec<u8>,
+    // the address of every instruction and data directive, and the line it came from
+    pub source_map: BTreeMap<u16, SourceLocation>,
+}
+
+impl Assembly {
+    // the line that produced the byte at addr
+    pub fn location_of(&self, addr: u16) -> Option<&SourceLocation> {
+        self.source_map
+            .range(..=addr)
+            .next_back()
+            .map(|(_, location)| location)
+    }
+}
+
+// the name errors report for source that didn't come from a file
+const SOURCE_NAME: &str = "<source>";
+// a file including itself would otherwise go on forever
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// includes are relative to the working directory
+pub fn assemble(source: &str) -> Result<Assembly, AssembleError> {
+    let mut lines = Vec::new();
+    read_lines(source, SOURCE_NAME, Path::new("."), 0, &mut lines)?;
+    Assembler::new(lines).assemble()
+}
+
+// includes are relative to the file
+pub fn assemble_file(path: &str) -> Result<Assembly, AssembleError> {
+    let source = fs::read_to_string(path).map_err(|e| AssembleError {
+        location: SourceLocation {
+            file: path.to_string(),
+            line: 0,
+        },
+        message: format!("could not read {}: {}", path, e),
+    })?;
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+    let mut lines = Vec::new();
+    read_lines(&source, path, dir, 0, &mut lines)?;
+    Assembler::new(lines).assemble()
+}
+
+struct Line {
+    location: SourceLocation,
+    // with the comment stripped off
+    text: String,
+}
+
+// splits the source into lines, pasting in any includes
+fn read_lines(
+    source: &str,
+    file: &str,
+    dir: &Path,
+    depth: usize,
+    lines: &mut Vec<Line>,
+) -> Result<(), AssembleError> {
+    for (i, text) in source.lines().enumerate() {
+        let location = SourceLocation {
+            file: file.to_string(),
+            line: i + 1,
+        };
+        let text = match text.find(';') {
+            Some(comment) => &text[..comment],
+            None => text,
+        }
+        .trim();
+
+        let (first_word, rest) = split_first_word(text);
+        if !first_word.eq_ignore_ascii_case("include") {
+            lines.push(Line {
+                location,
+                text: text.to_string(),
+            });
+            continue;
+        }
+
+        let error = |message: String| AssembleError {
+            location: location.clone(),
+            message,
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(error("includes are nested too deep".to_string()));
+        }
+        let name = rest.trim().trim_matches('"');
+        if name.is_empty() {
+            return Err(error("include needs a file name".to_string()));
+        }
+        let path: PathBuf = dir.join(name);
+        let included = fs::read_to_string(&path)
+            .map_err(|e| error(format!("could not include {}: {}", path.display(), e)))?;
+        let included_dir = path.parent().unwrap_or(dir).to_path_buf();
+        read_lines(
+            &included,
+            &path.display().to_string(),
+            &included_dir,
+            depth + 1,
+            lines,
+        )?;
+    }
+    Ok(())
+}
+
+fn split_first_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], &text[end..]),
+        None => (text, ""),
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// names that can't be used for labels or constants, because they mean something as an operand
+fn is_reserved(name: &str) -> bool {
+    parse_register(name).is_some()
+        || ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"]
+            .iter()
+            .any(|reserved| name.eq_ignore_ascii_case(reserved))
+}
+
+fn parse_register(operand: &str) -> Option<u8> {
+    let mut chars = operand.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(digit), None) | (Some('v'), Some(digit), None) => {
+            digit.to_digit(16).map(|value| value as u8)
+        }
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse().ok()
+    } else {
+        None
+    }
+}
+
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+struct Placed {
+    statement: Statement,
+    address: u16,
+    location: SourceLocation,
+}
+
+struct Assembler {
+    lines: Vec<Line>,
+    labels: HashMap<String, u16>,
+    // constants are kept as expressions, so they can refer to labels further down
+    constants: HashMap<String, String>,
+}
+
+impl Assembler {
+    fn new(lines: Vec<Line>) -> Self {
+        Assembler {
+            lines,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+        }
+    }
+
+    fn assemble(mut self) -> Result<Assembly, AssembleError> {
+        let placed = self.first_pass()?;
+
+        let mut rom = Vec::new();
+        let mut source_map = BTreeMap::new();
+        for item in placed.iter() {
+            let bytes = self.encode(item)?;
+            if !bytes.is_empty() {
+                source_map.insert(item.address, item.location.clone());
+            }
+            rom.extend(bytes);
+        }
+        Ok(Assembly { rom, source_map })
+    }
+
+    // gives every label its address and lays out the statements, without encoding anything
+    fn first_pass(&mut self) -> Result<Vec<Placed>, AssembleError> {
+        let mut placed = Vec::new();
+        let mut address = PROGRAM_START;
+        let lines = std::mem::take(&mut self.lines);
+        for line in lines {
+            let error = |message: String| AssembleError {
+                location: line.location.clone(),
+                message,
+            };
+            let mut text = line.text.as_str();
+
+            // any number of labels can start a line
+            while let Some(colon) = text.find(':') {
+                let name = text[..colon].trim();
+                if !is_identifier(name) {
+                    break;
+                }
+                self.define(name, &line.location)?;
+                self.labels.insert(name.to_string(), address as u16);
+                text = text[colon + 1..].trim();
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            if let Some(equals) = text.find('=') {
+                let name = text[..equals].trim();
+                if !is_identifier(name) {
+                    return Err(error(format!("{} isn't a valid constant name", name)));
+                }
+                self.define(name, &line.location)?;
+                self.constants
+                    .insert(name.to_string(), text[equals + 1..].trim().to_string());
+                continue;
+            }
+
+            let (first_word, rest) = split_first_word(text);
+            let operands: Vec<String> = if rest.trim().is_empty() {
+                Vec::new()
+            } else {
+                rest.split(',').map(|op| op.trim().to_string()).collect()
+            };
+            if operands.iter().any(|op| op.is_empty()) {
+                return Err(error("missing operand".to_string()));
+            }
+            let (statement, size) = match first_word.to_ascii_lowercase().as_str() {
+                "db" => {
+                    let size = operands.len();
+                    (Statement::Bytes(operands), size)
+                }
+                "dw" => {
+                    let size = operands.len() * 2;
+                    (Statement::Words(operands), size)
+                }
+                _ => {
+                    let mnemonic = first_word.to_ascii_uppercase();
+                    // LD I, LONG NNNN is followed by its address
+                    let long = mnemonic == "LD"
+                        && operands.len() == 2
+                        && split_first_word(&operands[1])
+                            .0
+                            .eq_ignore_ascii_case("LONG");
+                    let size = if long { 4 } else { 2 };
+                    (Statement::Instruction { mnemonic, operands }, size)
+                }
+            };
+            placed.push(Placed {
+                statement,
+                address: address as u16,
+                location: line.location.clone(),
+            });
+            address += size;
+            if address > 0x10000 {
+                return Err(error("the program doesn't fit in memory".to_string()));
+            }
+        }
+        Ok(placed)
+    }
+
+    fn define(&self, name: &str, location: &SourceLocation) -> Result<(), AssembleError> {
+        let message = if is_reserved(name) {
+            format!("{} is reserved and can't be used as a name", name)
+        } else if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            format!("{} is already defined", name)
+        } else {
+            return Ok(());
+        };
+        Err(AssembleError {
+            location: location.clone(),
+            message,
+        })
+    }
+
+    // numbers, labels and constants added or subtracted, eg `sprites + 5` or `-1`
+    fn evaluate(
+        &self,
+        expression: &str,
+        location: &SourceLocation,
+        depth: usize,
+    ) -> Result<i64, AssembleError> {
+        let error = |message: String| AssembleError {
+            location: location.clone(),
+            message,
+        };
+        if depth > self.constants.len() {
+            return Err(error(format!(
+                "{} is defined in terms of itself",
+                expression
+            )));
+        }
+
+        let mut total = 0;
+        let mut negative = false;
+        let mut rest = expression.trim();
+        loop {
+            if let Some(stripped) = rest.strip_prefix('-') {
+                negative = !negative;
+                rest = stripped.trim_start();
+                continue;
+            }
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = rest[..end].trim();
+            let value = if let Some(number) = parse_number(term) {
+                number
+            } else if let Some(&address) = self.labels.get(term) {
+                address as i64
+            } else if let Some(constant) = self.constants.get(term) {
+                self.evaluate(constant, location, depth + 1)?
+            } else if term.is_empty() {
+                return Err(error(format!("{} is missing a value", expression)));
+            } else {
+                return Err(error(format!("{} isn't a number, label or constant", term)));
+            };
+            total += if negative { -value } else { value };
+
+            if end == rest.len() {
+                return Ok(total);
+            }
+            negative = &rest[end..end + 1] == "-";
+            rest = rest[end + 1..].trim_start();
+        }
+    }
+
+    // evaluates an operand and checks it fits in max, negative values wrap as two's complement
+    fn value(
+        &self,
+        operand: &str,
+        max: i64,
+        what: &str,
+        location: &SourceLocation,
+    ) -> Result<u16, AssembleError> {
+        let value = self.evaluate(operand, location, 0)?;
+        if value > max || value < -(max + 1) / 2 {
+            return Err(AssembleError {
+                location: location.clone(),
+                message: format!("{} doesn't fit in {} ({:#x} max)", operand, what, max),
+            });
+        }
+        Ok((value & max) as u16)
+    }
+
+    fn encode(&self, item: &Placed) -> Result<Vec<u8>, AssembleError> {
+        let location = &item.location;
+        match &item.statement {
+            Statement::Bytes(values) => values
+                .iter()
+                .map(|value| Ok(self.value(value, 0xFF, "a byte", location)? as u8))
+                .collect(),
+            Statement::Words(values) => {
+                let mut bytes = Vec::new();
+                for value in values {
+                    let word = self.value(value, 0xFFFF, "a word", location)?;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            Statement::Instruction { mnemonic, operands } => {
+                let mut bytes = self
+                    .encode_instruction(mnemonic, operands, location)?
+                    .to_be_bytes()
+                    .to_vec();
+                if bytes == [0xF0, 0x00] {
+                    let (_, address) = split_first_word(&operands[1]);
+                    let address = self.value(address, 0xFFFF, "an address", location)?;
+                    bytes.extend_from_slice(&address.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn encode_instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[String],
+        location: &SourceLocation,
+    ) -> Result<u16, AssembleError> {
+        let x = |i: usize| parse_register(&operands[i]).map(|x| (x as u16) << 8);
+        let y = |i: usize| parse_register(&operands[i]).map(|y| (y as u16) << 4);
+        let byte = |i: usize| self.value(&operands[i], 0xFF, "a byte", location);
+        let address = |i: usize| self.value(&operands[i], 0xFFF, "an address", location);
+        let nibble = |i: usize| self.value(&operands[i], 0xF, "a nibble", location);
+        // the operands, but just the ones that are keywords rather than values
+        let shape: Vec<String> = operands
+            .iter()
+            .map(|op| {
+                if parse_register(op).is_some() {
+                    "V".to_string()
+                } else if is_reserved(op)
+                    || op.eq_ignore_ascii_case("[I]")
+                    || split_first_word(op).0.eq_ignore_ascii_case("LONG")
+                {
+                    split_first_word(op).0.to_ascii_uppercase()
+                } else {
+                    "N".to_string()
+                }
+            })
+            .collect();
+        let shape: Vec<&str> = shape.iter().map(|s| s.as_str()).collect();
+
+        let code = match (mnemonic, shape.as_slice()) {
+            ("CLS", []) => 0x00E0,
+            ("RET", []) => 0x00EE,
+            ("SCR", []) => 0x00FB,
+            ("SCL", []) => 0x00FC,
+            ("EXIT", []) => 0x00FD,
+            ("LOW", []) => 0x00FE,
+            ("HIGH", []) => 0x00FF,
+            ("SCD", ["N"]) => 0x00C0 | nibble(0)?,
+            ("SCU", ["N"]) => 0x00D0 | nibble(0)?,
+            ("SYS", ["N"]) => address(0)?,
+            ("JP", ["N"]) => 0x1000 | address(0)?,
+            ("JP", ["V", "N"]) if parse_register(&operands[0]) == Some(0) => 0xB000 | address(1)?,
+            ("CALL", ["N"]) => 0x2000 | address(0)?,
+            ("SE", ["V", "N"]) => 0x3000 | x(0).unwrap() | byte(1)?,
+            ("SNE", ["V", "N"]) => 0x4000 | x(0).unwrap() | byte(1)?,
+            ("SE", ["V", "V"]) => 0x5000 | x(0).unwrap() | y(1).unwrap(),
+            ("SAVE", ["V", "V"]) => 0x5002 | x(0).unwrap() | y(1).unwrap(),
+            ("LOAD", ["V", "V"]) => 0x5003 | x(0).unwrap() | y(1).unwrap(),
+            ("LD", ["V", "N"]) => 0x6000 | x(0).unwrap() | byte(1)?,
+            ("ADD", ["V", "N"]) => 0x7000 | x(0).unwrap() | byte(1)?,
+            ("LD", ["V", "V"]) => 0x8000 | x(0).unwrap() | y(1).unwrap(),
+            ("OR", ["V", "V"]) => 0x8001 | x(0).unwrap() | y(1).unwrap(),
+            ("AND", ["V", "V"]) => 0x8002 | x(0).unwrap() | y(1).unwrap(),
+            ("XOR", ["V", "V"]) => 0x8003 | x(0).unwrap() | y(1).unwrap(),
+            ("ADD", ["V", "V"]) => 0x8004 | x(0).unwrap() | y(1).unwrap(),
+            ("SUB", ["V", "V"]) => 0x8005 | x(0).unwrap() | y(1).unwrap(),
+            // with no VY, shift VX in place
+            ("SHR", ["V"]) => 0x8006 | x(0).unwrap() | (x(0).unwrap() >> 4),
+            ("SHR", ["V", "V"]) => 0x8006 | x(0).unwrap() | y(1).unwrap(),
+            ("SUBN", ["V", "V"]) => 0x8007 | x(0).unwrap() | y(1).unwrap(),
+            ("SHL", ["V"]) => 0x800E | x(0).unwrap() | (x(0).unwrap() >> 4),
+            ("SHL", ["V", "V"]) => 0x800E | x(0).unwrap() | y(1).unwrap(),
+            ("SNE", ["V", "V"]) => 0x9000 | x(0).unwrap() | y(1).unwrap(),
+            ("LD", ["I", "N"]) => 0xA000 | address(1)?,
+            ("LD", ["I", "LONG"]) => 0xF000,
+            ("RND", ["V", "N"]) => 0xC000 | x(0).unwrap() | byte(1)?,
+            ("DRW", ["V", "V", "N"]) => 0xD000 | x(0).unwrap() | y(1).unwrap() | nibble(2)?,
+            ("SKP", ["V"]) => 0xE09E | x(0).unwrap(),
+            ("SKNP", ["V"]) => 0xE0A1 | x(0).unwrap(),
+            ("PLANE", ["N"]) => 0xF001 | nibble(0)? << 8,
+            ("AUDIO", []) => 0xF002,
+            ("LD", ["V", "DT"]) => 0xF007 | x(0).unwrap(),
+            ("LD", ["V", "K"]) => 0xF00A | x(0).unwrap(),
+            ("LD", ["DT", "V"]) => 0xF015 | x(1).unwrap(),
+            ("LD", ["ST", "V"]) => 0xF018 | x(1).unwrap(),
+            ("ADD", ["I", "V"]) => 0xF01E | x(1).unwrap(),
+            ("LD", ["F", "V"]) => 0xF029 | x(1).unwrap(),
+            ("LD", ["HF", "V"]) => 0xF030 | x(1).unwrap(),
+            ("LD", ["B", "V"]) => 0xF033 | x(1).unwrap(),
+            ("PITCH", ["V"]) => 0xF03A | x(0).unwrap(),
+            ("LD", ["[I]", "V"]) => 0xF055 | x(1).unwrap(),
+            ("LD", ["V", "[I]"]) => 0xF065 | x(0).unwrap(),
+            ("LD", ["R", "V"]) => 0xF075 | x(1).unwrap(),
+            ("LD", ["V", "R"]) => 0xF085 | x(0).unwrap(),
+            _ => {
+                let message = if operands.is_empty() {
+                    format!("{} isn't a valid instruction", mnemonic)
+                } else {
+                    format!(
+                        "{} {} isn't a valid instruction",
+                        mnemonic,
+                        operands.join(", ")
+                    )
+                };
+                return Err(AssembleError {
+                    location: location.clone(),
+                    message,
+                });
+            }
+        };
+        Ok(code)
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+
+// XO-CHIP plays a 16 byte (128 bit) pattern one bit at a time while the sound timer is running,
+// a set bit is the speaker pushed out and an unset bit is it pulled back in
+pub const AUDIO_PATTERN_LEN: usize = 16;
+const PATTERN_BITS: f64 = (AUDIO_PATTERN_LEN * 8) as f64;
+
+// the pitch that plays the pattern back at 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+// the pattern buffer before a program loads its own, a plain square wave that at the default
+// pitch is a 500hz beep. Players with a Beep play that instead until the program runs F002
+pub const DEFAULT_AUDIO_PATTERN: [u8; AUDIO_PATTERN_LEN] = [0xF0; AUDIO_PATTERN_LEN];
+
+// how many bits of the pattern are played per second at a given pitch
+pub fn pattern_playback_rate(pitch: u8) -> f64 {
+    4000.0 * 2_f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+// The pattern and pitch last set by the program. The emulator writes these and the audio
+// output reads them from its own thread, so they're atomics rather than plain bytes
+#[derive(Debug)]
+pub struct AudioState {
+    pattern: [AtomicU8; AUDIO_PATTERN_LEN],
+    pitch: AtomicU8,
+    // set once the program's loaded a pattern with F002, until then it's the plain beep
+    pattern_loaded: AtomicBool,
+}
+
+impl AudioState {
+    pub fn new() -> Self {
+        let state = AudioState {
+            pattern: Default::default(),
+            pitch: AtomicU8::new(DEFAULT_PITCH),
+            pattern_loaded: AtomicBool::new(false),
+        };
+        state.set_pattern(&DEFAULT_AUDIO_PATTERN);
+        state
+    }
+
+    pub fn pattern(&self) -> [u8; AUDIO_PATTERN_LEN] {
+        let mut pattern = [0_u8; AUDIO_PATTERN_LEN];
+        for (byte, atomic) in pattern.iter_mut().zip(self.pattern.iter()) {
+            *byte = atomic.load(Ordering::Relaxed);
+        }
+        pattern
+    }
+
+    pub fn set_pattern(&self, pattern: &[u8; AUDIO_PATTERN_LEN]) {
+        for (byte, atomic) in pattern.iter().zip(self.pattern.iter()) {
+            atomic.store(*byte, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_pattern_loaded(&self) -> bool {
+        self.pattern_loaded.load(Ordering::Relaxed)
+    }
+
+    pub fn set_pattern_loaded(&self, loaded: bool) {
+        self.pattern_loaded.store(loaded, Ordering::Relaxed);
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch.load(Ordering::Relaxed)
+    }
+
+    pub fn set_pitch(&self, pitch: u8) {
+        self.pitch.store(pitch, Ordering::Relaxed);
+    }
+}
+
+impl Default for AudioState {
+    fn default() -> Self {
+        AudioState::new()
+    }
+}
+
+// A recorded sound looped for as long as the sound timer runs, for programs that don't load a
+// pattern of their own. Samples are in the -1.0 to 1.0 range
+#[derive(Debug)]
+pub struct Beep {
+    samples: Vec<f32>,
+    sample_rate: f64,
+}
+
+impl Beep {
+    pub fn new(samples: Vec<f32>, sample_rate: f64) -> Self {
+        Beep {
+            samples,
+            sample_rate,
+        }
+    }
+
+    // beep2.wav, the beep chip8_rust has always made. Decoding it needs hound, which comes with
+    // the recording feature (and so with the frontend)
+    #[cfg(feature = "recording")]
+    pub fn default_beep() -> Self {
+        let wav = hound::WavReader::new(&include_bytes!("../beep2.wav")[..]).unwrap();
+        let sample_rate = wav.spec().sample_rate as f64;
+        let samples = wav
+            .into_samples::<i16>()
+            .map(|sample| sample.unwrap() as f32 / i16::MAX as f32)
+            .collect();
+        Beep::new(samples, sample_rate)
+    }
+}
+
+// Synthesizes the pattern as a stream of samples, the audio output asks it for one sample at a
+// time while the sound timer is running. With a beep, that's played until the program loads a
+// pattern
+#[derive(Debug, Default)]
+pub struct PatternPlayer {
+    // how far through the pattern we are, in bits
+    position: f64,
+    beep: Option<Arc<Beep>>,
+    // how far through the beep we are, in samples
+    beep_position: f64,
+}
+
+impl PatternPlayer {
+    pub fn new() -> Self {
+        PatternPlayer::default()
+    }
+
+    pub fn with_beep(beep: Arc<Beep>) -> Self {
+        PatternPlayer {
+            beep: Some(beep),
+            ..PatternPlayer::default()
+        }
+    }
+
+    // returns a sample in the -1.0 to 1.0 range
+    pub fn next_sample(&mut self, state: &AudioState, sample_rate: f64) -> f32 {
+        if let Some(beep) = &self.beep {
+            if !state.is_pattern_loaded() && !beep.samples.is_empty() {
+                let sample = beep.samples[self.beep_position as usize];
+                self.beep_position += beep.sample_rate / sample_rate;
+                self.beep_position %= beep.samples.len() as f64;
+                return sample;
+            }
+        }
+
+        let bit_index = self.position as usize;
+        let byte = state.pattern[bit_index / 8].load(Ordering::Relaxed);
+        let bit = (byte >> (7 - bit_index % 8)) & 1;
+
+        self.position += pattern_playback_rate(state.pitch()) / sample_rate;
+        self.position %= PATTERN_BITS;
+
+        if bit == 1 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    // start from the beginning of the pattern next time the sound timer starts
+    pub fn reset(&mut self) {
+        self.position = 0.0;
+        self.beep_position = 0.0;
+    }
+}
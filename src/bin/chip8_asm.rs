@@ -0,0 +1,15 @@
+use std::env;
+
+use chip8_rust::cli::{assemble_to_file, fail};
+
+// usage: chip8_asm <source> <rom>
+// assembles the source into a rom that can be loaded like any other, .8o files are compiled
+// as Octo. The same as `chip8_rust asm`
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        fail("usage: chip8_asm <source> <rom>");
+    }
+    let size = assemble_to_file(&args[1], &args[2]).unwrap_or_else(|e| fail(&e));
+    println!("Assembled {} bytes", size);
+}
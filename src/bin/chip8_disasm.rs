@@ -0,0 +1,16 @@
+use std::env;
+
+use chip8_rust::cli::{disassemble_file, fail};
+
+// usage: chip8_disasm <rom>
+// prints a listing of the rom with addresses, raw bytes and labels for jump and call targets,
+// the same as `chip8_rust disasm`
+fn main() {
+    let rom_path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| fail("usage: chip8_disasm <rom>"));
+    print!(
+        "{}",
+        disassemble_file(&rom_path).unwrap_or_else(|e| fail(&e))
+    );
+}
@@ -0,0 +1,186 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::sync::atomic::Ordering;
+
+use serde_derive::Serialize;
+
+use chip8_rust::cli::{self, fail, Limit, MachineOptions, RecordingFiles};
+use chip8_rust::input::InputScript;
+use chip8_rust::Emulator;
+
+const USAGE: &str = "usage: chip8_run <rom> [--frames <n> | --instructions <n>] [--ipf <n>] [--seed <n>] \
+[--rng xorshift|vip] [--quirks <preset>] [--vip-interpreter <file>] [--inputs <file>] \
+[--input \"<frame> press|release <key>\"] [--screen ascii|pbm|png] [--screen-out <file>] [--scale <n>] \
+[--registers <file>] [--gif <file>] [--y4m <file>] [--wav <file>] [--movie <file>]";
+
+// the registers as they're written out to JSON
+#[derive(Serialize)]
+struct Registers {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: Vec<u16>,
+    dt: u8,
+    st: u8,
+    frames: u64,
+    cycles: u64,
+}
+
+// where the screen and registers go once the run is over, None is stdout for the screen and
+// not at all for the registers
+struct Output {
+    screen_format: String,
+    screen_path: Option<String>,
+    scale: usize,
+    registers_path: Option<String>,
+    // recordings of the whole run
+    recording: RecordingFiles,
+}
+
+// Runs a rom without a window for a number of frames (60 by default) or instructions, then
+// prints the screen and optionally writes the registers out as JSON. The run can be recorded
+// too, as a GIF, a Y4M video and a WAV of the beep, at --scale times the hires resolution.
+// With a fixed seed and scripted inputs every run is the same, which makes it good for checking
+// roms in CI and for attaching to bug reports. --movie plays back a movie recorded in the
+// frontend, so a player's bug report can become a regression test
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (paths, options) =
+        cli::split_args(&args, &[]).unwrap_or_else(|e| fail(&format!("{}\n{}", e, USAGE)));
+    let rom_path = match paths.as_slice() {
+        [rom_path] => rom_path,
+        _ => fail(USAGE),
+    };
+
+    let mut machine = MachineOptions::default();
+    let mut limit = None;
+    let mut script = String::new();
+    let mut movie_path = None;
+    let mut output = Output {
+        screen_format: "ascii".to_string(),
+        screen_path: None,
+        scale: 1,
+        registers_path: None,
+        recording: RecordingFiles::default(),
+    };
+    for (option, value) in &options {
+        if machine
+            .apply_option(option, value)
+            .unwrap_or_else(|e| fail(&e))
+        {
+            continue;
+        }
+        if let Some(option_limit) = Limit::from_option(option, value).unwrap_or_else(|e| fail(&e)) {
+            limit = Some(option_limit);
+            continue;
+        }
+        match option.as_str() {
+            "--inputs" => {
+                let text = fs::read_to_string(value)
+                    .unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", value, e)));
+                script.push_str(&text);
+                script.push('\n');
+            }
+            "--input" => {
+                script.push_str(value);
+                script.push('\n');
+            }
+            "--movie" => movie_path = Some(value.clone()),
+            "--screen" => match value.as_str() {
+                "ascii" | "pbm" | "png" => output.screen_format = value.clone(),
+                _ => fail(&format!("unknown screen format {}", value)),
+            },
+            "--screen-out" => output.screen_path = Some(value.clone()),
+            "--scale" => output.scale = cli::parse_number(value).unwrap_or_else(|e| fail(&e)),
+            "--registers" => output.registers_path = Some(value.clone()),
+            "--gif" => output.recording.gif = Some(value.clone()),
+            "--y4m" => output.recording.y4m = Some(value.clone()),
+            "--wav" => output.recording.wav = Some(value.clone()),
+            _ => fail(&format!("unknown option {}\n{}", option, USAGE)),
+        }
+    }
+    let mut inputs =
+        InputScript::parse(&script).unwrap_or_else(|e| fail(&format!("bad inputs, {}", e)));
+
+    // a movie brings its own settings and inputs, and runs for as long as it was recorded for
+    let movie = movie_path.map(|path| {
+        let movie = cli::load_movie(&path, rom_path).unwrap_or_else(|e| fail(&e));
+        machine = movie.machine.clone();
+        inputs = movie.inputs.clone();
+        movie
+    });
+    let mut emulator = machine
+        .headless_emulator(rom_path)
+        .unwrap_or_else(|e| fail(&e));
+    if let Some(warning) = cli::vip_rng_warning(&emulator) {
+        eprintln!("{}", warning);
+    }
+    let limit = match (limit, &movie) {
+        (Some(limit), _) => limit,
+        (None, Some(movie)) => Limit::Frames(movie.frames),
+        (None, None) => Limit::default(),
+    };
+
+    let mut recorder = output
+        .recording
+        .start(output.scale)
+        .unwrap_or_else(|e| fail(&e));
+    let result = cli::run_headless(&mut emulator, limit, &inputs, |emulator| {
+        recorder.capture_frame(emulator)
+    });
+    if let Err(e) = recorder.finish() {
+        fail(&format!("Failed to write the recording: {}", e));
+    }
+    if let Err(e) = result {
+        // still dump what we've got, it's probably what's wanted for a bug report
+        eprintln!("Emulator stopped: {}", e);
+        dump(&emulator, &output);
+        process::exit(1);
+    }
+    dump(&emulator, &output);
+}
+
+fn dump(emulator: &Emulator, output: &Output) {
+    let display = &emulator.display;
+    let screen = match output.screen_format.as_str() {
+        "pbm" => display.to_pbm().into_bytes(),
+        "png" => {
+            let mut png = Vec::new();
+            display
+                .write_png(&mut png, output.scale)
+                .unwrap_or_else(|e| fail(&format!("Failed to encode the screen: {}", e)));
+            png
+        }
+        _ => display.to_ascii().into_bytes(),
+    };
+    write_output(&output.screen_path, &screen);
+
+    if let Some(path) = &output.registers_path {
+        let registers = Registers {
+            v: emulator.registers,
+            i: emulator.address_register,
+            pc: emulator.program_counter,
+            stack: emulator.subroutine_return_pointers.clone(),
+            dt: emulator.timer_counter.load(Ordering::Relaxed),
+            st: emulator.sound_counter.load(Ordering::Relaxed),
+            frames: emulator.frame_count,
+            cycles: emulator.cycle_count,
+        };
+        let mut json = serde_json::to_string_pretty(&registers).unwrap();
+        json.push('\n');
+        write_output(&Some(path.clone()), json.as_bytes());
+    }
+}
+
+// - or no path at all is stdout
+fn write_output(path: &Option<String>, bytes: &[u8]) {
+    let result = match path.as_deref() {
+        None | Some("-") => io::stdout().write_all(bytes),
+        Some(path) => fs::write(path, bytes),
+    };
+    if let Err(e) = result {
+        fail(&format!("Failed to write the output: {}", e));
+    }
+}
@@ -0,0 +1,58 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip8_rust::cli::{self, fail, MachineOptions};
+use chip8_rust::input::InputScript;
+use chip8_rust::tracediff::{diff, parse_reference, DiffResult};
+
+const USAGE: &str =
+    "usage: chip8_tracediff <rom> <reference trace> [--seed <n>] [--rng xorshift|vip] \
+[--quirks <preset>] [--ipf <n>] [--inputs <file>] [--vip-interpreter <file>]";
+
+// runs the rom headless against a reference trace from another emulator and reports the first
+// instruction where they differ. The seed defaults to 0 so runs are repeatable, see
+// tracediff.rs for the reference format and input.rs for the inputs
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (paths, options) =
+        cli::split_args(&args, &[]).unwrap_or_else(|e| fail(&format!("{}\n{}", e, USAGE)));
+    let (rom_path, reference_path) = match paths.as_slice() {
+        [rom_path, reference_path] => (rom_path, reference_path),
+        _ => fail(USAGE),
+    };
+
+    let mut machine = MachineOptions::default();
+    let mut inputs = InputScript::default();
+    for (option, value) in &options {
+        if machine
+            .apply_option(option, value)
+            .unwrap_or_else(|e| fail(&e))
+        {
+            continue;
+        }
+        match option.as_str() {
+            "--inputs" => inputs = cli::read_inputs(value).unwrap_or_else(|e| fail(&e)),
+            _ => fail(&format!("unknown option {}\n{}", option, USAGE)),
+        }
+    }
+
+    let reference = fs::read_to_string(reference_path)
+        .map_err(|e| format!("Failed to read {}: {}", reference_path, e))
+        .and_then(|text| parse_reference(&text).map_err(|e| format!("{}: {}", reference_path, e)))
+        .unwrap_or_else(|e| fail(&e));
+    let mut emulator = machine
+        .headless_emulator(rom_path)
+        .unwrap_or_else(|e| fail(&e));
+    if let Some(warning) = cli::vip_rng_warning(&emulator) {
+        eprintln!("{}", warning);
+    }
+
+    match diff(&mut emulator, &reference, &inputs) {
+        DiffResult::Matched { steps } => println!("Matched the reference for all {} steps", steps),
+        DiffResult::Diverged(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::process;
+use std::str::FromStr;
+
+use crate::assembler::assemble_file;
+use crate::disasm::disassemble_rom;
+use crate::emulator::{Emulator, EmulatorError, InstructionResult, DEFAULT_INSTRUCTIONS_PER_FRAME};
+use crate::input::InputScript;
+#[cfg(feature = "movies")]
+use crate::movie::Movie;
+use crate::octo;
+use crate::quirks::Quirks;
+#[cfg(feature = "recording")]
+use crate::recording::Recorder;
+use crate::rng::{Rng, RngMode};
+
+// The parts of the command line chip8_rust and the tools in src/bin share, so they take the
+// same options and run roms the same way. Anything that can go wrong comes back as a message
+// for the binary to print
+
+// the options for how a rom runs, which every command that runs one takes
+#[derive(Clone, Debug)]
+pub struct MachineOptions {
+    pub instructions_per_frame: u32,
+    pub quirks: Quirks,
+    // None leaves it to the caller, the window picks one at random and headless runs use 0
+    pub seed: Option<u64>,
+    pub rng_mode: RngMode,
+    pub vip_interpreter: Option<String>,
+}
+
+impl Default for MachineOptions {
+    fn default() -> Self {
+        MachineOptions {
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            quirks: Quirks::default(),
+            seed: None,
+            rng_mode: RngMode::Xorshift,
+            vip_interpreter: None,
+        }
+    }
+}
+
+impl MachineOptions {
+    // Ok(false) if option isn't one of these
+    pub fn apply_option(&mut self, option: &str, value: &str) -> Result<bool, String> {
+        match option {
+            "--ipf" => self.instructions_per_frame = parse_instructions_per_frame(value)?,
+            "--quirks" => {
+                self.quirks = Quirks::from_preset(value)
+                    .ok_or_else(|| format!("unknown quirks preset {}", value))?
+            }
+            "--seed" => self.seed = Some(parse_number(value)?),
+            "--rng" => {
+                self.rng_mode =
+                    RngMode::from_name(value).ok_or_else(|| format!("unknown rng {}", value))?
+            }
+            "--vip-interpreter" => self.vip_interpreter = Some(value.to_string()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // each option that's been set by the name it has in a movie, with its value. Quirks are
+    // the names of the ones that are on
+    pub fn named(&self) -> Vec<(&'static str, String)> {
+        let mut named = Vec::new();
+        if let Some(seed) = self.seed {
+            named.push(("seed", seed.to_string()));
+        }
+        named.push(("rng", self.rng_mode.name().to_string()));
+        named.push((
+            "instructions_per_frame",
+            self.instructions_per_frame.to_string(),
+        ));
+        let quirks: Vec<&str> = self
+            .quirks
+            .named()
+            .iter()
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect();
+        named.push(("quirks", quirks.join(" ")));
+        if let Some(path) = &self.vip_interpreter {
+            named.push(("vip_interpreter", path.clone()));
+        }
+        named
+    }
+
+    // the other way round from named, Ok(false) if name isn't one of them
+    pub fn set_named(&mut self, name: &str, value: &str) -> Result<bool, String> {
+        match name {
+            "seed" => self.seed = Some(parse_number(value)?),
+            "rng" => {
+                self.rng_mode =
+                    RngMode::from_name(value).ok_or_else(|| format!("unknown rng {}", value))?
+            }
+            "instructions_per_frame" => {
+                self.instructions_per_frame = parse_instructions_per_frame(value)?
+            }
+            "quirks" => {
+                self.quirks = Quirks::default();
+                for quirk in value.split_whitespace() {
+                    self.quirks.set_named(quirk, true)?;
+                }
+            }
+            "vip_interpreter" => self.vip_interpreter = Some(value.to_string()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // sets the emulator up before a rom is loaded, seed is used when there's no seed option
+    pub fn configure(&self, emulator: &mut Emulator, seed: u64) -> Result<(), String> {
+        emulator.instructions_per_frame = self.instructions_per_frame;
+        emulator.quirks = self.quirks;
+        emulator.rng = Rng::with_mode(self.rng_mode, self.seed.unwrap_or(seed));
+        if let Some(path) = &self.vip_interpreter {
+            let interpreter = fs::read(path)
+                .map_err(|e| format!("Failed to read the VIP interpreter {}: {}", path, e))?;
+            emulator.load_vip_interpreter_page(&interpreter);
+        }
+        Ok(())
+    }
+
+    // an emulator for running without a window, with the rom loaded. The seed is 0 unless
+    // there's an option for it, so every run is the same
+    pub fn headless_emulator(&self, rom_path: &str) -> Result<Emulator, String> {
+        let mut emulator = Emulator::new();
+        self.configure(&mut emulator, 0)?;
+        emulator
+            .load_program(rom_path)
+            .map_err(|e| format!("Failed to load {}: {}", rom_path, e))?;
+        Ok(emulator)
+    }
+}
+
+// the VIP rng only gives the numbers a VIP would with the interpreter page loaded
+pub fn vip_rng_warning(emulator: &Emulator) -> Option<&'static str> {
+    if emulator.rng.mode() == RngMode::CosmacVip && !emulator.has_vip_interpreter_page() {
+        Some("The vip rng needs --vip-interpreter to give the numbers a VIP would")
+    } else {
+        None
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Limit {
+    Frames(u64),
+    Instructions(u64),
+}
+
+// a second's worth, for when a run isn't told how long to go for
+impl Default for Limit {
+    fn default() -> Self {
+        Limit::Frames(60)
+    }
+}
+
+impl Limit {
+    // --frames <n> or --instructions <n>, Ok(None) if option is neither
+    pub fn from_option(option: &str, value: &str) -> Result<Option<Limit>, String> {
+        match option {
+            "--frames" => Ok(Some(Limit::Frames(parse_number(value)?))),
+            "--instructions" => Ok(Some(Limit::Instructions(parse_number(value)?))),
+            _ => Ok(None),
+        }
+    }
+}
+
+// Runs the emulator without a window until the limit's reached or the rom exits, pressing keys
+// from inputs. on_frame is called after each frame that finishes, eg to record it
+pub fn run_headless<F>(
+    emulator: &mut Emulator,
+    limit: Limit,
+    inputs: &InputScript,
+    mut on_frame: F,
+) -> Result<(), EmulatorError>
+where
+    F: FnMut(&Emulator) -> io::Result<()>,
+{
+    loop {
+        let done = match limit {
+            Limit::Frames(frames) => emulator.frame_count >= frames,
+            Limit::Instructions(instructions) => emulator.cycle_count >= instructions,
+        };
+        if done {
+            return Ok(());
+        }
+        emulator.key_states = inputs.key_states_at(emulator.frame_count);
+        let frame = emulator.frame_count;
+        let result = emulator.execute_next_instruction()?;
+        if emulator.frame_count != frame {
+            on_frame(emulator)?;
+        }
+        if result == InstructionResult::Terminated {
+            return Ok(());
+        }
+    }
+}
+
+// an --option and its value
+pub type OptionValue = (String, String);
+
+// splits the arguments into the positional ones and (--option, value) pairs. The options in
+// flags don't take a value, and are paired with an empty one
+pub fn split_args(
+    args: &[String],
+    flags: &[&str],
+) -> Result<(Vec<String>, Vec<OptionValue>), String> {
+    let mut positional = Vec::new();
+    let mut options = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.clone());
+        } else if flags.contains(&arg.as_str()) {
+            options.push((arg.clone(), String::new()));
+        } else {
+            match args.next() {
+                Some(value) => options.push((arg.clone(), value.clone())),
+                None => return Err(format!("{} needs a value", arg)),
+            }
+        }
+    }
+    Ok((positional, options))
+}
+
+pub fn parse_number<T: FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("{} isn't a number", text))
+}
+
+// at least 1, with none a frame would never end
+pub fn parse_instructions_per_frame(text: &str) -> Result<u32, String> {
+    let instructions: u64 = parse_number(text)?;
+    match u32::try_from(instructions) {
+        Ok(0) => Err("instructions per frame has to be at least 1".to_string()),
+        Ok(instructions) => Ok(instructions),
+        Err(_) => Err(format!(
+            "{} instructions per frame is too many, the most is {}",
+            text,
+            u32::MAX
+        )),
+    }
+}
+
+// an input script file, see input.rs
+pub fn read_inputs(path: &str) -> Result<InputScript, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    InputScript::parse(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+pub fn disassemble_file(rom_path: &str) -> Result<String, String> {
+    let rom = fs::read(rom_path).map_err(|e| format!("Failed to read {}: {}", rom_path, e))?;
+    disassemble_rom(&rom).map_err(|e| format!("Failed to disassemble {}: {}", rom_path, e))
+}
+
+// assembles source into a rom at rom_path, compiling it as Octo if it's a .8o file. Returns
+// the size of the rom
+pub fn assemble_to_file(source: &str, rom_path: &str) -> Result<usize, String> {
+    let rom = if source.ends_with(".8o") {
+        let text =
+            fs::read_to_string(source).map_err(|e| format!("Failed to read {}: {}", source, e))?;
+        octo::compile(&text).map_err(|e| format!("{}: {}", source, e))?
+    } else {
+        assemble_file(source).map_err(|e| e.to_string())?.rom
+    };
+    fs::write(rom_path, &rom).map_err(|e| format!("Failed to write {}: {}", rom_path, e))?;
+    Ok(rom.len())
+}
+
+// the files to record a run to, see recording.rs
+#[cfg(feature = "recording")]
+#[derive(Clone, Default, Debug)]
+pub struct RecordingFiles {
+    pub gif: Option<String>,
+    pub y4m: Option<String>,
+    pub wav: Option<String>,
+}
+
+#[cfg(feature = "recording")]
+impl RecordingFiles {
+    pub fn paths(&self) -> impl Iterator<Item = &String> {
+        self.gif.iter().chain(&self.y4m).chain(&self.wav)
+    }
+
+    // a recorder that's recording to all of them, at scale times the hires resolution
+    pub fn start(&self, scale: usize) -> Result<Recorder, String> {
+        let mut recorder = Recorder::new(scale);
+        let failed = |path: &str, e: io::Error| format!("Failed to create {}: {}", path, e);
+        if let Some(path) = &self.gif {
+            recorder.record_gif(path).map_err(|e| failed(path, e))?;
+        }
+        if let Some(path) = &self.y4m {
+            recorder.record_y4m(path).map_err(|e| failed(path, e))?;
+        }
+        if let Some(path) = &self.wav {
+            recorder.record_wav(path).map_err(|e| failed(path, e))?;
+        }
+        Ok(recorder)
+    }
+}
+
+// a movie to play back on the rom at rom_path, an error if it was recorded on a different rom
+#[cfg(feature = "movies")]
+pub fn load_movie(path: &str, rom_path: &str) -> Result<Movie, String> {
+    let movie =
+        Movie::load(path).map_err(|e| format!("Failed to load the movie {}: {}", path, e))?;
+    let rom = fs::read(rom_path).map_err(|e| format!("Failed to read {}: {}", rom_path, e))?;
+    movie
+        .check_rom(&rom)
+        .map_err(|e| format!("Can't play the movie, {}", e))?;
+    Ok(movie)
+}
+
+// for the binaries, errors go to stderr so they don't end up in output piped from stdout
+pub fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+use std::sync::atomic::Ordering;
+
+use crate::emulator::Emulator;
+
+// Breakpoint conditions, a small C-like expression language over the machine state:
+//
+//     V0-VF  I  PC  DT  ST  SP    registers, SP is the stack depth
+//     [expr]                      the memory byte at expr, eg [I+2]
+//     HITS                        times the breakpoint has been reached, this time included
+//     123  0x7B                   numbers
+//
+// with, loosest binding first, || && == != < <= > >= | ^ & + - and unary ! and -, plus brackets.
+// Comparisons and logic give 1 or 0, and anything non zero is true. Names are case insensitive
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Copy, Clone)]
+enum Value {
+    Register(usize),
+    I,
+    Pc,
+    Dt,
+    St,
+    Sp,
+    Hits,
+}
+
+#[derive(Copy, Clone)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Subtract,
+}
+
+enum Expr {
+    Number(i64),
+    Value(Value),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+// binding strength of each level of binary operators, loosest first
+const LEVELS: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Equal),
+        ("!=", BinaryOp::NotEqual),
+        ("<=", BinaryOp::LessEqual),
+        (">=", BinaryOp::GreaterEqual),
+        ("<", BinaryOp::Less),
+        (">", BinaryOp::Greater),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+];
+
+const OPERATORS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "(", ")", "[", "]",
+];
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let expr = parser.expression(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {} in condition", token));
+        }
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn evaluate(&self, emu: &Emulator, hits: u32) -> i64 {
+        evaluate(&self.expr, emu, hits)
+    }
+
+    pub fn is_true(&self, emu: &Emulator, hits: u32) -> bool {
+        self.evaluate(emu, hits) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        let len = if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            operator.len()
+        } else if rest.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+            rest.find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len())
+        } else {
+            // a char rather than a byte, so a typo like ≥ is an error rather than a panic
+            let unexpected = rest.chars().next().unwrap_or_default();
+            return Err(format!("unexpected {} in condition", unexpected));
+        };
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|token| token.as_str())
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let token = self
+            .peek()
+            .ok_or_else(|| "condition ends too early".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected {} but found {}", expected, token)),
+        }
+    }
+
+    // the binary operators at this level and tighter
+    fn expression(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.expression(level + 1)?;
+        while let Some(&(_, op)) = self
+            .peek()
+            .and_then(|token| LEVELS[level].iter().find(|(text, _)| *text == token))
+        {
+            self.position += 1;
+            let right = self.expression(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self.next()?;
+        match token {
+            "!" => Ok(Expr::Not(Box::new(self.unary()?))),
+            "-" => Ok(Expr::Negate(Box::new(self.unary()?))),
+            "(" => {
+                let expr = self.expression(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            "[" => {
+                let expr = self.expression(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(expr)))
+            }
+            _ => {
+                if let Some(value) = parse_value(token) {
+                    return Ok(Expr::Value(value));
+                }
+                let number = match token
+                    .strip_prefix("0x")
+                    .or_else(|| token.strip_prefix("0X"))
+                {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => token.parse(),
+                };
+                number
+                    .map(Expr::Number)
+                    .map_err(|_| format!("{} isn't a number or register", token))
+            }
+        }
+    }
+}
+
+fn parse_value(token: &str) -> Option<Value> {
+    let upper = token.to_ascii_uppercase();
+    let value = match upper.as_str() {
+        "I" => Value::I,
+        "PC" => Value::Pc,
+        "DT" => Value::Dt,
+        "ST" => Value::St,
+        "SP" => Value::Sp,
+        "HITS" => Value::Hits,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            Value::Register(usize::from_str_radix(&upper[1..], 16).ok()?)
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn evaluate(expr: &Expr, emu: &Emulator, hits: u32) -> i64 {
+    match expr {
+        Expr::Number(number) => *number,
+        Expr::Value(value) => match value {
+            Value::Register(register) => emu.registers[*register] as i64,
+            Value::I => emu.address_register as i64,
+            Value::Pc => emu.program_counter as i64,
+            Value::Dt => emu.timer_counter.load(Ordering::Relaxed) as i64,
+            Value::St => emu.sound_counter.load(Ordering::Relaxed) as i64,
+            Value::Sp => emu.subroutine_return_pointers.len() as i64,
+            Value::Hits => hits as i64,
+        },
+        // reading outside of memory gives 0 rather than failing the whole condition
+        Expr::Memory(addr) => {
+            let addr = evaluate(addr, emu, hits);
+            if addr < 0 {
+                return 0;
+            }
+            emu.memory_space.get(addr as usize).copied().unwrap_or(0) as i64
+        }
+        Expr::Not(expr) => (evaluate(expr, emu, hits) == 0) as i64,
+        Expr::Negate(expr) => evaluate(expr, emu, hits).wrapping_neg(),
+        Expr::Binary(op, left, right) => {
+            let left = evaluate(left, emu, hits);
+            // || and && don't look at the right side unless they have to
+            match op {
+                BinaryOp::Or if left != 0 => return 1,
+                BinaryOp::And if left == 0 => return 0,
+                _ => (),
+            }
+            let right = evaluate(right, emu, hits);
+            match op {
+                BinaryOp::Or | BinaryOp::And => (right != 0) as i64,
+                BinaryOp::Equal => (left == right) as i64,
+                BinaryOp::NotEqual => (left != right) as i64,
+                BinaryOp::Less => (left < right) as i64,
+                BinaryOp::LessEqual => (left <= right) as i64,
+                BinaryOp::Greater => (left > right) as i64,
+                BinaryOp::GreaterEqual => (left >= right) as i64,
+                BinaryOp::BitOr => left | right,
+                BinaryOp::BitXor => left ^ right,
+                BinaryOp::BitAnd => left & right,
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Subtract => left.wrapping_sub(right),
+            }
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+use crate::condition::Condition;
+use crate::disasm::disassemble_at;
+use crate::emulator::{Emulator, EmulatorError, InstructionResult};
+use crate::watchpoint::{Access, Watchpoint};
+
+// An interactive step debugger. It sits between the frontend's loop and the emulator: the
+// frontend calls run_frame every frame as it would Emulator::run_frame, and hands it any
+// command lines the user types. While paused run_frame doesn't execute anything, so the
+// frontend keeps drawing the window as normal.
+// Commands return their output as text rather than printing it, so any frontend can show it
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint_id: u32,
+    paused: bool,
+    // instructions left to step before pausing again
+    steps_remaining: u32,
+    // set when resuming from a breakpoint, so we don't stop on it straight away again
+    resuming_from: Option<u16>,
+    // things that happened while running (breakpoints hit, steps finished) for the frontend to
+    // show, see take_messages
+    messages: Vec<String>,
+}
+
+// Stops before running the instruction at addr, or before any instruction when there's no addr,
+// as long as the condition (if it has one) holds
+struct Breakpoint {
+    id: u32,
+    addr: Option<u16>,
+    condition: Option<Condition>,
+    // times the breakpoint has been reached, which is every instruction when it has no addr
+    hits: u32,
+}
+
+impl Breakpoint {
+    fn describe(&self) -> String {
+        let mut text = format!("#{}", self.id);
+        if let Some(addr) = self.addr {
+            write!(text, " {:#06x}", addr).unwrap();
+        }
+        if let Some(condition) = &self.condition {
+            write!(text, " if {}", condition).unwrap();
+        }
+        write!(text, ", hit {} times", self.hits).unwrap();
+        text
+    }
+}
+
+pub const HELP: &str = "\
+commands (addresses and values are decimal, or hex with 0x):
+  step [n]          s   execute n instructions (default 1) then pause
+  continue          c   run until a breakpoint
+  pause                 pause before the next instruction
+  break <addr>      b   pause when PC reaches addr
+  break [addr] if <condition>   pause when the condition holds, at addr or anywhere
+                        eg break if V3 == 0 && I >= 0x300 && [I+2] != 0
+                        conditions can use V0-VF I PC DT ST SP HITS [addr], numbers and
+                        || && == != < <= > >= | ^ & + - ! ( )
+  delete <addr|#id> d   remove the breakpoints at addr, or the one numbered id
+  breakpoints           list breakpoints and watchpoints
+  watch <r|w|x> <addr> [end]    pause when addr (to end) is read, written or executed
+  unwatch <r|w|x> <addr> [end]  remove a watchpoint
+  regs              r   print V0-VF, I, PC, the stack and the timers
+  mem <addr> [len]  x   dump len bytes of memory (default 64)
+  set <target> <value>  set V0-VF, I, PC, DT, ST or memory ([addr])
+  list [n]          l   disassemble n instructions either side of PC (default 5)
+  help              h   print this";
+
+impl Debugger {
+    // starts paused, so nothing runs until the user says so
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            paused: true,
+            steps_remaining: 0,
+            resuming_from: None,
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused && self.steps_remaining == 0
+    }
+
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.steps_remaining = 0;
+    }
+
+    // spec is what follows break on the command line: `addr`, `addr if condition` or
+    // `if condition`
+    pub fn add_breakpoint(&mut self, spec: &str) -> Result<String, String> {
+        let spec = spec.trim();
+        let (addr, condition) = match spec.find("if") {
+            Some(start) if spec[..start].trim().is_empty() || spec[..start].ends_with(' ') => {
+                (spec[..start].trim(), Some(&spec[start + 2..]))
+            }
+            _ => (spec, None),
+        };
+        let addr = match addr {
+            "" => None,
+            addr => Some(parse_address(Some(&addr))?),
+        };
+        let condition = match condition {
+            Some(condition) => Some(Condition::parse(condition)?),
+            None => None,
+        };
+        if addr.is_none() && condition.is_none() {
+            return Err("missing an address".to_string());
+        }
+        let breakpoint = Breakpoint {
+            id: self.next_breakpoint_id,
+            addr,
+            condition,
+            hits: 0,
+        };
+        self.next_breakpoint_id += 1;
+        let description = format!("breakpoint {}", breakpoint.describe());
+        self.breakpoints.push(breakpoint);
+        Ok(description)
+    }
+
+    // a file of breakpoints, one per line in the same form as add_breakpoint, # starts a comment
+    pub fn load_breakpoints(&mut self, text: &str) -> Result<usize, String> {
+        let mut count = 0;
+        for (i, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            self.add_breakpoint(line)
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // counts a hit on every breakpoint reached at PC, and returns the first whose condition holds
+    fn check_breakpoints(&mut self, emu: &Emulator) -> Option<u32> {
+        let pc = emu.program_counter;
+        let mut triggered = None;
+        for breakpoint in &mut self.breakpoints {
+            if matches!(breakpoint.addr, Some(addr) if addr != pc) {
+                continue;
+            }
+            breakpoint.hits += 1;
+            let holds = match &breakpoint.condition {
+                Some(condition) => condition.is_true(emu, breakpoint.hits),
+                None => true,
+            };
+            if holds && triggered.is_none() {
+                triggered = Some(breakpoint.id);
+            }
+        }
+        triggered
+    }
+
+    // runs the emulator up to the end of the frame, stopping early on a breakpoint, a watchpoint
+    // or at the end of a step
+    pub fn run_frame(&mut self, emu: &mut Emulator) -> Result<InstructionResult, EmulatorError> {
+        let frame = emu.frame_count;
+        let mut redraw_requested = false;
+        while emu.frame_count == frame && !self.is_paused() {
+            let pc = emu.program_counter;
+            if !self.paused && self.resuming_from != Some(pc) {
+                if let Some(id) = self.check_breakpoints(emu) {
+                    self.paused = true;
+                    self.messages
+                        .push(format!("breakpoint #{}\n{}", id, current_line(emu)));
+                    break;
+                }
+            }
+            self.resuming_from = None;
+
+            match emu.execute_next_instruction()? {
+                InstructionResult::Terminated => return Ok(InstructionResult::Terminated),
+                InstructionResult::RedrawRequested => redraw_requested = true,
+                InstructionResult::Working => (),
+                InstructionResult::WatchpointHit(hits) => {
+                    redraw_requested = true;
+                    self.paused = true;
+                    self.steps_remaining = 0;
+                    for hit in hits {
+                        self.messages.push(format!("watchpoint, {}", hit));
+                    }
+                    self.messages.push(current_line(emu));
+                    break;
+                }
+            }
+
+            if self.paused {
+                self.steps_remaining -= 1;
+                if self.steps_remaining == 0 {
+                    self.messages.push(current_line(emu));
+                }
+            }
+        }
+
+        if redraw_requested {
+            Ok(InstructionResult::RedrawRequested)
+        } else {
+            Ok(InstructionResult::Working)
+        }
+    }
+
+    pub fn execute_command(&mut self, line: &str, emu: &mut Emulator) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.first() {
+            Some(command) => *command,
+            None => return String::new(),
+        };
+        let args = &words[1..];
+        match self.command(command, args, emu) {
+            Ok(output) => output,
+            Err(e) => e,
+        }
+    }
+
+    fn command(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        emu: &mut Emulator,
+    ) -> Result<String, String> {
+        match command {
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(count) => parse_value(count)?,
+                    None => 1,
+                };
+                if count == 0 {
+                    return Err("step needs a count above 0".to_string());
+                }
+                self.paused = true;
+                self.steps_remaining = count;
+                Ok(String::new())
+            }
+            "continue" | "c" => {
+                self.paused = false;
+                self.steps_remaining = 0;
+                self.resuming_from = Some(emu.program_counter);
+                Ok("continuing".to_string())
+            }
+            "pause" => {
+                self.paused = true;
+                self.steps_remaining = 0;
+                Ok(current_line(emu))
+            }
+            "break" | "b" => self.add_breakpoint(&args.join(" ")),
+            "delete" | "d" => {
+                let count = self.breakpoints.len();
+                match args.first().and_then(|arg| arg.strip_prefix('#')) {
+                    Some(id) => {
+                        let id = parse_value(id)?;
+                        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+                    }
+                    None => {
+                        let addr = parse_address(args.first())?;
+                        self.breakpoints
+                            .retain(|breakpoint| breakpoint.addr != Some(addr));
+                    }
+                }
+                match count - self.breakpoints.len() {
+                    0 => Err("there's no breakpoint like that".to_string()),
+                    1 => Ok("breakpoint deleted".to_string()),
+                    deleted => Ok(format!("{} breakpoints deleted", deleted)),
+                }
+            }
+            "breakpoints" => {
+                if self.breakpoints.is_empty() && emu.watchpoints.is_empty() {
+                    return Ok("no breakpoints".to_string());
+                }
+                let mut lines: Vec<String> = self
+                    .breakpoints
+                    .iter()
+                    .map(|breakpoint| breakpoint.describe())
+                    .collect();
+                lines.extend(emu.watchpoints.iter().map(|watchpoint| {
+                    format!(
+                        "{:#06x}-{:#06x} {}",
+                        watchpoint.start, watchpoint.end, watchpoint.access
+                    )
+                }));
+                Ok(lines.join("\n"))
+            }
+            "watch" => {
+                let watchpoint = parse_watchpoint(args)?;
+                if !emu.watchpoints.contains(&watchpoint) {
+                    emu.watchpoints.push(watchpoint);
+                }
+                Ok(format!(
+                    "watching {:#06x}-{:#06x} for {}",
+                    watchpoint.start, watchpoint.end, watchpoint.access
+                ))
+            }
+            "unwatch" => {
+                let watchpoint = parse_watchpoint(args)?;
+                let count = emu.watchpoints.len();
+                emu.watchpoints.retain(|w| *w != watchpoint);
+                if emu.watchpoints.len() < count {
+                    Ok("watchpoint deleted".to_string())
+                } else {
+                    Err("there's no watchpoint like that".to_string())
+                }
+            }
+            "regs" | "r" => Ok(registers(emu)),
+            "mem" | "x" => {
+                let addr = parse_address(args.first())? as usize;
+                let len = match args.get(1) {
+                    Some(len) => parse_value(len)? as usize,
+                    None => 64,
+                };
+                let end = (addr + len).min(emu.memory_space.len());
+                let mut output = String::new();
+                for row in (addr..end).step_by(16) {
+                    let bytes: Vec<String> = emu.memory_space[row..(row + 16).min(end)]
+                        .iter()
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect();
+                    writeln!(output, "{:#06x}  {}", row, bytes.join(" ")).unwrap();
+                }
+                Ok(output.trim_end().to_string())
+            }
+            "set" => {
+                if args.len() != 2 {
+                    return Err("usage: set <target> <value>".to_string());
+                }
+                self.set(args[0], parse_value(args[1])?, emu)?;
+                Ok(String::new())
+            }
+            "list" | "l" => {
+                let count = match args.first() {
+                    Some(count) => parse_value(count)? as usize,
+                    None => 5,
+                };
+                Ok(listing(emu, count))
+            }
+            "help" | "h" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command {}, try help", command)),
+        }
+    }
+
+    fn set(&mut self, target: &str, value: u32, emu: &mut Emulator) -> Result<(), String> {
+        let upper = target.to_ascii_uppercase();
+        let byte = || {
+            if value > 0xFF {
+                Err(format!("{:#x} doesn't fit in a byte", value))
+            } else {
+                Ok(value as u8)
+            }
+        };
+        let word = || {
+            if value > 0xFFFF {
+                Err(format!("{:#x} doesn't fit in 16 bits", value))
+            } else {
+                Ok(value as u16)
+            }
+        };
+        match upper.as_str() {
+            "I" => emu.address_register = word()?,
+            "PC" => emu.program_counter = word()?,
+            "DT" => emu.timer_counter.store(byte()?, Ordering::Relaxed),
+            "ST" => emu.sound_counter.store(byte()?, Ordering::Relaxed),
+            _ if upper.len() == 2 && upper.starts_with('V') => {
+                let register = u8::from_str_radix(&upper[1..], 16)
+                    .map_err(|_| format!("{} isn't a register", target))?;
+                emu.registers[register as usize] = byte()?;
+            }
+            _ if upper.starts_with('[') && upper.ends_with(']') => {
+                let addr = parse_value(&upper[1..upper.len() - 1])? as usize;
+                if addr >= emu.memory_space.len() {
+                    return Err(format!("{:#x} is outside of memory", addr));
+                }
+                emu.memory_space[addr] = byte()?;
+            }
+            _ => return Err(format!("can't set {}", target)),
+        }
+        Ok(())
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+// the instruction about to run
+fn current_line(emu: &Emulator) -> String {
+    let pc = emu.program_counter;
+    let (text, _) = disassemble_at(&emu.memory_space, pc as usize);
+    format!("{:#06x}  {}", pc, text)
+}
+
+fn registers(emu: &Emulator) -> String {
+    let mut output = String::new();
+    for (i, value) in emu.registers.iter().enumerate() {
+        write!(output, "V{:X}={:02X} ", i, value).unwrap();
+        if i == 7 {
+            output.push('\n');
+        }
+    }
+    let stack: Vec<String> = emu
+        .subroutine_return_pointers
+        .iter()
+        .map(|addr| format!("{:#06x}", addr))
+        .collect();
+    write!(
+        output,
+        "\nI={:#06x} PC={:#06x} SP={} DT={} ST={}\nstack: [{}]",
+        emu.address_register,
+        emu.program_counter,
+        emu.subroutine_return_pointers.len(),
+        emu.timer_counter.load(Ordering::Relaxed),
+        emu.sound_counter.load(Ordering::Relaxed),
+        stack.join(", ")
+    )
+    .unwrap();
+    output
+}
+
+// count instructions either side of PC. Going backwards we can't know where instructions
+// start, so that side assumes they're all 2 bytes
+fn listing(emu: &Emulator, count: usize) -> String {
+    let pc = emu.program_counter as usize;
+    let mut addr = pc.saturating_sub(count * 2);
+    let mut output = String::new();
+    let mut after = 0;
+    while addr < emu.memory_space.len() && after <= count {
+        let (text, len) = disassemble_at(&emu.memory_space, addr);
+        let marker = if addr == pc { "->" } else { "  " };
+        writeln!(output, "{} {:#06x}  {}", marker, addr, text).unwrap();
+        if addr >= pc {
+            after += 1;
+        }
+        addr += len;
+    }
+    output.trim_end().to_string()
+}
+
+fn parse_value(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("{} isn't a number", text))
+}
+
+// <r|w|x> <addr> [end]
+fn parse_watchpoint(args: &[&str]) -> Result<Watchpoint, String> {
+    let access = match args.first() {
+        Some(&"r") | Some(&"read") => Access::Read,
+        Some(&"w") | Some(&"write") => Access::Write,
+        Some(&"x") | Some(&"execute") => Access::Execute,
+        _ => return Err("usage: watch <r|w|x> <addr> [end]".to_string()),
+    };
+    let start = parse_address(args.get(1))?;
+    let end = match args.get(2) {
+        Some(_) => parse_address(args.get(2))?,
+        None => start,
+    };
+    if end < start {
+        return Err("the end of the range is before the start".to_string());
+    }
+    Ok(Watchpoint::new(start, end, access))
+}
+
+fn parse_address(text: Option<&&str>) -> Result<u16, String> {
+    let text = text.ok_or_else(|| "missing an address".to_string())?;
+    let value = parse_value(text)?;
+    if value > 0xFFFF {
+        return Err(format!("{:#x} is outside of memory", value));
+    }
+    Ok(value as u16)
+}
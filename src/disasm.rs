@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+use crate::emulator::{EmulatorError, Opcode, MAX_MEMORY, PROGRAM_START};
+
+// Mnemonics follow Cowgod's technical reference
+// (http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.1), with the SUPER-CHIP and XO-CHIP
+// additions named after what they do in the same style
+impl Opcode {
+    // None if this isn't an instruction we know, most likely it's data.
+    // F000 is followed by the address it loads, which isn't part of the opcode, so that's left
+    // off here (disassemble_rom fills it in)
+    pub fn disassemble(&self) -> Option<String> {
+        let code = self.full_opcode();
+        let x = (code >> 8) & 0xF;
+        let y = (code >> 4) & 0xF;
+        let n = code & 0xF;
+        let nn = code & 0xFF;
+        let nnn = code & 0xFFF;
+
+        let text = match code >> 12 {
+            0x0 => match code {
+                0x00E0 => "CLS".to_string(),
+                0x00EE => "RET".to_string(),
+                0x00FB => "SCR".to_string(),
+                0x00FC => "SCL".to_string(),
+                0x00FD => "EXIT".to_string(),
+                0x00FE => "LOW".to_string(),
+                0x00FF => "HIGH".to_string(),
+                0x00C0..=0x00CF => format!("SCD {}", n),
+                0x00D0..=0x00DF => format!("SCU {}", n),
+                _ => format!("SYS 0x{:03X}", nnn),
+            },
+            0x1 => format!("JP 0x{:03X}", nnn),
+            0x2 => format!("CALL 0x{:03X}", nnn),
+            0x3 => format!("SE V{:X}, 0x{:02X}", x, nn),
+            0x4 => format!("SNE V{:X}, 0x{:02X}", x, nn),
+            0x5 => match n {
+                0x0 => format!("SE V{:X}, V{:X}", x, y),
+                0x2 => format!("SAVE V{:X}, V{:X}", x, y),
+                0x3 => format!("LOAD V{:X}, V{:X}", x, y),
+                _ => return None,
+            },
+            0x6 => format!("LD V{:X}, 0x{:02X}", x, nn),
+            0x7 => format!("ADD V{:X}, 0x{:02X}", x, nn),
+            0x8 => {
+                let mnemonic = match n {
+                    0x0 => "LD",
+                    0x1 => "OR",
+                    0x2 => "AND",
+                    0x3 => "XOR",
+                    0x4 => "ADD",
+                    0x5 => "SUB",
+                    0x6 => "SHR",
+                    0x7 => "SUBN",
+                    0xE => "SHL",
+                    _ => return None,
+                };
+                format!("{} V{:X}, V{:X}", mnemonic, x, y)
+            }
+            0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+            0xA => format!("LD I, 0x{:03X}", nnn),
+            0xB => format!("JP V0, 0x{:03X}", nnn),
+            0xC => format!("RND V{:X}, 0x{:02X}", x, nn),
+            0xD => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            0xE => match nn {
+                0x9E => format!("SKP V{:X}", x),
+                0xA1 => format!("SKNP V{:X}", x),
+                _ => return None,
+            },
+            0xF => match nn {
+                0x00 if x == 0 => "LD I, LONG".to_string(),
+                0x01 => format!("PLANE {}", x),
+                0x02 if x == 0 => "AUDIO".to_string(),
+                0x07 => format!("LD V{:X}, DT", x),
+                0x0A => format!("LD V{:X}, K", x),
+                0x15 => format!("LD DT, V{:X}", x),
+                0x18 => format!("LD ST, V{:X}", x),
+                0x1E => format!("ADD I, V{:X}", x),
+                0x29 => format!("LD F, V{:X}", x),
+                0x30 => format!("LD HF, V{:X}", x),
+                0x33 => format!("LD B, V{:X}", x),
+                0x3A => format!("PITCH V{:X}", x),
+                0x55 => format!("LD [I], V{:X}", x),
+                0x65 => format!("LD V{:X}, [I]", x),
+                0x75 => format!("LD R, V{:X}", x),
+                0x85 => format!("LD V{:X}, R", x),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    // the first interpreter to have this instruction, unknown opcodes count as CHIP-8
+    pub fn platform(&self) -> Platform {
+        let code = self.full_opcode();
+        match code {
+            0x00C0..=0x00CF | 0x00FB..=0x00FF => Platform::SuperChip,
+            0x00D0..=0x00DF => Platform::XoChip,
+            _ => match (code >> 12, code & 0xF, code & 0xFF) {
+                (0xD, 0x0, _) => Platform::SuperChip,
+                (0xF, _, 0x30) | (0xF, _, 0x75) | (0xF, _, 0x85) => Platform::SuperChip,
+                (0x5, 0x2, _) | (0x5, 0x3, _) => Platform::XoChip,
+                (0xF, _, 0x01) | (0xF, _, 0x3A) => Platform::XoChip,
+                (0xF, _, 0x00) | (0xF, _, 0x02) if code & 0x0F00 == 0 => Platform::XoChip,
+                _ => Platform::Chip8,
+            },
+        }
+    }
+}
+
+// the interpreters that added instructions to CHIP-8, in the order they came out
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// disassembles the instruction at addr in memory, filling in F000's address from the word after
+// it. Returns the text and how many bytes the instruction takes
+pub fn disassemble_at(memory: &[u8], addr: usize) -> (String, usize) {
+    if addr + 1 >= memory.len() {
+        return ("(out of memory)".to_string(), 2);
+    }
+    let code = (memory[addr] as u16) << 8 | memory[addr + 1] as u16;
+    match Opcode::new(code).disassemble() {
+        Some(_) if code == 0xF000 && addr + 3 < memory.len() => {
+            let address = (memory[addr + 2] as u16) << 8 | memory[addr + 3] as u16;
+            (format!("LD I, LONG 0x{:04X}", address), 4)
+        }
+        Some(text) => (text, 2),
+        None => (format!("DW 0x{:04X}", code), 2),
+    }
+}
+
+// where execution can go after an instruction
+enum Flow {
+    Next,
+    Stop,
+    Jump(u16),
+    Call(u16),
+    Skip,
+}
+
+fn flow(code: u16) -> Flow {
+    match code >> 12 {
+        // 0000 ends the program in this emulator, the rest of 0NNN does nothing
+        0x0 => match code {
+            0x0000 | 0x00EE | 0x00FD => Flow::Stop,
+            _ => Flow::Next,
+        },
+        0x1 => Flow::Jump(code & 0xFFF),
+        0x2 => Flow::Call(code & 0xFFF),
+        0x3 | 0x4 | 0x5 | 0x9 | 0xE => Flow::Skip,
+        // the target depends on V0, so there's no telling where it goes
+        0xB => Flow::Stop,
+        _ => Flow::Next,
+    }
+}
+
+// F000 NNNN is the only instruction that takes 4 bytes
+fn instruction_len(code: u16) -> usize {
+    if code == 0xF000 {
+        4
+    } else {
+        2
+    }
+}
+
+enum Label {
+    Jump,
+    Subroutine,
+}
+
+fn label_name(addr: u16, label: &Label) -> String {
+    match label {
+        Label::Jump => format!("label_{:03X}", addr),
+        Label::Subroutine => format!("sub_{:03X}", addr),
+    }
+}
+
+// follows the program from the entry point, returning every instruction it reaches by address
+// and the labels for the places it jumps and calls to
+fn trace_rom(rom: &[u8]) -> (BTreeMap<u16, u16>, BTreeMap<u16, Label>) {
+    let origin = PROGRAM_START as u16;
+    let in_rom = |addr: u16| addr >= origin && ((addr - origin) as usize) + 1 < rom.len();
+    let word = |addr: u16| {
+        let i = (addr - origin) as usize;
+        (rom[i] as u16) << 8 | rom[i + 1] as u16
+    };
+    let mut instructions = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+    let mut pending = vec![origin];
+    while let Some(addr) = pending.pop() {
+        if !in_rom(addr) || instructions.contains_key(&addr) {
+            continue;
+        }
+        let code = word(addr);
+        instructions.insert(addr, code);
+        if Opcode::new(code).disassemble().is_none() {
+            continue;
+        }
+        let next = addr.wrapping_add(instruction_len(code) as u16);
+        match flow(code) {
+            Flow::Next => pending.push(next),
+            Flow::Stop => (),
+            Flow::Jump(target) => {
+                labels.entry(target).or_insert(Label::Jump);
+                pending.push(target);
+            }
+            Flow::Call(target) => {
+                labels.insert(target, Label::Subroutine);
+                pending.push(target);
+                pending.push(next);
+            }
+            Flow::Skip => {
+                pending.push(next);
+                if in_rom(next) {
+                    pending.push(next.wrapping_add(instruction_len(word(next)) as u16));
+                }
+            }
+        }
+    }
+    (instructions, labels)
+}
+
+// the opcode of every instruction reachable from the entry point, by address
+pub fn reachable_instructions(rom: &[u8]) -> BTreeMap<u16, u16> {
+    trace_rom(rom).0
+}
+
+// Disassembles a rom as if loaded at 0x200. Rather than decoding every pair of bytes, it follows
+// the program from its entry point through jumps, calls and skips, so sprites and other data
+// mixed in with the code are shown as DB rows instead of nonsense instructions. Anything it
+// reaches that doesn't decode is flagged, as are jumps through BNNN which it can't follow.
+// A rom too big to load is an error, since its end would be past the top of memory
+pub fn disassemble_rom(rom: &[u8]) -> Result<String, EmulatorError> {
+    let max = MAX_MEMORY - PROGRAM_START;
+    if rom.len() > max {
+        return Err(EmulatorError::RomTooLarge {
+            size: rom.len(),
+            max,
+        });
+    }
+    let origin = PROGRAM_START as u16;
+    let in_rom = |addr: u16| addr >= origin && ((addr - origin) as usize) + 1 < rom.len();
+    let word = |addr: u16| {
+        let i = (addr - origin) as usize;
+        (rom[i] as u16) << 8 | rom[i + 1] as u16
+    };
+    let (instructions, labels) = trace_rom(rom);
+    let rows = listing_rows(rom, &instructions, &labels);
+    // a jump into the middle of an instruction (eg to an odd address just after a 2 byte one)
+    // has nowhere in the listing for its label to go
+    let row_starts: BTreeSet<u16> = rows.iter().map(|(i, _)| origin + *i as u16).collect();
+
+    let mut listing = String::new();
+    for (i, len) in rows {
+        let addr = origin + i as u16;
+        if let Some(label) = labels.get(&addr) {
+            if !listing.is_empty() {
+                listing.push('\n');
+            }
+            writeln!(listing, "{}:", label_name(addr, label)).unwrap();
+        }
+        if let Some(&code) = instructions.get(&addr) {
+            let raw: String = rom[i..i + len]
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect();
+            let text = match Opcode::new(code).disassemble() {
+                Some(_) if code == 0xF000 && len == 4 => {
+                    format!("LD I, LONG 0x{:04X}", word(addr + 2))
+                }
+                Some(text) => match flow(code) {
+                    Flow::Jump(target) | Flow::Call(target) => match labels.get(&target) {
+                        Some(label) if in_rom(target) && row_starts.contains(&target) => {
+                            let mnemonic = if code >> 12 == 0x1 { "JP" } else { "CALL" };
+                            format!("{} {}", mnemonic, label_name(target, label))
+                        }
+                        Some(_) if in_rom(target) => {
+                            format!("{:<24}; into the middle of an instruction", text)
+                        }
+                        _ => text,
+                    },
+                    Flow::Stop if code >> 12 == 0xB => {
+                        format!("{:<24}; computed jump, not followed", text)
+                    }
+                    _ => text,
+                },
+                None => format!("{:<24}; unknown opcode", format!("DW 0x{:04X}", code)),
+            };
+            writeln!(listing, "0x{:04X}  {:<8}  {}", addr, raw, text).unwrap();
+            continue;
+        }
+
+        let data = &rom[i..i + len];
+        let raw: String = data.iter().map(|b| format!("{:02X}", b)).collect();
+        let bytes: Vec<String> = data.iter().map(|b| format!("0x{:02X}", b)).collect();
+        writeln!(
+            listing,
+            "0x{:04X}  {:<8}  DB {}",
+            addr,
+            raw,
+            bytes.join(", ")
+        )
+        .unwrap();
+    }
+    Ok(listing)
+}
+
+// splits the rom into the rows of the listing, as (offset, length) pairs. Each instruction is a
+// row, and the data between them is in runs of up to 4 bytes, broken up by labels
+fn listing_rows(
+    rom: &[u8],
+    instructions: &BTreeMap<u16, u16>,
+    labels: &BTreeMap<u16, Label>,
+) -> Vec<(usize, usize)> {
+    let origin = PROGRAM_START as u16;
+    let mut rows = Vec::new();
+    let mut i = 0;
+    while i < rom.len() {
+        let len = match instructions.get(&(origin + i as u16)) {
+            Some(&code) => instruction_len(code).min(rom.len() - i),
+            None => {
+                let mut len = 1;
+                while len < 4 && i + len < rom.len() {
+                    let next = origin + (i + len) as u16;
+                    if labels.contains_key(&next) || instructions.contains_key(&next) {
+                        break;
+                    }
+                    len += 1;
+                }
+                len
+            }
+        };
+        rows.push((i, len));
+        i += len;
+    }
+    rows
+}
@@ -0,0 +1,247 @@
+#[cfg(feature = "screenshots")]
+use std::io::{self, Write};
+
+use serde_derive::{Deserialize, Serialize};
+
+// lores screen 64 x 32 pixels
+// top left 00, 00
+// bottom right 3F, 1F
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+// SUPER-CHIP's hires mode doubles both, 128 x 64 pixels
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// black is the default window fill
+// white is the default pixel fill while initializing
+// https://coolors.co/89b6a5-4c3b4d-c9eddc-82968c-6a706e
+// https://coolors.co/14110f-34312d-7e7f83-d9c5b2-f3f3f4
+pub const SET_COLOUR: [u8; 4] = [0xF3, 0xF3, 0xF4, 0xFF];
+pub const UNSET_COLOUR: [u8; 4] = [0x14, 0x11, 0x0F, 0xFF];
+// XO-CHIP draws to two bitplanes, so a pixel can be in one of four states
+// index 1 is a pixel only set in the first plane, 2 only the second plane, 3 both
+pub const PALETTE: [[u8; 4]; 4] = [
+    UNSET_COLOUR,
+    SET_COLOUR,
+    [0x7E, 0x7F, 0x83, 0xFF],
+    [0xD9, 0xC5, 0xB2, 0xFF],
+];
+
+// The emulator's model of the screen, one byte per pixel with a bit for each bitplane it's set
+// in (only XO-CHIP programs ever use the second plane).
+// All drawing and collision detection happens against this, the pixels frame buffer
+// is only ever a presentation of it, so it works the same when running headless
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    // bitmask of the planes that drawing, clearing and scrolling affect
+    selected_planes: u8,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display {
+            width: LORES_WIDTH,
+            height: LORES_HEIGHT,
+            pixels: vec![0; LORES_WIDTH * LORES_HEIGHT],
+            selected_planes: 0b01,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & 0b11;
+    }
+
+    // false if the fields don't fit together, which only happens when a display comes from
+    // somewhere like a damaged save state
+    pub fn is_valid(&self) -> bool {
+        let size = (self.width, self.height);
+        (size == (LORES_WIDTH, LORES_HEIGHT) || size == (HIRES_WIDTH, HIRES_HEIGHT))
+            && self.pixels.len() == self.width * self.height
+            && self.pixels.iter().all(|pixel| *pixel <= 0b11)
+            && self.selected_planes <= 0b11
+    }
+
+    // switching resolution clears every plane
+    pub fn set_hires(&mut self, hires: bool) {
+        if hires {
+            self.width = HIRES_WIDTH;
+            self.height = HIRES_HEIGHT;
+        } else {
+            self.width = LORES_WIDTH;
+            self.height = LORES_HEIGHT;
+        }
+        self.pixels = vec![0; self.width * self.height];
+    }
+
+    pub fn clear(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= !self.selected_planes;
+        }
+    }
+
+    // true if the pixel is set in any plane
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[x + y * self.width] != 0
+    }
+
+    // the planes the pixel is set in, as a bitmask
+    pub fn get_pixel_planes(&self, x: usize, y: usize) -> u8 {
+        self.pixels[x + y * self.width]
+    }
+
+    // XORs a set pixel onto the given plane (as a bitmask) of the display
+    // returns true if this caused a set pixel to be unset (a collision)
+    pub fn flip_pixel(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let index = x + y * self.width;
+        let was_set = self.pixels[index] & plane != 0;
+        self.pixels[index] ^= plane;
+        was_set
+    }
+
+    // scrolling moves the selected planes, pixels scrolled in from outside are unset
+    pub fn scroll_down(&mut self, rows: usize) {
+        let rows = rows.min(self.height);
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let source = if y >= rows {
+                    self.pixels[x + (y - rows) * self.width]
+                } else {
+                    0
+                };
+                self.move_pixel(source, x, y);
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        let rows = rows.min(self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let source = if y + rows < self.height {
+                    self.pixels[x + (y + rows) * self.width]
+                } else {
+                    0
+                };
+                self.move_pixel(source, x, y);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        let columns = columns.min(self.width);
+        for y in 0..self.height {
+            for x in (0..self.width).rev() {
+                let source = if x >= columns {
+                    self.pixels[x - columns + y * self.width]
+                } else {
+                    0
+                };
+                self.move_pixel(source, x, y);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        let columns = columns.min(self.width);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let source = if x + columns < self.width {
+                    self.pixels[x + columns + y * self.width]
+                } else {
+                    0
+                };
+                self.move_pixel(source, x, y);
+            }
+        }
+    }
+
+    // replaces the selected planes of a pixel with the ones from source
+    fn move_pixel(&mut self, source: u8, x: usize, y: usize) {
+        let index = x + y * self.width;
+        self.pixels[index] =
+            (self.pixels[index] & !self.selected_planes) | (source & self.selected_planes);
+    }
+
+    // writes the display into an RGBA frame (eg the one from Pixels::get_frame)
+    // the frame needs to be width x height pixels
+    pub fn draw_to_frame(&self, frame: &mut [u8]) {
+        for (pixel, rgba_chunk) in self.pixels.iter().zip(frame.chunks_exact_mut(4)) {
+            rgba_chunk.copy_from_slice(&PALETTE[*pixel as usize]);
+        }
+    }
+
+    // one character per pixel and a line per row, . for unset and # for set. With XO-CHIP's
+    // second plane, + is a pixel only set in that plane and @ one set in both
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::with_capacity((self.width + 1) * self.height);
+        for row in self.pixels.chunks_exact(self.width) {
+            ascii.extend(
+                row.iter()
+                    .map(|pixel| ['.', '#', '+', '@'][*pixel as usize]),
+            );
+            ascii.push('\n');
+        }
+        ascii
+    }
+
+    // a plain (P1) PBM, where pixels set in any plane are black
+    pub fn to_pbm(&self) -> String {
+        let mut pbm = format!("P1\n{} {}\n", self.width, self.height);
+        for row in self.pixels.chunks_exact(self.width) {
+            let bits: Vec<&str> = row
+                .iter()
+                .map(|pixel| if *pixel != 0 { "1" } else { "0" })
+                .collect();
+            pbm.push_str(&bits.join(" "));
+            pbm.push('\n');
+        }
+        pbm
+    }
+
+    // a PNG in the same colours as the window, each pixel blown up to scale x scale
+    #[cfg(feature = "screenshots")]
+    pub fn write_png<W: Write>(&self, writer: W, scale: usize) -> io::Result<()> {
+        let scale = scale.max(1);
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut data = Vec::with_capacity(width * height * 4);
+        for row in self.pixels.chunks_exact(self.width) {
+            let mut scaled_row = Vec::with_capacity(width * 4);
+            for pixel in row {
+                for _ in 0..scale {
+                    scaled_row.extend_from_slice(&PALETTE[*pixel as usize]);
+                }
+            }
+            for _ in 0..scale {
+                data.extend_from_slice(&scaled_row);
+            }
+        }
+
+        let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(&data)?;
+        Ok(())
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new()
+    }
+}
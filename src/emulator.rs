@@ -1,28 +1,40 @@
-use pixels::Pixels;
-use rand::random;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::prelude::*;
-use std::sync::atomic::{AtomicU8, Ordering};
+#[cfg(feature = "screenshots")]
+use std::io::BufWriter;
+use std::io::{self, prelude::*};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use winit::dpi::PhysicalSize;
+
+use crate::audio::{AudioState, AUDIO_PATTERN_LEN};
+use crate::display::Display;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+use crate::savestate::{SaveState, SAVE_STATE_VERSION};
+use crate::trace::{TraceEntry, Tracer};
+use crate::watchpoint::{Access, Watchpoint, WatchpointHit};
 
 //refactor todo list
 // todo newtypes for address and registers and maybe program counter
-// todo timer_counter decremented on side thread dedicated to just decrementing it at regular
-//      interval (we'll just use arc and an atomic integer)
 
-// 4kb memory, 512bytes reserved for system
+// classic chip 8 has 4kb memory, 512bytes reserved for system
 // 4096 - 512 = 3584 max bytes for apps
-const MAX_MEMORY: usize = 4096;
+// XO-CHIP grows that to 64kb with the xochip_memory quirk, the most any rom gets
+pub const MAX_MEMORY: usize = 0x10000;
+pub const PROGRAM_START: usize = 0x200;
+
+// the original interpreter had room for 12 levels of nesting, most later ones allow 16
+pub const MAX_STACK_DEPTH: usize = 16;
 
-// black is the default window fill
-// white is the default pixel fill while initializing
-// https://coolors.co/89b6a5-4c3b4d-c9eddc-82968c-6a706e
-// https://coolors.co/14110f-34312d-7e7f83-d9c5b2-f3f3f4
-const SET_COLOUR: [u8; 4] = [0xF3, 0xF3, 0xF4, 0xFF];
-const UNSET_COLOUR: [u8; 4] = [0x14, 0x11, 0x0F, 0xFF];
+// the timers run at 60hz, so this is how many instructions make up one emulated 60th of a second
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+// where the built-in fonts get loaded into the reserved system memory
+const SMALL_FONT_START: usize = 0x0;
+const BIG_FONT_START: usize = 0x50;
 
 //built-in hex sprites, taken from http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.5
 const BUILTIN_SPRITES: [u8; 80] = [
@@ -44,6 +56,27 @@ const BUILTIN_SPRITES: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+//built-in 8x10 hex sprites for SUPER-CHIP's hires mode, SUPER-CHIP itself only had 0-9 but
+//XO-CHIP fills in A-F, these are the ones Octo uses
+const BUILTIN_BIG_SPRITES: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // zero
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // one
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // two
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // three
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // four
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // five
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // six
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // seven
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // eight
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // nine
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 #[derive(Copy, Clone)]
 pub struct Opcode {
     full_opcode: u16,
@@ -99,6 +132,10 @@ impl Opcode {
 
         opcode
     }
+
+    pub fn full_opcode(&self) -> u16 {
+        self.full_opcode
+    }
 }
 
 impl From<u16> for Opcode {
@@ -116,19 +153,101 @@ impl From<(u8, u8)> for Opcode {
 #[derive(PartialEq)]
 enum OpcodeResult {
     Continue,
+    ContinueLong,
     Terminate,
     Jump(u16),
     SkipNext,
     Malformed,
     RequestRedraw,
     WaitingForKey(u8),
+    StackUnderflow,
+    StackOverflow,
+    OutOfBounds(usize),
 }
 
-#[derive(PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TimerMode {
+    // the delay and sound timers tick once per emulated frame (every instructions_per_frame
+    // instructions), so timing only depends on what the program executes
+    Emulated,
+    // the timers are ticked by a background thread every ~16ms of wall clock time
+    FreeRunning,
+}
+
+#[derive(PartialEq, Debug)]
 pub enum InstructionResult {
     Working,
     RedrawRequested,
     Terminated,
+    // the instruction touched watched memory. Drawing a sprite can trip a watchpoint, so
+    // frontends should redraw on this too
+    WatchpointHit(Vec<WatchpointHit>),
+}
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    MalformedOpcode { addr: u16, opcode: u16 },
+    StackUnderflow { addr: u16 },
+    StackOverflow { addr: u16 },
+    MemoryOutOfBounds { addr: usize },
+    RomTooLarge { size: usize, max: usize },
+    InvalidSaveState,
+    UnsupportedSaveStateVersion { version: u16 },
+    Io(io::Error),
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::MalformedOpcode { addr, opcode } => {
+                write!(f, "malformed opcode {:#06x} at {:#06x}", opcode, addr)
+            }
+            EmulatorError::StackUnderflow { addr } => write!(
+                f,
+                "returned from a subroutine at {:#06x} with an empty stack",
+                addr
+            ),
+            EmulatorError::StackOverflow { addr } => write!(
+                f,
+                "subroutine call at {:#06x} exceeded the max stack depth of {}",
+                addr, MAX_STACK_DEPTH
+            ),
+            EmulatorError::MemoryOutOfBounds { addr } => {
+                write!(
+                    f,
+                    "attempted to access out of bounds memory at {:#06x}",
+                    addr
+                )
+            }
+            EmulatorError::RomTooLarge { size, max } => write!(
+                f,
+                "rom is {} bytes, but only {} bytes are available for programs",
+                size, max
+            ),
+            EmulatorError::InvalidSaveState => write!(f, "not a valid save state"),
+            EmulatorError::UnsupportedSaveStateVersion { version } => write!(
+                f,
+                "save state is version {}, only version {} is supported",
+                version, SAVE_STATE_VERSION
+            ),
+            EmulatorError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EmulatorError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(e: io::Error) -> Self {
+        EmulatorError::Io(e)
+    }
 }
 
 pub struct Emulator {
@@ -136,97 +255,337 @@ pub struct Emulator {
     // although we need usize to access the array that the instructions are stored in
     // its better to explicitly say u16 as usize can technically be as small as u8
     pub address_register: u16,
-    pub memory_space: [u8; MAX_MEMORY],
+    pub memory_space: Vec<u8>,
     pub timer_counter: Arc<AtomicU8>,
     pub sound_counter: Arc<AtomicU8>,
+    pub audio_state: Arc<AudioState>,
     pub program_counter: u16,
     pub subroutine_return_pointers: Vec<u16>,
-    pub pixels_frame_buffer: Option<Pixels>, // is option to support headless mode (for testing)
+    pub display: Display,
+    pub quirks: Quirks,
+    pub rng: Rng,
+    pub rpl_flags: [u8; 16],
     pub end_loop_reached: bool,
     pub key_states: [bool; 16],
     key_states_last_cycle: [bool; 16],
     waiting_for_key: bool,
     reg_waiting_for_key: u8,
+    pub instructions_per_frame: u32,
+    pub frame_count: u64,
+    frame_instruction_count: u32,
+    // every instruction executed, including the cycles spent waiting for a key
+    pub cycle_count: u64,
+    timer_mode: TimerMode,
+    timer_thread_running: Option<Arc<AtomicBool>>,
+    // debugging aids rather than machine state, so these aren't saved with save states
+    pub watchpoints: Vec<Watchpoint>,
+    watchpoint_hits: Vec<WatchpointHit>,
+    // the address an execute watchpoint last stopped on, so running again carries on past it
+    resuming_from_watchpoint: Option<u16>,
+    pub tracer: Option<Tracer>,
 }
 
 impl Emulator {
-    pub fn new(p: Pixels) -> Self {
+    // the emulator knows nothing of windows or audio devices, a frontend draws the display
+    // and plays the sound timer however it likes
+    pub fn new() -> Self {
         let mut emu = Emulator {
             registers: [0_u8; 16],
             address_register: 0_u16,
-            memory_space: [0_u8; MAX_MEMORY],
+            memory_space: vec![0_u8; Quirks::default().memory_size()],
             timer_counter: Arc::new(AtomicU8::new(0)),
             sound_counter: Arc::new(AtomicU8::new(0)),
-            program_counter: 0x200_u16,
+            audio_state: Arc::new(AudioState::new()),
+            program_counter: PROGRAM_START as u16,
             subroutine_return_pointers: Vec::new(),
-            pixels_frame_buffer: Some(p),
+            display: Display::new(),
+            quirks: Quirks::default(),
+            rng: Rng::from_entropy(),
+            rpl_flags: [0_u8; 16],
             end_loop_reached: false,
             key_states: [false; 16],
             key_states_last_cycle: [false; 16],
             waiting_for_key: false,
             reg_waiting_for_key: 0,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            frame_count: 0,
+            frame_instruction_count: 0,
+            cycle_count: 0,
+            timer_mode: TimerMode::Emulated,
+            timer_thread_running: None,
+            watchpoints: Vec::new(),
+            watchpoint_hits: Vec::new(),
+            resuming_from_watchpoint: None,
+            tracer: None,
         };
-        // fill first 80 bytes of memory with out built-in hex digit sprites
-        emu.memory_space[..80].copy_from_slice(&BUILTIN_SPRITES);
-        emu.clear_screen();
-        start_timer_thread(&emu);
+        // fill the start of memory with our built-in hex digit sprites
+        emu.load_fonts();
         emu
     }
 
+    // every emulator is headless now the window lives in the frontend, this is the same as new
     pub fn new_headless() -> Self {
-        let mut emu = Emulator {
-            registers: [0_u8; 16],
-            address_register: 0_u16,
-            memory_space: [0_u8; MAX_MEMORY],
-            timer_counter: Arc::new(AtomicU8::new(0)),
-            sound_counter: Arc::new(AtomicU8::new(0)),
-            program_counter: 0x200_u16,
-            subroutine_return_pointers: Vec::new(),
-            pixels_frame_buffer: None,
-            end_loop_reached: false,
-            key_states: [false; 16],
-            key_states_last_cycle: [false; 16],
-            waiting_for_key: false,
-            reg_waiting_for_key: 0,
-        };
-        // fill first 80 bytes of memory with out built-in hex digit sprites
-        emu.memory_space[..80].copy_from_slice(&BUILTIN_SPRITES);
-        start_timer_thread(&emu);
-        emu
+        Emulator::new()
     }
 
-    pub fn load_program(&mut self, file_name: &str) -> usize {
-        let mut rom_file = File::open(file_name).unwrap();
-        rom_file.read(&mut self.memory_space[0x200..]).unwrap()
+    fn load_fonts(&mut self) {
+        self.memory_space[SMALL_FONT_START..SMALL_FONT_START + BUILTIN_SPRITES.len()]
+            .copy_from_slice(&BUILTIN_SPRITES);
+        self.memory_space[BIG_FONT_START..BIG_FONT_START + BUILTIN_BIG_SPRITES.len()]
+            .copy_from_slice(&BUILTIN_BIG_SPRITES);
     }
 
-    pub fn pixels_render(&mut self) {
-        if let Some(p) = &mut self.pixels_frame_buffer {
-            p.render().unwrap()
+    pub fn load_program(&mut self, file_name: &str) -> Result<usize, EmulatorError> {
+        let mut rom = Vec::new();
+        File::open(file_name)?.read_to_end(&mut rom)?;
+        self.load_rom(&rom)
+    }
+
+    // loads a program already in memory, eg one from the assembler. Memory is sized for the
+    // quirks here, as they're set after the emulator's made
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<usize, EmulatorError> {
+        self.memory_space.resize(self.quirks.memory_size(), 0);
+        let max = self.memory_space.len() - PROGRAM_START;
+        if rom.len() > max {
+            return Err(EmulatorError::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
         }
+        self.memory_space[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+        Ok(rom.len())
     }
 
-    pub fn pixels_surface_resize(&mut self, size: PhysicalSize<u32>) {
-        if let Some(p) = &mut self.pixels_frame_buffer {
-            p.resize_surface(size.width, size.height);
+    // captures the whole machine, see SaveState for what that covers
+    pub fn snapshot(&self) -> SaveState {
+        SaveState {
+            registers: self.registers,
+            address_register: self.address_register,
+            memory_space: self.memory_space.clone(),
+            program_counter: self.program_counter,
+            subroutine_return_pointers: self.subroutine_return_pointers.clone(),
+            timer_counter: self.timer_counter.load(Ordering::Relaxed),
+            sound_counter: self.sound_counter.load(Ordering::Relaxed),
+            audio_pattern: self.audio_state.pattern(),
+            audio_pattern_loaded: self.audio_state.is_pattern_loaded(),
+            pitch: self.audio_state.pitch(),
+            display: self.display.clone(),
+            quirks: self.quirks,
+            rng: self.rng.clone(),
+            rpl_flags: self.rpl_flags,
+            end_loop_reached: self.end_loop_reached,
+            key_states_last_cycle: self.key_states_last_cycle,
+            waiting_for_key: self.waiting_for_key,
+            reg_waiting_for_key: self.reg_waiting_for_key,
+            frame_count: self.frame_count,
+            frame_instruction_count: self.frame_instruction_count,
         }
     }
 
-    pub fn update_time_counters(&mut self) {}
+    pub fn restore(&mut self, state: SaveState) {
+        self.registers = state.registers;
+        self.address_register = state.address_register;
+        self.memory_space = state.memory_space;
+        self.program_counter = state.program_counter;
+        self.subroutine_return_pointers = state.subroutine_return_pointers;
+        // the counters are shared with the timer thread and audio output, so they're
+        // written through rather than replaced
+        self.timer_counter
+            .store(state.timer_counter, Ordering::Relaxed);
+        self.sound_counter
+            .store(state.sound_counter, Ordering::Relaxed);
+        self.audio_state.set_pattern(&state.audio_pattern);
+        self.audio_state
+            .set_pattern_loaded(state.audio_pattern_loaded);
+        self.audio_state.set_pitch(state.pitch);
+        self.display = state.display;
+        self.quirks = state.quirks;
+        self.rng = state.rng;
+        self.rpl_flags = state.rpl_flags;
+        self.end_loop_reached = state.end_loop_reached;
+        self.key_states_last_cycle = state.key_states_last_cycle;
+        self.waiting_for_key = state.waiting_for_key;
+        self.reg_waiting_for_key = state.reg_waiting_for_key;
+        self.frame_count = state.frame_count;
+        self.frame_instruction_count = state.frame_instruction_count;
+    }
+
+    #[cfg(feature = "savestates")]
+    pub fn save_state(&self, file_name: &str) -> Result<(), EmulatorError> {
+        File::create(file_name)?.write_all(&self.snapshot().encode())?;
+        Ok(())
+    }
+
+    // writes the display to a PNG in the window's palette, scale times its native resolution
+    #[cfg(feature = "screenshots")]
+    pub fn save_screenshot(&self, file_name: &str, scale: usize) -> Result<(), EmulatorError> {
+        let mut file = BufWriter::new(File::create(file_name)?);
+        self.display.write_png(&mut file, scale)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    // the emulator is left untouched if the file can't be read or isn't a valid save state.
+    // The state's quirks replace ours, as the program was running with them (and memory's sized
+    // for them), so this returns the names of any that changed for the caller to warn about
+    #[cfg(feature = "savestates")]
+    pub fn load_state(&mut self, file_name: &str) -> Result<Vec<&'static str>, EmulatorError> {
+        let mut bytes = Vec::new();
+        File::open(file_name)?.read_to_end(&mut bytes)?;
+        let state = SaveState::decode(&bytes)?;
+        let changed = self.quirks.differences(&state.quirks);
+        self.restore(state);
+        Ok(changed)
+    }
 
-    pub fn execute_next_instruction(&mut self) -> InstructionResult {
+    // The COSMAC VIP random number generator reads the second page of the VIP's interpreter,
+    // this takes a dump of the interpreter (as it sat at 0x000-0x1FF) and copies that page into
+    // the same place in our memory. Our fonts live in the first page so they aren't touched
+    pub fn load_vip_interpreter_page(&mut self, interpreter: &[u8]) {
+        let page = interpreter.get(0x100..).unwrap_or(&[]);
+        let len = page.len().min(0x100);
+        self.memory_space[0x100..0x100 + len].copy_from_slice(&page[..len]);
+    }
+
+    // Without the interpreter page the VIP algorithm only ever adds zero, so CXNN gives the same
+    // few numbers over and over. Runners warn when the VIP rng is picked without one
+    pub fn has_vip_interpreter_page(&self) -> bool {
+        self.memory_space[0x100..0x200]
+            .iter()
+            .any(|byte| *byte != 0)
+    }
+
+    // FX0A is waiting for a key, each cycle spent waiting executes nothing
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+
+    pub fn set_timer_mode(&mut self, mode: TimerMode) {
+        if mode == self.timer_mode {
+            return;
+        }
+        self.timer_mode = mode;
+        match mode {
+            TimerMode::FreeRunning => {
+                self.timer_thread_running = Some(start_timer_thread(self));
+            }
+            TimerMode::Emulated => self.stop_timer_thread(),
+        }
+    }
+
+    fn stop_timer_thread(&mut self) {
+        if let Some(running) = self.timer_thread_running.take() {
+            running.store(false, Ordering::Release);
+        }
+    }
+
+    // counts both timers down by one, this is one 60hz tick
+    pub fn tick_timers(&mut self) {
+        decrement_counter(&self.timer_counter);
+        decrement_counter(&self.sound_counter);
+    }
+
+    // runs instructions until the current emulated 60hz frame is over, or a watchpoint is hit
+    pub fn run_frame(&mut self) -> Result<InstructionResult, EmulatorError> {
+        let frame = self.frame_count;
+        let mut redraw_requested = false;
+        while self.frame_count == frame {
+            match self.execute_next_instruction()? {
+                InstructionResult::Terminated => return Ok(InstructionResult::Terminated),
+                InstructionResult::RedrawRequested => redraw_requested = true,
+                InstructionResult::Working => (),
+                // stop straight away so the caller sees the state right after the access (or
+                // right before an execute), calling run_frame again carries on with the frame
+                hit => return Ok(hit),
+            }
+        }
+
+        if redraw_requested {
+            Ok(InstructionResult::RedrawRequested)
+        } else {
+            Ok(InstructionResult::Working)
+        }
+    }
+
+    pub fn execute_next_instruction(&mut self) -> Result<InstructionResult, EmulatorError> {
         let pc = self.program_counter as usize;
+        if pc + 1 >= self.memory_space.len() {
+            return Err(EmulatorError::MemoryOutOfBounds { addr: pc + 1 });
+        }
         let left_byte = self.memory_space[pc];
         let right_byte = self.memory_space[pc + 1];
         let opcode: Opcode = (left_byte, right_byte).into();
-        if !self.end_loop_reached {
-            // print!("{:02x?}{:02x?}, ", opcode.left_byte, opcode.right_byte,);
+        // an instruction waiting on a key is fetched again every cycle, but it's only run once.
+        // Execute watchpoints stop before the instruction runs, like a breakpoint would
+        if !self.waiting_for_key && self.resuming_from_watchpoint != Some(pc as u16) {
+            self.watch(pc, Access::Execute, left_byte, left_byte);
+            if !self.watchpoint_hits.is_empty() {
+                self.resuming_from_watchpoint = Some(pc as u16);
+                let hits = std::mem::take(&mut self.watchpoint_hits);
+                return Ok(InstructionResult::WatchpointHit(hits));
+            }
         }
+        self.resuming_from_watchpoint = None;
+
+        let cycle = self.cycle_count;
+        let traced = !self.waiting_for_key
+            && match &self.tracer {
+                Some(tracer) => tracer.filter.includes(cycle, pc as u16),
+                None => false,
+            };
+        let registers_before = self.registers;
+
+        let result = self.execute_instruction(opcode);
+
+        if traced {
+            let changed_registers = (0..16)
+                .filter(|i| self.registers[*i] != registers_before[*i])
+                .map(|i| (i as u8, self.registers[i]))
+                .collect();
+            let entry = TraceEntry {
+                cycle,
+                pc: pc as u16,
+                opcode: opcode.full_opcode,
+                changed_registers,
+                address_register: self.address_register,
+                delay_timer: self.timer_counter.load(Ordering::Relaxed),
+                sound_timer: self.sound_counter.load(Ordering::Relaxed),
+            };
+            if let Some(tracer) = &mut self.tracer {
+                tracer.record(&entry)?;
+            }
+        }
+        result
+    }
 
-        self.execute_instruction(opcode)
+    pub fn execute_instruction(
+        &mut self,
+        opcode: Opcode,
+    ) -> Result<InstructionResult, EmulatorError> {
+        let mut result = self.step(opcode);
+        let hits = std::mem::take(&mut self.watchpoint_hits);
+        if !hits.is_empty() {
+            result = match result {
+                Ok(InstructionResult::Terminated) | Err(_) => result,
+                Ok(_) => Ok(InstructionResult::WatchpointHit(hits)),
+            };
+        }
+
+        // waiting for a key still uses up an instruction's worth of time
+        self.cycle_count += 1;
+        self.frame_instruction_count += 1;
+        if self.frame_instruction_count >= self.instructions_per_frame {
+            self.frame_instruction_count = 0;
+            self.frame_count += 1;
+            if self.timer_mode == TimerMode::Emulated {
+                self.tick_timers();
+            }
+        }
+
+        result
     }
 
-    pub fn execute_instruction(&mut self, opcode: Opcode) -> InstructionResult {
+    fn step(&mut self, opcode: Opcode) -> Result<InstructionResult, EmulatorError> {
         if self.waiting_for_key {
             if self.check_for_pressed_keys(self.reg_waiting_for_key) {
                 self.waiting_for_key = false;
@@ -234,59 +593,62 @@ impl Emulator {
                 //we've finished the wait_for_key_and_store instruction,
                 //so we need to advance to program counter and return
                 //otherwise the instruction double fires
-                if self.program_counter + 2 < self.memory_space.len() as u16 - 1 {
-                    self.program_counter += 2;
-                } else {
-                    panic!("Program counter exceeded memory bounds");
-                }
+                self.advance_program_counter(2)?;
             } else {
                 self.key_states_last_cycle.copy_from_slice(&self.key_states);
             }
-            return InstructionResult::Working;
+            return Ok(InstructionResult::Working);
         }
 
         let opcode_result = self.process_opcode(opcode);
 
         match opcode_result {
-            OpcodeResult::Terminate => {
-                println!("Terminating");
-                return InstructionResult::Terminated;
-            }
+            OpcodeResult::Terminate => return Ok(InstructionResult::Terminated),
             OpcodeResult::Jump(target) => {
-                if target < self.memory_space.len() as u16 - 1 {
+                if (target as usize) < self.memory_space.len() - 1 {
                     self.program_counter = target;
                 } else {
-                    panic!("Jump instruction attempted to access out of bounds memory");
+                    return Err(EmulatorError::MemoryOutOfBounds {
+                        addr: target as usize,
+                    });
                 }
             }
             OpcodeResult::SkipNext => {
-                if self.program_counter + 4 < self.memory_space.len() as u16 - 1 {
-                    self.program_counter += 4;
+                // the 4 byte XO-CHIP long load has to be skipped over in one go
+                let next = self.program_counter as usize + 2;
+                if self.quirks.xochip_memory
+                    && self.memory_space.get(next..next + 2) == Some(&[0xF0, 0x00])
+                {
+                    self.advance_program_counter(6)?
                 } else {
-                    panic!("Skip instruction attempted to access out of bounds memory");
+                    self.advance_program_counter(4)?
                 }
             }
             OpcodeResult::Malformed => {
-                panic!(
-                    "Malformed opcode 0x{:#06x}{:#06x}",
-                    opcode.left_byte, opcode.right_byte
-                );
+                return Err(EmulatorError::MalformedOpcode {
+                    addr: self.program_counter,
+                    opcode: opcode.full_opcode,
+                });
             }
-            OpcodeResult::Continue => {
-                if self.program_counter + 2 < self.memory_space.len() as u16 - 1 {
-                    self.program_counter += 2;
-                } else {
-                    panic!("Program counter exceeded memory bounds");
-                }
+            OpcodeResult::StackUnderflow => {
+                return Err(EmulatorError::StackUnderflow {
+                    addr: self.program_counter,
+                });
+            }
+            OpcodeResult::StackOverflow => {
+                return Err(EmulatorError::StackOverflow {
+                    addr: self.program_counter,
+                });
+            }
+            OpcodeResult::OutOfBounds(addr) => {
+                return Err(EmulatorError::MemoryOutOfBounds { addr });
             }
+            OpcodeResult::Continue => self.advance_program_counter(2)?,
+            OpcodeResult::ContinueLong => self.advance_program_counter(4)?,
             OpcodeResult::RequestRedraw => {
-                if self.program_counter + 2 < self.memory_space.len() as u16 - 1 {
-                    self.program_counter += 2;
-                } else {
-                    panic!("Program counter exceeded memory bounds");
-                }
+                self.advance_program_counter(2)?;
                 self.key_states_last_cycle.copy_from_slice(&self.key_states);
-                return InstructionResult::RedrawRequested;
+                return Ok(InstructionResult::RedrawRequested);
             }
             OpcodeResult::WaitingForKey(reg) => {
                 self.waiting_for_key = true;
@@ -295,7 +657,47 @@ impl Emulator {
         }
 
         self.key_states_last_cycle.copy_from_slice(&self.key_states);
-        InstructionResult::Working
+        Ok(InstructionResult::Working)
+    }
+
+    // Every memory access made by an instruction goes through read_memory and write_memory so
+    // watchpoints can see it. Callers check the address is in bounds first
+    fn read_memory(&mut self, addr: usize) -> u8 {
+        let value = self.memory_space[addr];
+        self.watch(addr, Access::Read, value, value);
+        value
+    }
+
+    fn write_memory(&mut self, addr: usize, value: u8) {
+        let old_value = self.memory_space[addr];
+        self.memory_space[addr] = value;
+        self.watch(addr, Access::Write, old_value, value);
+    }
+
+    fn watch(&mut self, addr: usize, access: Access, old_value: u8, new_value: u8) {
+        if self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.matches(addr, access))
+        {
+            self.watchpoint_hits.push(WatchpointHit {
+                access,
+                addr: addr as u16,
+                old_value,
+                new_value,
+                pc: self.program_counter,
+            });
+        }
+    }
+
+    fn advance_program_counter(&mut self, amount: u16) -> Result<(), EmulatorError> {
+        let next = self.program_counter as usize + amount as usize;
+        if next < self.memory_space.len() - 1 {
+            self.program_counter = next as u16;
+            Ok(())
+        } else {
+            Err(EmulatorError::MemoryOutOfBounds { addr: next })
+        }
     }
 
     // returns desired program counter location
@@ -310,6 +712,16 @@ impl Emulator {
                 match opcode.full_opcode {
                     0x00E0 => self.clear_screen(),
                     0x00EE => self.return_from_subroutine(),
+                    0x00FB => self.scroll_right(),
+                    0x00FC => self.scroll_left(),
+                    0x00FD => {
+                        // 0x00FD Exit the interpreter (SUPER-CHIP)
+                        OpcodeResult::Terminate
+                    }
+                    0x00FE => self.set_hires(false),
+                    0x00FF => self.set_hires(true),
+                    0x00C0..=0x00CF => self.scroll_down(opcode),
+                    0x00D0..=0x00DF => self.scroll_up(opcode),
                     0x0000 => {
                         // 0x0000 EOF
                         OpcodeResult::Terminate
@@ -325,7 +737,12 @@ impl Emulator {
             0x2 => self.call_subroutine(opcode),
             0x3 => self.skip_next_if_x_reg_equal(opcode),
             0x4 => self.skip_next_if_x_reg_not_equal(opcode),
-            0x5 => self.skip_next_if_regs_equal(opcode),
+            0x5 => match opcode.first_nibble {
+                0x0 => self.skip_next_if_regs_equal(opcode),
+                0x2 => self.store_register_range_to_address(opcode),
+                0x3 => self.load_register_range_from_address(opcode),
+                _ => OpcodeResult::Malformed,
+            },
             0x6 => self.x_reg_store_value(opcode),
             0x7 => self.x_reg_add_value(opcode),
             0x8 => match opcode.first_nibble {
@@ -351,15 +768,22 @@ impl Emulator {
                 _ => OpcodeResult::Malformed,
             },
             0xF => match opcode.right_byte {
+                0x00 if opcode.third_nibble == 0 => self.store_long_address(),
+                0x01 => self.select_planes(opcode),
+                0x02 if opcode.third_nibble == 0 => self.load_audio_pattern(),
                 0x07 => self.load_delay_counter_value(opcode),
                 0x0A => self.wait_for_key_and_store(opcode),
                 0x15 => self.set_delay_counter(opcode),
                 0x18 => self.set_sound_counter(opcode),
                 0x1E => self.add_to_address_reg(opcode),
                 0x29 => self.lookup_sprite_for_digit(opcode),
+                0x30 => self.lookup_big_sprite_for_digit(opcode),
                 0x33 => self.store_bcd_at_address(opcode),
+                0x3A => self.set_audio_pitch(opcode),
                 0x55 => self.store_registers_to_address(opcode),
                 0x65 => self.load_registers_from_address(opcode),
+                0x75 => self.store_registers_to_flags(opcode),
+                0x85 => self.load_registers_from_flags(opcode),
 
                 _ => OpcodeResult::Malformed,
             },
@@ -369,19 +793,17 @@ impl Emulator {
 
     fn return_from_subroutine(&mut self) -> OpcodeResult {
         // 0x00EE Return from a subroutine
-        let return_address = self.subroutine_return_pointers.pop().unwrap_or_else(|| {
-            println!("could not return from subroutine, no return pointers");
-            0_u16
-        });
-        if return_address != 0 {
-            OpcodeResult::Jump(return_address)
-        } else {
-            OpcodeResult::Malformed
+        match self.subroutine_return_pointers.pop() {
+            Some(return_address) => OpcodeResult::Jump(return_address),
+            None => OpcodeResult::StackUnderflow,
         }
     }
 
     fn call_subroutine(&mut self, opcode: Opcode) -> OpcodeResult {
         // 0x2NNN Execute subroutine starting at address NNN
+        if self.subroutine_return_pointers.len() >= MAX_STACK_DEPTH {
+            return OpcodeResult::StackOverflow;
+        }
         // +2 so that we don't loop on return
         self.subroutine_return_pointers
             .push(self.program_counter + 2);
@@ -418,6 +840,48 @@ impl Emulator {
         }
     }
 
+    // the registers from VX to VY inclusive, in that order, so backwards if X > Y
+    fn register_range(opcode: Opcode) -> Vec<usize> {
+        let x = opcode.third_nibble as usize;
+        let y = opcode.second_nibble as usize;
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    fn store_register_range_to_address(&mut self, opcode: Opcode) -> OpcodeResult {
+        // 0x5XY2 Store VX to VY (including both) in memory starting at address I, I is left
+        // unmodified (XO-CHIP)
+        let registers = Emulator::register_range(opcode);
+        let last_address = self.address_register as usize + registers.len() - 1;
+        if last_address >= self.memory_space.len() {
+            return OpcodeResult::OutOfBounds(last_address);
+        }
+        for (offset, reg) in registers.iter().enumerate() {
+            self.write_memory(
+                self.address_register as usize + offset,
+                self.registers[*reg],
+            );
+        }
+        OpcodeResult::Continue
+    }
+
+    fn load_register_range_from_address(&mut self, opcode: Opcode) -> OpcodeResult {
+        // 0x5XY3 Fill VX to VY (including both) from memory starting at address I, I is left
+        // unmodified (XO-CHIP)
+        let registers = Emulator::register_range(opcode);
+        let last_address = self.address_register as usize + registers.len() - 1;
+        if last_address >= self.memory_space.len() {
+            return OpcodeResult::OutOfBounds(last_address);
+        }
+        for (offset, reg) in registers.iter().enumerate() {
+            self.registers[*reg] = self.read_memory(self.address_register as usize + offset);
+        }
+        OpcodeResult::Continue
+    }
+
     fn x_reg_store_value(&mut self, opcode: Opcode) -> OpcodeResult {
         //0x6XNN store number NN in register VX
         self.registers[opcode.third_nibble as usize] = opcode.right_byte;
@@ -443,6 +907,9 @@ impl Emulator {
         //0x8XY1 Set VX to VX OR VY
         self.registers[opcode.third_nibble as usize] |=
             self.registers[opcode.second_nibble as usize];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
         OpcodeResult::Continue
     }
 
@@ -450,6 +917,9 @@ impl Emulator {
         //0x8XY2 Set VX to VX AND VY
         self.registers[opcode.third_nibble as usize] &=
             self.registers[opcode.second_nibble as usize];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
         OpcodeResult::Continue
     }
 
@@ -457,6 +927,9 @@ impl Emulator {
         //0x8XY3 Set VX to VX XOR VY
         self.registers[opcode.third_nibble as usize] ^=
             self.registers[opcode.second_nibble as usize];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
         OpcodeResult::Continue
     }
 
@@ -487,9 +960,10 @@ impl Emulator {
     fn shift_register_right(&mut self, opcode: Opcode) -> OpcodeResult {
         //0x8XY6
         //Stores the least significant bit of VX in VF and then shifts VX to the right by 1.[b]
-        let val = self.registers[opcode.third_nibble as usize];
+        //(with the shift quirk it's VY that gets shifted, and the result stored in VX)
+        let val = self.shift_source(opcode);
+        self.registers[opcode.third_nibble as usize] = val >> 1;
         self.registers[0xF] = val & 0x01;
-        self.registers[opcode.third_nibble as usize] >>= 1;
         OpcodeResult::Continue
     }
 
@@ -507,12 +981,20 @@ impl Emulator {
     fn shift_register_left(&mut self, opcode: Opcode) -> OpcodeResult {
         //0x8XYE
         //Stores the most significant bit of VX in VF and then shifts VX to the left by 1
-        let val = self.registers[opcode.third_nibble as usize];
+        let val = self.shift_source(opcode);
+        self.registers[opcode.third_nibble as usize] = val << 1;
         self.registers[0xF] = val >> 7;
-        self.registers[opcode.third_nibble as usize] <<= 1;
         OpcodeResult::Continue
     }
 
+    fn shift_source(&self, opcode: Opcode) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[opcode.second_nibble as usize]
+        } else {
+            self.registers[opcode.third_nibble as usize]
+        }
+    }
+
     fn skip_next_if_not_equal(&mut self, opcode: Opcode) -> OpcodeResult {
         // 0x9XY0 Skip the following instruction if the value of register VX is NOT equal to the
         // value of register VY
@@ -525,6 +1007,18 @@ impl Emulator {
         }
     }
 
+    fn store_long_address(&mut self) -> OpcodeResult {
+        // 0xF000 0xNNNN Store the 16 bit address NNNN in register I (XO-CHIP)
+        // this is the only 4 byte instruction, NNNN is the 2 bytes after the opcode
+        let addr_start = self.program_counter as usize + 2;
+        if addr_start + 1 >= self.memory_space.len() {
+            return OpcodeResult::OutOfBounds(addr_start + 1);
+        }
+        self.address_register =
+            (self.read_memory(addr_start) as u16) << 8 | self.read_memory(addr_start + 1) as u16;
+        OpcodeResult::ContinueLong
+    }
+
     fn store_address(&mut self, opcode: Opcode) -> OpcodeResult {
         // 0xANNN Store memory address NNN in register I (address register)
         // extract address from opcode
@@ -534,16 +1028,19 @@ impl Emulator {
 
     fn jump_with_offset(&mut self, opcode: Opcode) -> OpcodeResult {
         //0xBNNN Jump to address NNN + V0
-        // let mut jump_target = jump_to_opcode_nnn();
+        //(with the jump quirk this is 0xBXNN, jump to address XNN + VX)
         let mut jump_addr = opcode.full_opcode & 0x0FFF;
-        jump_addr += self.registers[0] as u16;
+        if self.quirks.jump_uses_vx {
+            jump_addr += self.registers[opcode.third_nibble as usize] as u16;
+        } else {
+            jump_addr += self.registers[0] as u16;
+        }
         OpcodeResult::Jump(jump_addr)
     }
 
     fn generate_rnd_num(&mut self, opcode: Opcode) -> OpcodeResult {
         // 0xCXNN Set VX to a random number with a mask of NN
-        let mut rand_val: u8 = random();
-        rand_val &= opcode.right_byte;
+        let rand_val = self.rng.next_byte(&self.memory_space) & opcode.right_byte;
         self.registers[opcode.third_nibble as usize] = rand_val;
         OpcodeResult::Continue
     }
@@ -572,6 +1069,30 @@ impl Emulator {
         OpcodeResult::Continue
     }
 
+    fn load_audio_pattern(&mut self) -> OpcodeResult {
+        //0xF002 Load the 16 byte audio pattern starting at address I (XO-CHIP)
+        let start = self.address_register as usize;
+        let end = start + AUDIO_PATTERN_LEN;
+        if end > self.memory_space.len() {
+            return OpcodeResult::OutOfBounds(end - 1);
+        }
+        let mut pattern = [0_u8; AUDIO_PATTERN_LEN];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.read_memory(start + offset);
+        }
+        self.audio_state.set_pattern(&pattern);
+        self.audio_state.set_pattern_loaded(true);
+        OpcodeResult::Continue
+    }
+
+    fn set_audio_pitch(&mut self, opcode: Opcode) -> OpcodeResult {
+        //0xFX3A Set the audio pattern playback rate to 4000*2^((VX-64)/48) bits per second
+        //(XO-CHIP)
+        self.audio_state
+            .set_pitch(self.registers[opcode.third_nibble as usize]);
+        OpcodeResult::Continue
+    }
+
     fn add_to_address_reg(&mut self, opcode: Opcode) -> OpcodeResult {
         //0xFX1E Add the value stored in register VX to register I
 
@@ -594,9 +1115,12 @@ impl Emulator {
         let ones = value;
 
         let base_address = self.address_register as usize;
-        self.memory_space[base_address] = hundreds;
-        self.memory_space[base_address + 1] = tens;
-        self.memory_space[base_address + 2] = ones;
+        if base_address + 2 >= self.memory_space.len() {
+            return OpcodeResult::OutOfBounds(base_address + 2);
+        }
+        self.write_memory(base_address, hundreds);
+        self.write_memory(base_address + 1, tens);
+        self.write_memory(base_address + 2, ones);
         OpcodeResult::Continue
     }
 
@@ -604,23 +1128,44 @@ impl Emulator {
         //0xFX55
         //Stores V0 to VX (including VX) in memory starting at address I. The offset from I is
         //increased by 1 for each value written, but I itself is left unmodified.
+        let last_address = self.address_register as usize + opcode.third_nibble as usize;
+        if last_address >= self.memory_space.len() {
+            return OpcodeResult::OutOfBounds(last_address);
+        }
         for reg_index in 0..(opcode.third_nibble + 1) {
             let write_address = self.address_register + reg_index as u16;
-            self.memory_space[write_address as usize] = self.registers[reg_index as usize];
+            self.write_memory(write_address as usize, self.registers[reg_index as usize]);
         }
-        // self.address_register += opcode.third_nibble as u16 + 1;
+        self.increment_i_after_load_store(opcode.third_nibble);
         OpcodeResult::Continue
     }
 
+    // FX55 and FX65 move I on afterwards under the load_store_increments_i quirks
+    fn increment_i_after_load_store(&mut self, last_register: u8) {
+        if !self.quirks.load_store_increments_i {
+            return;
+        }
+        let increment = if self.quirks.load_store_increments_i_by_x {
+            last_register as u16
+        } else {
+            last_register as u16 + 1
+        };
+        self.address_register = self.address_register.wrapping_add(increment);
+    }
+
     fn load_registers_from_address(&mut self, opcode: Opcode) -> OpcodeResult {
         //0xFX65
         //Fills V0 to VX (including VX) with values from memory starting at address I. The offset
         //from I is increased by 1 for each value written, but I itself is left unmodified.
+        let last_address = self.address_register as usize + opcode.third_nibble as usize;
+        if last_address >= self.memory_space.len() {
+            return OpcodeResult::OutOfBounds(last_address);
+        }
         for reg_index in 0..(opcode.third_nibble + 1) {
             let read_address = self.address_register + reg_index as u16;
-            self.registers[reg_index as usize] = self.memory_space[read_address as usize];
+            self.registers[reg_index as usize] = self.read_memory(read_address as usize);
         }
-        // self.address_register += opcode.third_nibble as u16 + 1;
+        self.increment_i_after_load_store(opcode.third_nibble);
         OpcodeResult::Continue
     }
 
@@ -636,18 +1181,41 @@ impl Emulator {
         OpcodeResult::Continue
     }
 
+    fn lookup_big_sprite_for_digit(&mut self, opcode: Opcode) -> OpcodeResult {
+        //0xFX30 Set I to the memory address of the 8x10 sprite data corresponding to the
+        // hexadecimal digit stored in register VX (SUPER-CHIP)
+        let digit = self.registers[opcode.third_nibble as usize] as u16 & 0xF;
+        self.address_register = BIG_FONT_START as u16 + digit * 10;
+
+        OpcodeResult::Continue
+    }
+
+    fn store_registers_to_flags(&mut self, opcode: Opcode) -> OpcodeResult {
+        //0xFX75 Store V0 to VX (including VX) in the RPL user flags (SUPER-CHIP)
+        //SUPER-CHIP only had 8 flags, we allow all 16 like XO-CHIP does
+        let count = opcode.third_nibble as usize + 1;
+        self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+        OpcodeResult::Continue
+    }
+
+    fn load_registers_from_flags(&mut self, opcode: Opcode) -> OpcodeResult {
+        //0xFX85 Fill V0 to VX (including VX) from the RPL user flags (SUPER-CHIP)
+        let count = opcode.third_nibble as usize + 1;
+        self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+        OpcodeResult::Continue
+    }
+
     fn wait_for_key_and_store(&mut self, opcode: Opcode) -> OpcodeResult {
         //0xFX0A Wait for a keypress and store the result in register VX
-        println!("hit the wait and store");
         if self.check_for_pressed_keys(opcode.third_nibble) {
             return OpcodeResult::Continue;
         }
 
-        OpcodeResult::WaitingForKey(opcode.third_nibble as u8)
+        OpcodeResult::WaitingForKey(opcode.third_nibble)
     }
 
     fn check_for_pressed_keys(&mut self, reg: u8) -> bool {
-        for i in 0..0xF_usize {
+        for i in 0..=0xF_usize {
             if self.key_states[i] && !self.key_states_last_cycle[i] {
                 self.registers[reg as usize] = i as u8;
                 return true;
@@ -660,7 +1228,8 @@ impl Emulator {
         // 0xEX9E Skip the following instruction if the key corresponding to the hex value
         // currently stored in register VX is pressed
 
-        let key = self.registers[opcode.third_nibble as usize] as usize;
+        // only the low nibble selects a key, like on the original hardware
+        let key = (self.registers[opcode.third_nibble as usize] & 0xF) as usize;
         if self.key_states[key] {
             OpcodeResult::SkipNext
         } else {
@@ -671,7 +1240,7 @@ impl Emulator {
     fn skip_next_if_key_is_not_down(&mut self, opcode: Opcode) -> OpcodeResult {
         // 0xEXA1 Skip the following instruction if the key corresponding to the hex value
         // currently stored in register VX is not pressed
-        let key = self.registers[opcode.third_nibble as usize] as usize;
+        let key = (self.registers[opcode.third_nibble as usize] & 0xF) as usize;
         if self.key_states[key] {
             OpcodeResult::Continue
         } else {
@@ -698,39 +1267,62 @@ impl Emulator {
         //instruction 8xy3 for more information on XOR, and section 2.4, Display, for more
         //information on the Chip-8 screen and sprites.
 
-        //todo wrap the sprite if it exceeds bounds in any direction
+        //The starting position always wraps, but whether the rest of the sprite wraps or is cut
+        //off at the edges depends on the clipping quirk
 
-        if let Some(buffer) = &mut self.pixels_frame_buffer {
-            let frame = buffer.get_frame();
+        //SUPER-CHIP added 0xDXY0, which draws a 16x16 sprite from 32 bytes (two bytes per row)
+        //we draw those the same way in both hires and lores
 
-            let x_origin = self.registers[opcode.third_nibble as usize] as usize;
-            let y_origin = self.registers[opcode.second_nibble as usize] as usize;
-            // println!("x: {} y: {}", x_origin, y_origin);
-            self.registers[0xF] = 0x0;
+        let width = self.display.width();
+        let height = self.display.height();
+        let x_origin = self.registers[opcode.third_nibble as usize] as usize % width;
+        let y_origin = self.registers[opcode.second_nibble as usize] as usize % height;
+        // println!("x: {} y: {}", x_origin, y_origin);
+        self.registers[0xF] = 0x0;
 
-            let start = self.address_register as usize;
-            let end = start + opcode.first_nibble as usize;
-            let sprite_slice = &self.memory_space[start..end];
+        let (sprite_width, rows) = if opcode.first_nibble == 0 {
+            (16, 16)
+        } else {
+            (8, opcode.first_nibble as usize)
+        };
+        let bytes_per_row = sprite_width / 8;
+        let sprite_len = rows * bytes_per_row;
+
+        //XO-CHIP draws the sprite once into each selected plane, when both are selected the
+        //data for the second plane follows straight after the first
+        let planes = [0b01_u8, 0b10]
+            .iter()
+            .filter(|plane| self.display.selected_planes() & **plane != 0)
+            .copied()
+            .collect::<Vec<u8>>();
+
+        let start = self.address_register as usize;
+        let end = start + sprite_len * planes.len();
+        if end > self.memory_space.len() {
+            return OpcodeResult::OutOfBounds(end - 1);
+        }
+
+        for (plane_index, plane) in planes.iter().enumerate() {
+            let plane_start = start + plane_index * sprite_len;
+            for row in 0..rows {
+                let row_start = plane_start + row * bytes_per_row;
+                let mut row_bits = 0_u16;
+                for addr in row_start..row_start + bytes_per_row {
+                    row_bits = (row_bits << 8) | self.read_memory(addr) as u16;
+                }
 
-            for (row, byte) in sprite_slice.iter().enumerate() {
-                for bit_index in 0..8u8 {
-                    let bit = byte & (1 << bit_index);
-                    let pixel_ind =
-                        ((x_origin + (7 - bit_index) as usize + ((y_origin + row) * 64)) * 4)
-                            % frame.len();
-                    // if pixel_ind > frame.len() - 1 {
-                    //     continue;
-                    // }
-                    let p = &mut frame[pixel_ind..pixel_ind + 4];
+                for column in 0..sprite_width {
+                    let bit = row_bits & (1 << (sprite_width - 1 - column));
 
-                    // we XOR the sprite onto the frame
+                    // we XOR the sprite onto the display
                     if bit != 0 {
-                        //set the pixel
-                        if p == SET_COLOUR {
+                        let x = x_origin + column;
+                        let y = y_origin + row;
+                        if self.quirks.clip_sprites && (x >= width || y >= height) {
+                            continue;
+                        }
+                        if self.display.flip_pixel(x % width, y % height, *plane) {
                             self.registers[0xF] = 0x1;
-                            p.copy_from_slice(&UNSET_COLOUR);
-                        } else {
-                            p.copy_from_slice(&SET_COLOUR);
                         }
                     }
                 }
@@ -743,9 +1335,9 @@ impl Emulator {
     fn jump(&mut self, opcode: Opcode) -> OpcodeResult {
         //0x1NNN Jump to address NNN
         let target = opcode.full_opcode & 0x0FFF;
-        if target == self.program_counter && !self.end_loop_reached {
+        // a jump to itself is how most roms end, front ends can check end_loop_reached
+        if target == self.program_counter {
             self.end_loop_reached = true;
-            println!("End of program loop reached");
         }
         OpcodeResult::Jump(target)
     }
@@ -753,45 +1345,85 @@ impl Emulator {
     fn clear_screen(&mut self) -> OpcodeResult {
         //0x00E0 Clear the screen
 
-        if let Some(p_buf) = &mut self.pixels_frame_buffer {
-            let frame = p_buf.get_frame();
-            for rgba_chunk in frame.chunks_exact_mut(4) {
-                // rgba
-                rgba_chunk.copy_from_slice(&UNSET_COLOUR);
-            }
-        }
+        self.display.clear();
+        OpcodeResult::RequestRedraw
+    }
+
+    fn scroll_down(&mut self, opcode: Opcode) -> OpcodeResult {
+        //0x00CN Scroll the display down by N pixels (SUPER-CHIP)
+        self.display.scroll_down(opcode.first_nibble as usize);
+        OpcodeResult::RequestRedraw
+    }
+
+    fn scroll_up(&mut self, opcode: Opcode) -> OpcodeResult {
+        //0x00DN Scroll the display up by N pixels (XO-CHIP)
+        self.display.scroll_up(opcode.first_nibble as usize);
+        OpcodeResult::RequestRedraw
+    }
+
+    fn select_planes(&mut self, opcode: Opcode) -> OpcodeResult {
+        //0xFN01 Select the bitplanes (as a bitmask N) that drawing, clearing and scrolling
+        //affect (XO-CHIP)
+        self.display.select_planes(opcode.third_nibble);
+        OpcodeResult::Continue
+    }
+
+    fn scroll_right(&mut self) -> OpcodeResult {
+        //0x00FB Scroll the display right by 4 pixels (SUPER-CHIP)
+        self.display.scroll_right(4);
+        OpcodeResult::RequestRedraw
+    }
+
+    fn scroll_left(&mut self) -> OpcodeResult {
+        //0x00FC Scroll the display left by 4 pixels (SUPER-CHIP)
+        self.display.scroll_left(4);
+        OpcodeResult::RequestRedraw
+    }
+
+    fn set_hires(&mut self, hires: bool) -> OpcodeResult {
+        //0x00FE Switch to lores 64x32 mode (SUPER-CHIP)
+        //0x00FF Switch to hires 128x64 mode (SUPER-CHIP)
+        self.display.set_hires(hires);
         OpcodeResult::RequestRedraw
     }
 }
 
-fn start_timer_thread(emu: &Emulator) {
-    #[allow(unused_must_use)]
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new()
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.stop_timer_thread();
+    }
+}
+
+fn decrement_counter(counter: &AtomicU8) {
+    // don't care if this errors, it just means the emulator set the number, so we'll
+    // just drop trying to decrement the value
+    // it is possible that it was set to a higher number, but also possible that it was set
+    // to zero, so we can't assume it's safe to decrement
+    let val = counter.load(Ordering::Acquire);
+    if val > 0 {
+        let _ = counter.compare_exchange(val, val - 1, Ordering::AcqRel, Ordering::Relaxed);
+    }
+}
+
+// the thread runs until the returned flag is cleared (done when the emulator is dropped)
+fn start_timer_thread(emu: &Emulator) -> Arc<AtomicBool> {
+    let running = Arc::new(AtomicBool::new(true));
+    let thread_running = Arc::clone(&running);
     let timer_counter = Arc::clone(&emu.timer_counter);
     let sound_counter = Arc::clone(&emu.sound_counter);
-    thread::spawn(move || loop {
-        // since we're sleep for 16 ms per cycle, this will very roughly approximate 60hz
-        let timer_val = timer_counter.load(Ordering::Acquire);
-        // don't care if this errors, it just means the emulator set the number, so we'll
-        // just drop trying to decrement the value
-        // it is possible that it was set to a higher number, but also possible that it was set
-        // to zero, so we can't assume it's safe to decrement
-        if timer_val > 0 {
-            let _ = timer_counter.compare_exchange(
-                timer_val,
-                timer_val - 1,
-                Ordering::AcqRel,
-                Ordering::Relaxed,
-            );
-        }
-        let sound_val = sound_counter.load(Ordering::Acquire);
-        if sound_val > 0 {
-            let _ = sound_counter.compare_exchange(
-                sound_val,
-                sound_val - 1,
-                Ordering::AcqRel,
-                Ordering::Relaxed,
-            );
+    thread::spawn(move || {
+        while thread_running.load(Ordering::Acquire) {
+            // since we're sleep for 16 ms per cycle, this will very roughly approximate 60hz
+            decrement_counter(&timer_counter);
+            decrement_counter(&sound_counter);
+            thread::sleep(Duration::from_millis(16));
         }
-        thread::sleep(Duration::from_millis(16));
     });
+    running
 }
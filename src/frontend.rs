@@ -0,0 +1,560 @@
+use std::io::{prelude::*, stdout};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{self, Instant, SystemTime, UNIX_EPOCH};
+
+use kira::audio_stream::AudioStream;
+use kira::manager::{AudioManager, AudioManagerSettings};
+use kira::mixer::TrackIndex;
+use kira::Frame;
+use pixels::{Pixels, SurfaceTexture};
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, KeyboardInput};
+use winit::{
+    event::{Event, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+
+use chip8_rust::audio::{AudioState, Beep, PatternPlayer};
+use chip8_rust::cli::{self, RecordingFiles};
+use chip8_rust::debugger::Debugger;
+use chip8_rust::display::Display;
+use chip8_rust::keymap::KeyMap;
+use chip8_rust::movie::Movie;
+use chip8_rust::recording::Recorder;
+use chip8_rust::rewind::Rewind;
+use chip8_rust::{Emulator, InstructionResult, TimerMode};
+
+use crate::{RecordingSettings, RunOptions, Settings};
+
+// one emulated frame, the timers run at 60hz
+const FRAME_DURATION: time::Duration = time::Duration::from_nanos(1_000_000_000 / 60);
+
+// runs the rom in a window, with the keyboard as the keypad, until the window's closed
+pub fn run(settings: Settings, options: RunOptions) {
+    // based on 4kb variant (hence 3215 bytes) (wait shouldn't it be 3583???)
+    // all memory accesses will be in big endian
+    // chip 8 is big endian!!!
+    // halt on bad memory access?
+
+    // planning on making these count down on another thread
+    // use a condition variable to wake the counting thread on counter set
+    // just use thread sleep to try and get the right 60hz freq
+
+    //1. load program into memory from file
+    //  if it exceeds size than halt with error
+    //2. begin running byte code
+    // (code is allowed to be self modifying (ie no write protection region))
+    // error on any address read/write below 0x200
+
+    let (event_loop, window, mut pixels) = init(settings.window_scale);
+    let mut emulator = Emulator::new();
+    let volume = settings.beep_volume;
+    let rom_path = settings.rom_path.clone();
+    let screenshot_scale = settings.screenshot_scale as usize;
+    let recording_settings = settings.recording;
+    // the rom's own [keys.roms."<name>"] overrides apply on top of the [keys] table
+    let rom_name = Path::new(&rom_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let key_map = match settings.keys.to_key_map(&rom_name) {
+        Ok(key_map) => key_map,
+        Err(e) => {
+            println!("Bad [keys] config, {}, using the default keys", e);
+            KeyMap::default()
+        }
+    };
+    // every host key held down, with its name, so a CHIP-8 key bound to several only lets go
+    // when the last of them does
+    let mut held_keys = Vec::new();
+    emulator.set_timer_mode(settings.timer_mode);
+    // print the seed we picked so the run can be reproduced with --seed or the config
+    let seed = settings.machine.seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        println!("Random seed {}", seed);
+        seed
+    });
+    if let Err(e) = settings.machine.configure(&mut emulator, seed) {
+        println!("{}", e);
+    }
+    if let Some(warning) = cli::vip_rng_warning(&emulator) {
+        println!("{}", warning);
+    }
+    if let Some(trace) = &settings.trace {
+        match trace.to_tracer() {
+            Ok(tracer) => {
+                println!("Tracing to {}", trace.file);
+                emulator.tracer = Some(tracer);
+            }
+            Err(e) => println!("Not tracing, {}", e),
+        }
+    }
+    // --play-movie <file> replays a movie in place of the keyboard, with the settings it was
+    // recorded with. --record-movie <file> records this session into one, saved on exit
+    let mut playing = options.play_movie.map(|path| {
+        let movie = cli::load_movie(&path, &rom_path).unwrap_or_else(|e| cli::fail(&e));
+        movie.apply(&mut emulator).unwrap_or_else(|e| cli::fail(&e));
+        println!("Playing {}, {} frames long", path, movie.frames);
+        movie
+    });
+    let record_movie_path = options.record_movie;
+    if record_movie_path.is_some() && settings.timer_mode == TimerMode::FreeRunning {
+        println!("Movies need emulated timers to replay exactly, switching to them");
+        emulator.set_timer_mode(TimerMode::Emulated);
+    }
+
+    let bytes_read = match emulator.load_program(&settings.rom_path) {
+        Ok(bytes_read) => bytes_read,
+        Err(e) => {
+            println!("Failed to load {}: {}", settings.rom_path, e);
+            process::exit(1);
+        }
+    };
+    println!("Loaded program, bytes {}", bytes_read);
+    let rom = std::fs::read(&settings.rom_path).unwrap_or_default();
+    // a movie that's playing is what the emulator was set up with
+    let machine = match &playing {
+        Some(movie) => &movie.machine,
+        None => &settings.machine,
+    };
+    let mut recording_movie = record_movie_path.map(|path| {
+        println!("Recording a movie to {}", path);
+        (Movie::new(&emulator, machine, &rom_name, &rom), path)
+    });
+    // loading a state or rewinding mid movie would knock it out of sync with its inputs
+    let movie_running = playing.is_some() || recording_movie.is_some();
+    if movie_running {
+        println!("Save states and rewinding are off while a movie records or plays");
+    }
+    window.request_redraw();
+
+    // the beep is synthesized on kira's audio thread, so its not
+    // affected by the emulation processing
+    let mut audio_manager = AudioManager::new(AudioManagerSettings::default()).unwrap();
+    audio_manager
+        .add_stream(
+            BeepStream {
+                sound_counter: Arc::clone(&emulator.sound_counter),
+                audio_state: Arc::clone(&emulator.audio_state),
+                player: PatternPlayer::with_beep(Arc::new(Beep::default_beep())),
+                volume: volume as f32,
+            },
+            TrackIndex::Main,
+        )
+        .unwrap();
+
+    // a snapshot is recorded every frame, holding backspace steps back through them
+    let mut rewind = Rewind::new(settings.rewind_seconds as usize * 60);
+    let mut rewinding = false;
+
+    // F9 starts and stops recording the emulated frames
+    let mut recorder: Option<Recorder> = None;
+
+    // with --debug the emulator starts paused and takes debugger commands from stdin.
+    // --breakpoints <file> loads breakpoints into the debugger, and without --debug runs until
+    // the first one is hit
+    let debug = options.debug;
+    let breakpoints_path = options.breakpoints;
+    let mut debugger = if debug || breakpoints_path.is_some() {
+        let mut debugger = Debugger::new();
+        if let Some(path) = breakpoints_path {
+            let loaded = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| debugger.load_breakpoints(&text));
+            match loaded {
+                Ok(count) => println!("Loaded {} breakpoints from {}", count, path),
+                Err(e) => {
+                    println!("Failed to load breakpoints from {}: {}", path, e);
+                    process::exit(1);
+                }
+            }
+        }
+        if debug {
+            println!("Debugging, paused before the first instruction. Type help for commands");
+            print!("(debug) ");
+        } else {
+            debugger.set_paused(false);
+        }
+        Some((debugger, spawn_stdin_reader()))
+    } else {
+        None
+    };
+
+    let mut next_frame = Instant::now() + FRAME_DURATION;
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        // the manager has to stay alive for the beep to keep playing
+        let _ = &audio_manager;
+
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                println!("The closed button was pressed; stopping");
+                *control_flow = ControlFlow::Exit
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => pixels.resize_surface(size.width, size.height),
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { input, .. },
+                ..
+            } => {
+                let (scancode, state) = (input.scancode, input.state);
+                if scancode == 0x001 {
+                    // escape hit
+                    println!("Escape key hit, closing");
+                    *control_flow = ControlFlow::Exit;
+                }
+                if state == ElementState::Pressed
+                    && !movie_running
+                    && handle_save_state_keys(scancode, &mut emulator, &rom_path)
+                {
+                    // the history leads up to where we were, not to the loaded state
+                    rewind.clear();
+                    window.request_redraw();
+                }
+                if scancode == 0x058 && state == ElementState::Pressed {
+                    // F12 hit
+                    save_screenshot(&emulator, &rom_path, screenshot_scale);
+                }
+                if scancode == 0x043 && state == ElementState::Pressed {
+                    // F9 hit
+                    recorder = match recorder.take() {
+                        Some(recorder) => {
+                            stop_recording(recorder);
+                            None
+                        }
+                        None => start_recording(&recording_settings, &rom_path),
+                    };
+                }
+                if scancode == 0x00E {
+                    // backspace held
+                    rewinding = state == ElementState::Pressed && !movie_running;
+                }
+                update_key_states(&input, &mut held_keys, &key_map, &mut emulator);
+            }
+
+            Event::MainEventsCleared => {
+                if rewinding {
+                    // one frame back per frame, so it plays back at the speed it was recorded
+                    if rewind.rewind(&mut emulator) {
+                        window.request_redraw();
+                    }
+                } else {
+                    if let Some(movie) = &playing {
+                        if movie.is_finished(emulator.frame_count) {
+                            println!("The movie's finished, the keyboard's back in control");
+                            playing = None;
+                        } else {
+                            emulator.key_states = movie.key_states_at(emulator.frame_count);
+                        }
+                    }
+                    if let Some((movie, _)) = &mut recording_movie {
+                        movie.record_frame(&emulator);
+                    }
+                    let result = match &mut debugger {
+                        Some((debugger, commands)) => {
+                            run_debugger_commands(debugger, commands, &mut emulator);
+                            let paused = debugger.is_paused();
+                            let result = debugger.run_frame(&mut emulator);
+                            for message in debugger.take_messages() {
+                                print!("{}\n(debug) ", message);
+                            }
+                            // a frame spent paused isn't worth rewinding through
+                            if paused {
+                                None
+                            } else {
+                                Some(result)
+                            }
+                        }
+                        None => Some(emulator.run_frame()),
+                    };
+                    let ran = matches!(result, Some(Ok(_)));
+                    match result {
+                        None => (),
+                        Some(Ok(InstructionResult::Terminated)) => {
+                            println!("Emulator self terminating");
+                            *control_flow = ControlFlow::Exit;
+                        }
+                        Some(Ok(InstructionResult::RedrawRequested))
+                        | Some(Ok(InstructionResult::WatchpointHit(_))) => {
+                            window.request_redraw();
+                            rewind.record(&emulator);
+                        }
+                        Some(Ok(_)) => rewind.record(&emulator),
+                        Some(Err(e)) => {
+                            println!("Emulator stopped: {}", e);
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                    if let (true, Some(active)) = (ran, &mut recorder) {
+                        if let Err(e) = active.capture_frame(&emulator) {
+                            println!("Failed to record a frame: {}", e);
+                            stop_recording(recorder.take().unwrap());
+                        }
+                    }
+                }
+
+                // so that stdout prints show up when printed
+                stdout().flush().unwrap();
+
+                // sleep off whatever is left of this frame to hold the emulation at 60hz
+                let now = Instant::now();
+                if next_frame > now {
+                    thread::sleep(next_frame - now);
+                    next_frame += FRAME_DURATION;
+                } else {
+                    // we've fallen behind, don't try to catch up by running frames back to back
+                    next_frame = now + FRAME_DURATION;
+                }
+            }
+            Event::RedrawRequested(_) => {
+                render(&emulator.display, &mut pixels);
+            }
+            Event::LoopDestroyed => {
+                // the emulator is never dropped when the loop exits, so buffered trace lines
+                // and recordings have to be written out here
+                if let Some(recorder) = recorder.take() {
+                    stop_recording(recorder);
+                }
+                if let Some((movie, path)) = &recording_movie {
+                    match movie.save(path) {
+                        Ok(()) => println!("Saved the movie to {}", path),
+                        Err(e) => println!("Failed to save the movie to {}: {}", path, e),
+                    }
+                }
+                if let Some(tracer) = &mut emulator.tracer {
+                    if let Err(e) = tracer.flush() {
+                        println!("Failed to write the trace: {}", e);
+                    }
+                }
+            }
+            _ => (),
+        }
+    });
+}
+
+// plays beep2.wav, or the program's audio pattern once it's loaded one, while the sound timer
+// is running
+#[derive(Debug)]
+struct BeepStream {
+    sound_counter: Arc<AtomicU8>,
+    audio_state: Arc<AudioState>,
+    player: PatternPlayer,
+    volume: f32,
+}
+
+impl AudioStream for BeepStream {
+    fn next(&mut self, dt: f64) -> Frame {
+        if self.sound_counter.load(Ordering::Relaxed) == 0 {
+            self.player.reset();
+            return Frame::from_mono(0.0);
+        }
+        // beep is loud af, so we turn that shit down
+        let sample = self.player.next_sample(&self.audio_state, 1.0 / dt);
+        Frame::from_mono(sample * self.volume)
+    }
+}
+
+// stdin is read on its own thread so waiting on the user never holds up the window
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn run_debugger_commands(
+    debugger: &mut Debugger,
+    commands: &Receiver<String>,
+    emulator: &mut Emulator,
+) {
+    while let Ok(line) = commands.try_recv() {
+        let output = debugger.execute_command(&line, emulator);
+        if !output.is_empty() {
+            println!("{}", output);
+        }
+        if debugger.is_paused() {
+            print!("(debug) ");
+        }
+    }
+}
+
+// F1-F4 save to slots 1-4, F5-F8 load them back
+// the slots are files next to the rom, eg roms/Pong.ch8.state1
+// returns true if a state was loaded, so the display needs redrawing
+fn handle_save_state_keys(scancode: u32, emulator: &mut Emulator, rom_path: &str) -> bool {
+    match scancode {
+        0x03B..=0x03E => {
+            let slot = scancode - 0x03B + 1;
+            let file_name = format!("{}.state{}", rom_path, slot);
+            match emulator.save_state(&file_name) {
+                Ok(()) => println!("Saved state to slot {}", slot),
+                Err(e) => println!("Failed to save state to {}: {}", file_name, e),
+            }
+            false
+        }
+        0x03F..=0x042 => {
+            let slot = scancode - 0x03F + 1;
+            let file_name = format!("{}.state{}", rom_path, slot);
+            match emulator.load_state(&file_name) {
+                Ok(changed) => {
+                    println!("Loaded state from slot {}", slot);
+                    if !changed.is_empty() {
+                        println!(
+                            "The state was saved with different quirks, it changed {}",
+                            changed.join(", ")
+                        );
+                    }
+                    true
+                }
+                Err(e) => {
+                    println!("Failed to load state from {}: {}", file_name, e);
+                    false
+                }
+            }
+        }
+        _ => false,
+    }
+}
+
+// screenshots go next to the rom, named after it and the time they were taken (in UTC),
+// eg roms/Pong-20211018-140322-123.png
+fn save_screenshot(emulator: &Emulator, rom_path: &str, scale: usize) {
+    let file_name = format!("{}.png", timestamped_path(rom_path));
+    match emulator.save_screenshot(&file_name, scale) {
+        Ok(()) => println!("Saved a screenshot to {}", file_name),
+        Err(e) => println!("Failed to save a screenshot to {}: {}", file_name, e),
+    }
+}
+
+// recordings are named the same way as screenshots, with a file for each format
+fn start_recording(settings: &RecordingSettings, rom_path: &str) -> Option<Recorder> {
+    let path = timestamped_path(rom_path);
+    let files = RecordingFiles {
+        gif: Some(format!("{}.gif", path)),
+        y4m: settings.y4m.then(|| format!("{}.y4m", path)),
+        wav: settings.wav.then(|| format!("{}.wav", path)),
+    };
+    match files.start(settings.scale) {
+        Ok(recorder) => {
+            for file_name in files.paths() {
+                println!("Recording to {}", file_name);
+            }
+            Some(recorder)
+        }
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    }
+}
+
+fn stop_recording(recorder: Recorder) {
+    let frames = recorder.frames();
+    match recorder.finish() {
+        Ok(()) => println!("Stopped recording after {} frames", frames),
+        Err(e) => println!("Failed to finish the recording: {}", e),
+    }
+}
+
+// the rom's path without its extension and with the time added, eg roms/Pong-20211018-140322-123
+fn timestamped_path(rom_path: &str) -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!(
+        "{}-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        Path::new(rom_path).with_extension("").display(),
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// the (year, month, day) that's this many days after 1970-01-01, from
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// keeps track of the host keys held down, and holds down the CHIP-8 keys they're bound to
+fn update_key_states(
+    input: &KeyboardInput,
+    held_keys: &mut Vec<(u32, Option<String>)>,
+    key_map: &KeyMap,
+    emulator: &mut Emulator,
+) {
+    held_keys.retain(|(scancode, _)| *scancode != input.scancode);
+    if input.state == ElementState::Pressed {
+        // the name is the same one used for the key in the config
+        let name = input.virtual_keycode.map(|key| format!("{:?}", key));
+        held_keys.push((input.scancode, name));
+    }
+    emulator.key_states = key_map.key_states(held_keys);
+}
+
+fn render(display: &Display, pixels: &mut Pixels) {
+    // the buffer has to follow the display between lores and hires
+    let (width, height) = (display.width(), display.height());
+    if pixels.get_frame().len() != width * height * 4 {
+        pixels.resize_buffer(width as u32, height as u32);
+    }
+    display.draw_to_frame(pixels.get_frame());
+    pixels.render().unwrap()
+}
+
+// scale is how many times the lores resolution to make the window, or None to let the
+// platform pick
+fn init(scale: Option<u32>) -> (EventLoop<()>, winit::window::Window, Pixels) {
+    let event_loop = EventLoop::new();
+    let mut builder = WindowBuilder::new().with_title("chip8_rust");
+    if let Some(scale) = scale {
+        builder = builder.with_inner_size(LogicalSize::new(64 * scale, 32 * scale));
+    }
+    let window = builder.build(&event_loop).unwrap();
+    let pixels = {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        Pixels::new(64, 32, surface_texture).unwrap()
+    };
+    (event_loop, window, pixels)
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+
+// Scripted key presses, so headless runs get the same input every time. A script is a list of
+// events, one per line:
+//
+//     # frame  action   key
+//     120      press    5
+//     130      release  5
+//
+// Frames count from 0 as the emulator runs, keys are the hex keypad keys 0-F, and # starts a
+// comment. A key stays down from the frame it's pressed until the frame it's released
+#[derive(Clone, Debug, Default)]
+pub struct InputScript {
+    // (frame, key, pressed), in the order they happen
+    events: Vec<(u64, usize, bool)>,
+}
+
+impl InputScript {
+    pub fn parse(source: &str) -> Result<InputScript, String> {
+        let mut events = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            if words.len() != 3 {
+                return Err(error("expected <frame> <press|release> <key>".to_string()));
+            }
+            let frame = words[0]
+                .parse()
+                .map_err(|_| error(format!("{} isn't a frame number", words[0])))?;
+            let pressed = match words[1] {
+                "press" | "down" => true,
+                "release" | "up" => false,
+                other => return Err(error(format!("unknown action {}", other))),
+            };
+            let key = match usize::from_str_radix(words[2], 16) {
+                Ok(key) if key < 16 => key,
+                _ => return Err(error(format!("{} isn't a key, keys are 0-F", words[2]))),
+            };
+            events.push((frame, key, pressed));
+        }
+        // sort by frame only, so events on the same frame keep the order they were written in
+        events.sort_by_key(|(frame, _, _)| *frame);
+        Ok(InputScript { events })
+    }
+
+    // adds an event after all the others
+    pub fn push(&mut self, frame: u64, key: usize, pressed: bool) {
+        self.events.push((frame, key, pressed));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // the frame of the last event
+    pub fn last_frame(&self) -> Option<u64> {
+        self.events.last().map(|(frame, _, _)| *frame)
+    }
+
+    // which keys are down during frame
+    pub fn key_states_at(&self, frame: u64) -> [bool; 16] {
+        let mut key_states = [false; 16];
+        for (_, key, pressed) in self.events.iter().take_while(|(at, _, _)| *at <= frame) {
+            key_states[*key] = *pressed;
+        }
+        key_states
+    }
+}
+
+// back in the format it's parsed from
+impl fmt::Display for InputScript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (frame, key, pressed) in &self.events {
+            let action = if *pressed { "press" } else { "release" };
+            writeln!(f, "{:<8} {:<8} {:X}", frame, action, key)?;
+        }
+        Ok(())
+    }
+}
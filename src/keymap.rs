@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_derive::Deserialize;
+
+// Which keys on the host keyboard press which CHIP-8 keys. A host key is either a scancode,
+// the key's physical position whatever the layout, or a key name like "Q" or "Numpad5", which
+// follows the layout so AZERTY and Dvorak keyboards get the key with that letter on it. Names
+// are winit's VirtualKeyCode names and aren't case sensitive
+#[derive(Clone, PartialEq, Debug)]
+pub enum HostKey {
+    Scancode(u32),
+    Named(String),
+}
+
+impl HostKey {
+    // "scancode:0x10" (or decimal) for a scancode, anything else is a name
+    pub fn parse(text: &str) -> Result<HostKey, String> {
+        match text.strip_prefix("scancode:") {
+            Some(number) => {
+                let scancode = match number.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => number.parse(),
+                };
+                scancode
+                    .map(HostKey::Scancode)
+                    .map_err(|_| format!("{} isn't a scancode", number))
+            }
+            None if text.is_empty() => Err("a key name can't be empty".to_string()),
+            None => Ok(HostKey::Named(text.to_string())),
+        }
+    }
+
+    fn matches(&self, scancode: u32, name: Option<&str>) -> bool {
+        match self {
+            HostKey::Scancode(code) => *code == scancode,
+            HostKey::Named(own) => matches!(name, Some(name) if own.eq_ignore_ascii_case(name)),
+        }
+    }
+}
+
+impl fmt::Display for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostKey::Scancode(code) => write!(f, "scancode:0x{:02X}", code),
+            HostKey::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// the host keys bound to each CHIP-8 key, any of them holds it down
+#[derive(Clone, PartialEq, Debug)]
+pub struct KeyMap {
+    bindings: [Vec<HostKey>; 16],
+}
+
+impl KeyMap {
+    // the keypad on the left of a QWERTY keyboard, by position so it works on any layout
+    //     Keypad      Keyboard
+    //     1 2 3 C     1 2 3 4
+    //     4 5 6 D ->  Q W E R
+    //     7 8 9 E     A S D F
+    //     A 0 B F     Z X C V
+    pub fn qwerty() -> Self {
+        let scancodes = [
+            0x02D, 0x002, 0x003, 0x004, 0x010, 0x011, 0x012, 0x01E, 0x01F, 0x020, 0x02C, 0x02E,
+            0x005, 0x013, 0x021, 0x02F,
+        ];
+        let mut key_map = KeyMap {
+            bindings: Default::default(),
+        };
+        for (key, scancode) in scancodes.iter().enumerate() {
+            key_map.bindings[key] = vec![HostKey::Scancode(*scancode)];
+        }
+        key_map
+    }
+
+    pub fn bindings(&self, key: usize) -> &[HostKey] {
+        &self.bindings[key]
+    }
+
+    pub fn bind(&mut self, key: usize, host_keys: Vec<HostKey>) {
+        self.bindings[key] = host_keys;
+    }
+
+    // the CHIP-8 keys held down while these host keys are, as (scancode, key name) pairs
+    pub fn key_states(&self, held: &[(u32, Option<String>)]) -> [bool; 16] {
+        let mut key_states = [false; 16];
+        for (state, host_keys) in key_states.iter_mut().zip(&self.bindings) {
+            *state = host_keys.iter().any(|host_key| {
+                held.iter()
+                    .any(|(scancode, name)| host_key.matches(*scancode, name.as_deref()))
+            });
+        }
+        key_states
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::qwerty()
+    }
+}
+
+// The [keys] table in chip8_rust_config.toml, CHIP-8 keys (0-F) to lists of host keys. Any key
+// left out keeps its QWERTY binding. [keys.roms."<rom file name>"] tables override it further for
+// one rom:
+//
+//     [keys]
+//     4 = ["Q", "Left"]
+//     6 = ["scancode:0x12", "Right"]
+//
+//     [keys.roms."Pong.ch8"]
+//     1 = ["Up"]
+//     4 = ["Down"]
+#[derive(Debug, Default, Deserialize)]
+pub struct KeysConfig {
+    #[serde(default)]
+    pub roms: HashMap<String, HashMap<String, Vec<String>>>,
+    #[serde(flatten)]
+    pub keys: HashMap<String, Vec<String>>,
+}
+
+impl KeysConfig {
+    // the mapping for the rom with this file name
+    pub fn to_key_map(&self, rom_name: &str) -> Result<KeyMap, String> {
+        let mut key_map = KeyMap::qwerty();
+        bind_all(&mut key_map, &self.keys)?;
+        if let Some(overrides) = self.roms.get(rom_name) {
+            bind_all(&mut key_map, overrides)
+                .map_err(|e| format!("in the keys for {}, {}", rom_name, e))?;
+        }
+        Ok(key_map)
+    }
+}
+
+fn bind_all(key_map: &mut KeyMap, bindings: &HashMap<String, Vec<String>>) -> Result<(), String> {
+    for (key, host_keys) in bindings {
+        let key = match usize::from_str_radix(key, 16) {
+            Ok(key) if key < 16 => key,
+            _ => return Err(format!("{} isn't a key, keys are 0-F", key)),
+        };
+        let host_keys = host_keys
+            .iter()
+            .map(|host_key| HostKey::parse(host_key))
+            .collect::<Result<Vec<_>, _>>()?;
+        key_map.bind(key, host_keys);
+    }
+    Ok(())
+}
@@ -0,0 +1,31 @@
+// The emulator core, with no windowing or audio dependencies so it can be embedded anywhere.
+// The winit/pixels/kira frontend in frontend.rs is built with the `frontend` feature, and
+// movies, recording and rewinding with their own features (see Cargo.toml)
+pub mod assembler;
+pub mod audio;
+pub mod cli;
+pub mod condition;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod emulator;
+pub mod input;
+pub mod keymap;
+#[cfg(feature = "movies")]
+pub mod movie;
+pub mod octo;
+pub mod quirks;
+#[cfg(feature = "recording")]
+pub mod recording;
+#[cfg(feature = "savestates")]
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod trace;
+pub mod tracediff;
+pub mod watchpoint;
+
+pub use emulator::{Emulator, EmulatorError, InstructionResult, Opcode, TimerMode};
+
+#[cfg(test)]
+mod tests;
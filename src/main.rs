@@ -1,35 +1,58 @@
-mod tests;
-
-use std::fs::File;
-use std::io::{prelude::*, stdout};
-use std::sync::atomic::Ordering;
-use std::sync::Arc;
-use std::thread;
-use std::time::{self, Duration};
-
-use kira::arrangement::{Arrangement, LoopArrangementSettings};
-use kira::instance::{InstanceLoopStart, InstanceSettings, StopInstanceSettings};
-use kira::manager::{AudioManager, AudioManagerSettings};
-use kira::sound::SoundSettings;
-use pixels::{Pixels, SurfaceTexture};
-use winit::event::{ElementState, KeyboardInput};
-use winit::{
-    event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
-};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufWriter};
+use std::time::Instant;
 
 use serde_derive::Deserialize;
 
-mod emulator;
-use emulator::*;
+use chip8_rust::cli::{self, fail, Limit, MachineOptions, OptionValue};
+use chip8_rust::disasm::{reachable_instructions, Platform};
+use chip8_rust::emulator::PROGRAM_START;
+use chip8_rust::input::InputScript;
+use chip8_rust::keymap::KeysConfig;
+use chip8_rust::quirks::{Quirks, QuirksConfig};
+use chip8_rust::rng::RngMode;
+use chip8_rust::trace::{TraceConfig, TraceFormat, Tracer};
+use chip8_rust::{Emulator, Opcode, TimerMode};
+
+// the window, audio and keyboard, only built with the frontend feature
+#[cfg(feature = "frontend")]
+mod frontend;
 
 // when using the c8_test rom, refer to this documentation https://github.com/Skosulor/c8int/blob/master/test/chip8_test.txt
 const DEFAULT_ROM: &str = "roms/c8_test.c8";
-const DEFAULT_INSTRUCTIONS_PER_CYCLE: u8 = 10;
 const DEFAULT_BEEP_VOLUME: f64 = 0.3;
+const DEFAULT_TIMER_MODE: TimerMode = TimerMode::Emulated;
+const DEFAULT_REWIND_SECONDS: u32 = 10;
+const DEFAULT_RECORDING_SCALE: u32 = 4;
+const DEFAULT_BENCH_FRAMES: u64 = 3600;
+
+const USAGE: &str = "usage: chip8_rust [<command>] [<args>]
+
+commands:
+  disasm <rom>            prints a listing of the rom
+  asm <source> <rom>      assembles the source into a rom, .8o files are compiled as Octo
+  trace <rom>             prints a trace of every instruction the rom runs
+      [--frames <n> | --instructions <n>] [--format text|binary] [--out <file>]
+      [--inputs <file>] [--ipf <n>] [--quirks <preset>] [--seed <n>] [--rng xorshift|vip]
+      [--vip-interpreter <file>]
+  bench <rom>             runs the rom flat out and reports how fast it went
+      [--frames <n> | --instructions <n>] [--ipf <n>] [--quirks <preset>] [--seed <n>]
+      [--rng xorshift|vip] [--vip-interpreter <file>]
+  info <rom>              prints the rom's size, CRC-32 and which instruction sets it uses
+
+Options given here override chip8_rust_config.toml";
+
+// run is only there with the window
+#[cfg(feature = "frontend")]
+const RUN_USAGE: &str = "
+  run [<rom>]             runs a rom in a window, the default when there's no command
+      [--ipf <n>] [--quirks <preset>] [--seed <n>] [--rng xorshift|vip] [--scale <n>]
+      [--vip-interpreter <file>] [--debug] [--breakpoints <file>] [--record-movie <file>]
+      [--play-movie <file>]";
+#[cfg(not(feature = "frontend"))]
+const RUN_USAGE: &str = "";
 
-const CYCLE_SLEEP_DURATION: time::Duration = time::Duration::from_millis(10);
 // general todo
 // todo implement error handling
 
@@ -64,289 +87,367 @@ const CYCLE_SLEEP_DURATION: time::Duration = time::Duration::from_millis(10);
 #[derive(Debug, Deserialize)]
 struct Config {
     rom: Option<String>,
-    instructions_per_cycle: Option<u8>,
+    instructions_per_cycle: Option<u32>,
     beep_volume: Option<f64>,
+    timer_mode: Option<String>,
+    quirks: Option<QuirksConfig>,
+    seed: Option<u64>,
+    rng: Option<String>,
+    vip_interpreter: Option<String>,
+    rewind_seconds: Option<u32>,
+    window_scale: Option<u32>,
+    screenshot_scale: Option<u32>,
+    keys: Option<KeysConfig>,
+    recording_scale: Option<u32>,
+    record_y4m: Option<bool>,
+    record_wav: Option<bool>,
+    trace: Option<TraceConfig>,
 }
 
-fn main() {
-    // based on 4kb variant (hence 3215 bytes) (wait shouldn't it be 3583???)
-    // all memory accesses will be in big endian
-    // chip 8 is big endian!!!
-    // halt on bad memory access?
-
-    // planning on making these count down on another thread
-    // use a condition variable to wake the counting thread on counter set
-    // just use thread sleep to try and get the right 60hz freq
-
-    //1. load program into memory from file
-    //  if it exceeds size than halt with error
-    //2. begin running byte code
-    // (code is allowed to be self modifying (ie no write protection region))
-    // error on any address read/write below 0x200
-
-    let (event_loop, window, mut emulator) = init();
-    let (rom_path, instructions_per_cycle, volume) = get_config();
-    let bytes_read = emulator.load_program(&rom_path);
-    println!("Loaded program, bytes {}", bytes_read);
-    window.request_redraw();
-
-    // putting the audio play / stop on its own thread so its not
-    // affected by the emulation processing
-    let sound_counter = Arc::clone(&emulator.sound_counter);
-    thread::spawn(move || {
-        let mut audio_manager = AudioManager::new(AudioManagerSettings::default()).unwrap();
-        let sound_handle = audio_manager
-            .load_sound("beep2.wav", SoundSettings::new())
-            .unwrap();
-        let mut arrangement_handle = audio_manager
-            .add_arrangement(Arrangement::new_loop(
-                &sound_handle,
-                LoopArrangementSettings::default(),
-            ))
-            .unwrap();
-        let mut is_beep_playing = false;
-
-        // beep is loud af, so we turn that shit down
-        let play_instance_settings = InstanceSettings {
-            volume: volume.into(),
-            ..InstanceSettings::default()
-        };
-
-        loop {
-            let counter_val = sound_counter.load(Ordering::Relaxed);
-            if !is_beep_playing && counter_val > 0 {
-                is_beep_playing = true;
-                arrangement_handle.play(play_instance_settings).unwrap();
-            }
-            if is_beep_playing && counter_val == 0 {
-                is_beep_playing = false;
-                arrangement_handle
-                    .stop(StopInstanceSettings::default())
-                    .unwrap();
-            }
-            //we yield to keep this thread well behaved in the OS's eyes
-            //this should actually help keep it more responsive on a normal system
-            //also lets the cpu chill way more without meaningfully affect performance
-            thread::sleep(Duration::from_millis(1));
-        }
-    });
-
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
-
-        match event {
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                ..
-            } => {
-                println!("The closed button was pressed; stopping");
-                *control_flow = ControlFlow::Exit
-            }
-            Event::WindowEvent {
-                event: WindowEvent::Resized(size),
-                ..
-            } => emulator.pixels_surface_resize(size),
-            Event::WindowEvent {
-                event:
-                    WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                scancode, state, ..
-                            },
-                        ..
-                    },
-                ..
-            } => {
-                if scancode == 0x001 {
-                    // escape hit
-                    println!("Escape key hit, closing");
-                    *control_flow = ControlFlow::Exit;
-                }
-                update_key_states(scancode, state, &mut emulator);
-            }
+// the config with defaults filled in for anything left out, and the command line on top.
+// Without the frontend the window's settings go unused
+#[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+struct Settings {
+    rom_path: String,
+    machine: MachineOptions,
+    beep_volume: f64,
+    timer_mode: TimerMode,
+    rewind_seconds: u32,
+    window_scale: Option<u32>,
+    screenshot_scale: u32,
+    keys: KeysConfig,
+    recording: RecordingSettings,
+    trace: Option<TraceConfig>,
+}
 
-            Event::MainEventsCleared => {
-                emulator.update_time_counters();
-                for _ in 1..instructions_per_cycle {
-                    match emulator.execute_next_instruction() {
-                        InstructionResult::Terminated => {
-                            println!("Emulator self terminating");
-                            *control_flow = ControlFlow::Exit;
-                        }
-                        InstructionResult::RedrawRequested => window.request_redraw(),
-                        _ => (),
-                    }
-                }
+// what F9 records, a gif is always made and the rest are optional
+#[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+struct RecordingSettings {
+    scale: usize,
+    y4m: bool,
+    wav: bool,
+}
 
-                // so that stdout prints show up when printed
-                stdout().flush().unwrap();
-                thread::sleep(CYCLE_SLEEP_DURATION);
-            }
-            Event::RedrawRequested(_) => {
-                emulator.pixels_render();
-            }
-            _ => (),
-        }
-    });
+// the run command's options that aren't settings
+#[cfg(feature = "frontend")]
+#[derive(Default)]
+struct RunOptions {
+    debug: bool,
+    breakpoints: Option<String>,
+    record_movie: Option<String>,
+    play_movie: Option<String>,
 }
 
-fn update_key_states(scancode: u32, state: ElementState, emulator: &mut Emulator) {
-    // x key -> 0
-    if scancode == 0x02D {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x0] = true;
-        } else {
-            emulator.key_states[0x0] = false;
-        }
+impl Settings {
+    // the options for how a rom runs, which all the commands that run one take. Returns false
+    // if option isn't one of them
+    fn apply_option(&mut self, option: &str, value: &str) -> bool {
+        self.machine
+            .apply_option(option, value)
+            .unwrap_or_else(|e| fail(&e))
     }
-    // 1 key -> 1
-    if scancode == 0x002 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x1] = true;
-        } else {
-            emulator.key_states[0x1] = false;
-        }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // with no command it's run, so `chip8_rust --debug` carries on working. Without the window
+    // there's nothing to run
+    let (command, args) = match args.first() {
+        Some(command) if !command.starts_with("--") => (command.as_str(), &args[1..]),
+        _ if cfg!(feature = "frontend") => ("run", &args[..]),
+        _ => fail(&usage()),
+    };
+    match command {
+        #[cfg(feature = "frontend")]
+        "run" => run(args),
+        "disasm" => disasm(args),
+        "asm" => asm(args),
+        "trace" => trace(args),
+        "bench" => bench(args),
+        "info" => info(args),
+        "help" => println!("{}", usage()),
+        _ => fail(&format!("unknown command {}\n{}", command, usage())),
     }
-    // 2 key -> 2
-    if scancode == 0x003 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x2] = true;
-        } else {
-            emulator.key_states[0x2] = false;
+}
+
+fn usage() -> String {
+    USAGE.replacen("commands:", &format!("commands:{}", RUN_USAGE), 1)
+}
+
+#[cfg(feature = "frontend")]
+fn run(args: &[String]) {
+    let mut settings = get_config();
+    let mut options = RunOptions::default();
+    let (roms, values) = split_args(args, &["--debug"]);
+    for (option, value) in &values {
+        if settings.apply_option(option, value) {
+            continue;
         }
-    }
-    // 3 key -> 3
-    if scancode == 0x004 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x3] = true;
-        } else {
-            emulator.key_states[0x3] = false;
+        match option.as_str() {
+            "--scale" => {
+                let scale: u32 = cli::parse_number(value).unwrap_or_else(|e| fail(&e));
+                settings.window_scale = Some(scale.max(1));
+            }
+            "--debug" => options.debug = true,
+            "--breakpoints" => options.breakpoints = Some(value.clone()),
+            "--record-movie" => options.record_movie = Some(value.clone()),
+            "--play-movie" => options.play_movie = Some(value.clone()),
+            _ => fail(&format!("unknown option {}\n{}", option, usage())),
         }
     }
-    // Q key -> 4
-    if scancode == 0x010 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x4] = true;
-        } else {
-            emulator.key_states[0x4] = false;
-        }
+    if let Some(rom) = roms.first() {
+        settings.rom_path = rom.clone();
     }
-    // W key -> 5
-    if scancode == 0x011 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x5] = true;
-        } else {
-            emulator.key_states[0x5] = false;
+    frontend::run(settings, options);
+}
+
+fn disasm(args: &[String]) {
+    let (paths, _) = split_args(args, &[]);
+    let rom_path = match paths.as_slice() {
+        [rom_path] => rom_path,
+        _ => fail("usage: chip8_rust disasm <rom>"),
+    };
+    print!(
+        "{}",
+        cli::disassemble_file(rom_path).unwrap_or_else(|e| fail(&e))
+    );
+}
+
+fn asm(args: &[String]) {
+    let (paths, _) = split_args(args, &[]);
+    let (source, rom_path) = match paths.as_slice() {
+        [source, rom_path] => (source, rom_path),
+        _ => fail("usage: chip8_rust asm <source> <rom>"),
+    };
+    let size = cli::assemble_to_file(source, rom_path).unwrap_or_else(|e| fail(&e));
+    println!("Assembled {} bytes", size);
+}
+
+fn trace(args: &[String]) {
+    let mut settings = get_config();
+    let (roms, values) = split_args(args, &[]);
+    let mut limit = Limit::default();
+    let mut format = TraceFormat::Text;
+    let mut out = None;
+    let mut inputs = InputScript::default();
+    for (option, value) in &values {
+        if settings.apply_option(option, value) {
+            continue;
         }
-    }
-    // E key -> 6
-    if scancode == 0x012 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x6] = true;
-        } else {
-            emulator.key_states[0x6] = false;
+        if let Some(option_limit) = Limit::from_option(option, value).unwrap_or_else(|e| fail(&e)) {
+            limit = option_limit;
+            continue;
         }
-    }
-    // A key -> 7
-    if scancode == 0x01E {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x7] = true;
-        } else {
-            emulator.key_states[0x7] = false;
+        match option.as_str() {
+            "--format" => {
+                format = match value.as_str() {
+                    "text" => TraceFormat::Text,
+                    "binary" => TraceFormat::Binary,
+                    _ => fail(&format!("unknown trace format {}", value)),
+                }
+            }
+            "--out" => out = Some(value.clone()),
+            "--inputs" => inputs = cli::read_inputs(value).unwrap_or_else(|e| fail(&e)),
+            _ => fail(&format!("unknown option {}\n{}", option, usage())),
         }
     }
-    // S key -> 8
-    if scancode == 0x01F {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x8] = true;
-        } else {
-            emulator.key_states[0x8] = false;
+    let rom_path = single_rom(&roms, "trace");
+
+    let mut emulator = headless_emulator(&settings, rom_path);
+    let tracer = match out.as_deref() {
+        None | Some("-") => Tracer::new(Box::new(BufWriter::new(io::stdout())), format),
+        Some(path) => Tracer::create(path, format)
+            .unwrap_or_else(|e| fail(&format!("Failed to create {}: {}", path, e))),
+    };
+    emulator.tracer = Some(tracer);
+    let result = cli::run_headless(&mut emulator, limit, &inputs, |_| Ok(()));
+    if let Some(tracer) = &mut emulator.tracer {
+        if let Err(e) = tracer.flush() {
+            fail(&format!("Failed to write the trace: {}", e));
         }
     }
-    // D key -> 9
-    if scancode == 0x020 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0x9] = true;
-        } else {
-            emulator.key_states[0x9] = false;
-        }
+    if let Err(e) = result {
+        fail(&format!("Emulator stopped: {}", e));
     }
-    // Z key -> A
-    if scancode == 0x02C {
-        if state == ElementState::Pressed {
-            emulator.key_states[0xA] = true;
-        } else {
-            emulator.key_states[0xA] = false;
+}
+
+fn bench(args: &[String]) {
+    let mut settings = get_config();
+    let (roms, values) = split_args(args, &[]);
+    let mut limit = Limit::Frames(DEFAULT_BENCH_FRAMES);
+    for (option, value) in &values {
+        if settings.apply_option(option, value) {
+            continue;
         }
-    }
-    // C key -> B
-    if scancode == 0x02E {
-        if state == ElementState::Pressed {
-            emulator.key_states[0xB] = true;
-        } else {
-            emulator.key_states[0xB] = false;
+        match Limit::from_option(option, value).unwrap_or_else(|e| fail(&e)) {
+            Some(option_limit) => limit = option_limit,
+            None => fail(&format!("unknown option {}\n{}", option, usage())),
         }
     }
-    // 4 key -> C
-    if scancode == 0x005 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0xC] = true;
-        } else {
-            emulator.key_states[0xC] = false;
-        }
+    let rom_path = single_rom(&roms, "bench");
+
+    let mut emulator = headless_emulator(&settings, rom_path);
+    let start = Instant::now();
+    let result = cli::run_headless(&mut emulator, limit, &InputScript::default(), |_| Ok(()));
+    if let Err(e) = result {
+        eprintln!("Emulator stopped: {}", e);
     }
-    // R key -> D
-    if scancode == 0x013 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0xD] = true;
-        } else {
-            emulator.key_states[0xD] = false;
-        }
+    let seconds = start.elapsed().as_secs_f64().max(f64::EPSILON);
+    println!(
+        "{} frames, {} instructions in {:.3} seconds",
+        emulator.frame_count, emulator.cycle_count, seconds
+    );
+    println!(
+        "{:.0} instructions a second, {:.0} frames a second, {:.1}x real time",
+        emulator.cycle_count as f64 / seconds,
+        emulator.frame_count as f64 / seconds,
+        emulator.frame_count as f64 / 60.0 / seconds
+    );
+}
+
+fn info(args: &[String]) {
+    let (roms, _) = split_args(args, &[]);
+    let rom_path = single_rom(&roms, "info");
+    let rom = read_rom(rom_path);
+
+    let instructions = reachable_instructions(&rom);
+    let platform = instructions
+        .values()
+        .map(|code| Opcode::new(*code).platform())
+        .fold(Platform::Chip8, |highest, platform| {
+            if platform > highest {
+                platform
+            } else {
+                highest
+            }
+        });
+    let unknown = instructions
+        .values()
+        .filter(|code| Opcode::new(**code).disassemble().is_none())
+        .count();
+    // only XO-CHIP has more than 4kb to fit into
+    let memory = if platform == Platform::XoChip {
+        Quirks::xochip().memory_size()
+    } else {
+        Quirks::default().memory_size()
+    };
+    let room = memory - PROGRAM_START;
+
+    println!("rom           {}", rom_path);
+    if rom.len() <= room {
+        println!(
+            "size          {} bytes, {} free",
+            rom.len(),
+            room - rom.len()
+        );
+    } else {
+        println!(
+            "size          {} bytes, too big by {}",
+            rom.len(),
+            rom.len() - room
+        );
     }
-    // F key -> E
-    if scancode == 0x021 {
-        if state == ElementState::Pressed {
-            emulator.key_states[0xE] = true;
-        } else {
-            emulator.key_states[0xE] = false;
-        }
+    println!("crc32         {:08X}", crc32fast::hash(&rom));
+    println!("instructions  {} reachable from 0x200", instructions.len());
+    if unknown > 0 {
+        println!("unknown       {} opcodes that don't decode", unknown);
     }
-    // V key -> F
-    if scancode == 0x02F {
-        if state == ElementState::Pressed {
-            emulator.key_states[0xF] = true;
-        } else {
-            emulator.key_states[0xF] = false;
-        }
+    println!("platform      {}", platform);
+}
+
+fn split_args(args: &[String], flags: &[&str]) -> (Vec<String>, Vec<OptionValue>) {
+    cli::split_args(args, flags).unwrap_or_else(|e| fail(&format!("{}\n{}", e, usage())))
+}
+
+fn single_rom<'a>(roms: &'a [String], command: &str) -> &'a str {
+    match roms {
+        [rom_path] => rom_path,
+        _ => fail(&format!("usage: chip8_rust {} <rom> [<options>]", command)),
+    }
+}
+
+fn read_rom(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", path, e)))
+}
+
+// an emulator for running without a window, with the rom loaded
+fn headless_emulator(settings: &Settings, rom_path: &str) -> Emulator {
+    let emulator = settings
+        .machine
+        .headless_emulator(rom_path)
+        .unwrap_or_else(|e| fail(&e));
+    if let Some(warning) = cli::vip_rng_warning(&emulator) {
+        eprintln!("{}", warning);
     }
+    emulator
 }
 
-fn get_config() -> (String, u8, f64) {
+// messages go to stderr, so they don't end up mixed in with a trace on stdout
+fn get_config() -> Settings {
     let mut file_buffer = String::new();
     if let Ok(mut config_file) = File::open("chip8_rust_config.toml") {
         config_file.read_to_string(&mut file_buffer).unwrap();
     }
     let decoded_toml: Config = toml::from_str(&file_buffer).unwrap();
-    println!("{:#?}", decoded_toml);
+    eprintln!("{:#?}", decoded_toml);
     let vol = decoded_toml.beep_volume.unwrap_or(DEFAULT_BEEP_VOLUME);
     let rom_path = decoded_toml.rom.unwrap_or(DEFAULT_ROM.to_string());
-    let instructions = decoded_toml
-        .instructions_per_cycle
-        .unwrap_or(DEFAULT_INSTRUCTIONS_PER_CYCLE);
-    (rom_path, instructions, vol)
-}
-
-fn init() -> (EventLoop<()>, winit::window::Window, Emulator) {
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("chip8_rust")
-        .build(&event_loop)
-        .unwrap();
-    let pixels = {
-        let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(64, 32, surface_texture).unwrap()
+    let timer_mode = match decoded_toml.timer_mode.as_deref() {
+        Some("emulated") => TimerMode::Emulated,
+        Some("free_running") => TimerMode::FreeRunning,
+        Some(other) => {
+            eprintln!("Unknown timer_mode {}, using the default", other);
+            DEFAULT_TIMER_MODE
+        }
+        None => DEFAULT_TIMER_MODE,
+    };
+    let quirks = match decoded_toml.quirks.unwrap_or_default().to_quirks() {
+        Ok(quirks) => quirks,
+        Err(e) => {
+            eprintln!("{}, using the default quirks", e);
+            Quirks::default()
+        }
     };
-    let emulator = Emulator::new(pixels);
-    (event_loop, window, emulator)
+    let rng_mode = match decoded_toml.rng.as_deref() {
+        None => RngMode::Xorshift,
+        Some(name) => RngMode::from_name(name).unwrap_or_else(|| {
+            eprintln!("Unknown rng {}, using xorshift", name);
+            RngMode::Xorshift
+        }),
+    };
+    let default_instructions = MachineOptions::default().instructions_per_frame;
+    let instructions_per_frame = match decoded_toml.instructions_per_cycle {
+        Some(0) => {
+            eprintln!("instructions_per_cycle has to be at least 1, using the default");
+            default_instructions
+        }
+        instructions => instructions.unwrap_or(default_instructions),
+    };
+    Settings {
+        rom_path,
+        machine: MachineOptions {
+            instructions_per_frame,
+            quirks,
+            seed: decoded_toml.seed,
+            rng_mode,
+            vip_interpreter: decoded_toml.vip_interpreter,
+        },
+        beep_volume: vol,
+        timer_mode,
+        rewind_seconds: decoded_toml
+            .rewind_seconds
+            .unwrap_or(DEFAULT_REWIND_SECONDS),
+        window_scale: decoded_toml.window_scale.map(|scale| scale.max(1)),
+        screenshot_scale: decoded_toml.screenshot_scale.unwrap_or(1).max(1),
+        keys: decoded_toml.keys.unwrap_or_default(),
+        recording: RecordingSettings {
+            scale: decoded_toml
+                .recording_scale
+                .unwrap_or(DEFAULT_RECORDING_SCALE)
+                .max(1) as usize,
+            y4m: decoded_toml.record_y4m.unwrap_or(false),
+            wav: decoded_toml.record_wav.unwrap_or(false),
+        },
+        trace: decoded_toml.trace,
+    }
 }
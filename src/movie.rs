@@ -0,0 +1,175 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::cli::MachineOptions;
+use crate::emulator::{Emulator, TimerMode};
+use crate::input::InputScript;
+
+// A recording of a session's inputs, with everything else needed to play it back exactly: the
+// rom it was played on and the MachineOptions it ran with, seed included. Movies are text, a
+// header followed by the key presses in the input script format (see input.rs):
+//
+//     # chip8_rust movie
+//     rom                     Pong.ch8
+//     rom_crc32               A8DA9D4A
+//     seed                    1234
+//     rng                     vip
+//     instructions_per_frame  10
+//     quirks                  shift_uses_vy vf_reset
+//     vip_interpreter         roms/vip.bin
+//     frames                  3600
+//     120      press    4
+//     130      release  4
+//
+// The options are written by MachineOptions::named, quirks lists the quirks that were on and
+// vip_interpreter is only there if one was loaded. Movies only replay exactly with emulated
+// timers, so playback always uses them
+#[derive(Clone, Debug)]
+pub struct Movie {
+    // the rom's file name, only there to tell people which rom it's for
+    pub rom: String,
+    pub rom_crc32: u32,
+    // always has a seed
+    pub machine: MachineOptions,
+    // how long the session went on for
+    pub frames: u64,
+    pub inputs: InputScript,
+    // the keys as they were at the last recorded frame
+    keys: [bool; 16],
+}
+
+impl Movie {
+    // starts recording a session on emu, which was configured with machine and should have just
+    // loaded rom and not run yet. The seed's taken from emu, as it may have been picked at random
+    pub fn new(emu: &Emulator, machine: &MachineOptions, rom_name: &str, rom: &[u8]) -> Self {
+        Movie {
+            rom: rom_name.to_string(),
+            rom_crc32: crc32fast::hash(rom),
+            machine: MachineOptions {
+                seed: Some(emu.rng.seed()),
+                ..machine.clone()
+            },
+            frames: 0,
+            inputs: InputScript::default(),
+            keys: [false; 16],
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Movie::parse(&text)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    // call before each frame runs, with the keys that frame sees
+    pub fn record_frame(&mut self, emu: &Emulator) {
+        for key in 0..16 {
+            if emu.key_states[key] != self.keys[key] {
+                self.inputs.push(emu.frame_count, key, emu.key_states[key]);
+            }
+        }
+        self.keys = emu.key_states;
+        self.frames = emu.frame_count + 1;
+    }
+
+    // an error if rom isn't the one the movie was recorded on
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), String> {
+        let crc32 = crc32fast::hash(rom);
+        if crc32 != self.rom_crc32 {
+            return Err(format!(
+                "the movie was recorded on {} (CRC-32 {:08X}) but this rom's CRC-32 is {:08X}",
+                self.rom, self.rom_crc32, crc32
+            ));
+        }
+        Ok(())
+    }
+
+    // sets emu up the way it was when the movie was recorded, before loading the rom. An
+    // error if the VIP interpreter it was recorded with can't be read
+    pub fn apply(&self, emu: &mut Emulator) -> Result<(), String> {
+        self.machine.configure(emu, 0)?;
+        emu.set_timer_mode(TimerMode::Emulated);
+        Ok(())
+    }
+
+    // the keys to hold down during frame
+    pub fn key_states_at(&self, frame: u64) -> [bool; 16] {
+        self.inputs.key_states_at(frame)
+    }
+
+    pub fn is_finished(&self, frame: u64) -> bool {
+        frame >= self.frames
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut rom = None;
+        let mut rom_crc32 = None;
+        let mut machine = MachineOptions::default();
+        let mut frames = None;
+        // the header is blanked out of what's left for the inputs, so the line numbers in
+        // their errors still match the file
+        let mut inputs = String::new();
+        for (i, line) in source.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let name = line.split_whitespace().next().unwrap_or("");
+            // file names can have spaces in them and quirks is a list, so it's the whole rest
+            let value = line.trim_start()[name.len()..].trim();
+            let number = |value: &str| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| error(format!("{} isn't a number", value)))
+            };
+            match name {
+                "rom" => rom = Some(value.to_string()),
+                "rom_crc32" => {
+                    rom_crc32 = Some(
+                        u32::from_str_radix(value, 16)
+                            .map_err(|_| error(format!("{} isn't a CRC-32", value)))?,
+                    )
+                }
+                "frames" => frames = Some(number(value)?),
+                _ => {
+                    if !machine.set_named(name, value).map_err(error)? {
+                        inputs.push_str(line);
+                        inputs.push('\n');
+                        continue;
+                    }
+                }
+            }
+            inputs.push('\n');
+        }
+        let missing = |name: &str| format!("the movie has no {} line", name);
+        let rom = rom.ok_or_else(|| missing("rom"))?;
+        let rom_crc32 = rom_crc32.ok_or_else(|| missing("rom_crc32"))?;
+        if machine.seed.is_none() {
+            return Err(missing("seed"));
+        }
+        Ok(Movie {
+            rom,
+            rom_crc32,
+            machine,
+            frames: frames.ok_or_else(|| missing("frames"))?,
+            inputs: InputScript::parse(&inputs)?,
+            keys: [false; 16],
+        })
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# chip8_rust movie")?;
+        writeln!(f, "{:<23} {}", "rom", self.rom)?;
+        writeln!(f, "{:<23} {:08X}", "rom_crc32", self.rom_crc32)?;
+        for (name, value) in self.machine.named() {
+            // no quirks leaves nothing after the name
+            let line = format!("{:<23} {}", name, value);
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        writeln!(f, "{:<23} {}", "frames", self.frames)?;
+        write!(f, "{}", self.inputs)
+    }
+}
@@ -0,0 +1,888 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use crate::emulator::PROGRAM_START;
+
+// A compiler for Octo (https://github.com/JohnEarnest/Octo), the language most modern CHIP-8,
+// SUPER-CHIP and XO-CHIP programs are written in. It covers the core of the language:
+//
+//     : label              defines a label, a bare label name calls it
+//     :const name value    :alias name vx         :org address       :byte value
+//     :macro name args { body }                   :calc name { expression }
+//     loop ... again       while condition (inside a loop)
+//     if condition then statement
+//     if condition begin ... else ... end
+//
+// plus every instruction, from `vx := random 0xFF` to `i := long label` and `scroll-left`.
+// Numbers on their own are emitted as bytes, which is how sprites are written.
+// Execution starts at the `main` label
+
+#[derive(Debug)]
+pub struct OctoError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for OctoError {}
+
+// compiles Octo source into a rom that loads at 0x200
+pub fn compile(source: &str) -> Result<Vec<u8>, OctoError> {
+    let mut compiler = Compiler::new(tokenize(source));
+    compiler.compile()?;
+    Ok(compiler.rom)
+}
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (i, line) in source.lines().enumerate() {
+        let line_text = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        for word in line_text.split_whitespace() {
+            tokens.push_back(Token {
+                text: word.to_string(),
+                line: i + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+// v0-vf, upper or lower case
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+            digit.to_digit(16).map(|value| value as u8)
+        }
+        _ => None,
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// a value a label will be patched into once it's defined
+enum FixupKind {
+    // the low 12 bits of the instruction
+    Address,
+    // the 16 bit word after i := long
+    LongAddress,
+}
+
+struct Fixup {
+    at: usize,
+    kind: FixupKind,
+    label: String,
+    line: usize,
+}
+
+enum Block {
+    // the address of the top of the loop, and the jumps out of it from any `while`s
+    Loop { start: u16, exits: Vec<usize> },
+    // the jump over the `begin` branch (or the `else` branch once it's been seen)
+    If { jump: usize, has_else: bool },
+}
+
+// the right hand side of a comparison or assignment
+enum Operand {
+    Register(u8),
+    Value(u8),
+}
+
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+const MAX_MACRO_DEPTH: usize = 64;
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    // memory from 0x200 up
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    macro_depth: usize,
+    // the line of the token being compiled, for errors
+    line: usize,
+}
+
+impl Compiler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        Compiler {
+            tokens,
+            rom: Vec::new(),
+            here: PROGRAM_START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            macro_depth: 0,
+            line: 1,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, OctoError> {
+        Err(OctoError {
+            line: self.line,
+            message,
+        })
+    }
+
+    fn compile(&mut self) -> Result<(), OctoError> {
+        // 0x200 jumps to main, unless main turns out to be right there (see define_label)
+        self.emit_address(0x1000, "main")?;
+        self.statements()?;
+
+        if let Some(block) = self.blocks.last() {
+            let message = match block {
+                Block::Loop { .. } => "loop is missing its again",
+                Block::If { .. } => "begin is missing its end",
+            };
+            return self.error(message.to_string());
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let address = match self.labels.get(&fixup.label) {
+                Some(&address) => address,
+                None if fixup.label == "main" => {
+                    return self.error("there's no : main to start from".to_string())
+                }
+                None => return self.error(format!("{} is never defined", fixup.label)),
+            };
+            match fixup.kind {
+                FixupKind::Address => {
+                    if address > 0xFFF {
+                        return self.error(format!(
+                            "{} is at {:#06x}, too far for a 12 bit address",
+                            fixup.label, address
+                        ));
+                    }
+                    self.rom[fixup.at] |= (address >> 8) as u8;
+                    self.rom[fixup.at + 1] = address as u8;
+                }
+                FixupKind::LongAddress => {
+                    self.rom[fixup.at..fixup.at + 2].copy_from_slice(&address.to_be_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn statements(&mut self) -> Result<(), OctoError> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String, OctoError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("unexpected end of the program".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), OctoError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("expected {} but found {}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), OctoError> {
+        let index = self.here - PROGRAM_START;
+        if self.here >= 0x10000 {
+            return self.error("the program doesn't fit in memory".to_string());
+        }
+        if self.rom.len() <= index {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, word: u16) -> Result<(), OctoError> {
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    // an instruction with a 12 bit address, which can be a label that isn't defined yet
+    fn emit_address(&mut self, opcode: u16, target: &str) -> Result<(), OctoError> {
+        match self.value(target) {
+            Some(address) if (0..=0xFFF).contains(&address) => self.emit(opcode | address as u16),
+            Some(address) => self.error(format!("{:#x} doesn't fit in 12 bits", address)),
+            None if is_name(target) => {
+                self.fixups.push(Fixup {
+                    at: self.here - PROGRAM_START,
+                    kind: FixupKind::Address,
+                    label: target.to_string(),
+                    line: self.line,
+                });
+                self.emit(opcode)
+            }
+            None => self.error(format!("{} isn't an address", target)),
+        }
+    }
+
+    // a number, a constant or a label that's already been defined
+    fn value(&self, text: &str) -> Option<i64> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|&address| address as i64))
+    }
+
+    fn byte(&mut self, text: &str) -> Result<u8, OctoError> {
+        match self.value(text) {
+            // negative numbers wrap, so -1 is 0xFF
+            Some(value) if (-128..=255).contains(&value) => Ok(value as u8),
+            Some(_) => self.error(format!("{} doesn't fit in a byte", text)),
+            None => self.error(format!("{} isn't a number or constant", text)),
+        }
+    }
+
+    fn nibble(&mut self, text: &str) -> Result<u16, OctoError> {
+        match self.value(text) {
+            Some(value) if (0..=15).contains(&value) => Ok(value as u16),
+            _ => self.error(format!("{} isn't a number from 0 to 15", text)),
+        }
+    }
+
+    fn register(&mut self, text: &str) -> Result<u16, OctoError> {
+        match self.try_register(text) {
+            Some(register) => Ok(register as u16),
+            None => self.error(format!("{} isn't a register", text)),
+        }
+    }
+
+    fn try_register(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn operand(&mut self, text: &str) -> Result<Operand, OctoError> {
+        match self.try_register(text) {
+            Some(register) => Ok(Operand::Register(register)),
+            None => Ok(Operand::Value(self.byte(text)?)),
+        }
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), OctoError> {
+        if self.labels.contains_key(&name) {
+            return self.error(format!("{} is already defined", name));
+        }
+        // when main is the first thing in the program there's no need to jump to it
+        if name == "main" && self.here == PROGRAM_START + 2 && self.rom.len() == 2 {
+            self.fixups.retain(|fixup| fixup.at != 0);
+            self.rom.clear();
+            self.here = PROGRAM_START;
+        }
+        self.labels.insert(name, self.here as u16);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), OctoError> {
+        let token = self.next()?;
+        if let Some(register) = self.try_register(&token) {
+            return self.register_statement(register as u16);
+        }
+        if parse_number(&token).is_some() || self.constants.contains_key(&token) {
+            let byte = self.byte(&token)?;
+            return self.emit_byte(byte);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(name)
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value_text = self.next()?;
+                let value = match self.value(&value_text) {
+                    Some(value) => value,
+                    None => return self.error(format!("{} isn't a number", value_text)),
+                };
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register_text = self.next()?;
+                let register = self.register(&register_text)?;
+                self.aliases.insert(name, register as u8);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":org" => {
+                let address = if self.peek() == Some("{") {
+                    self.calc()?
+                } else {
+                    let text = self.next()?;
+                    match self.value(&text) {
+                        Some(address) => address,
+                        None => return self.error(format!("{} isn't an address", text)),
+                    }
+                };
+                if !(PROGRAM_START as i64..0x10000).contains(&address) {
+                    return self.error(format!("can't :org to {:#x}", address));
+                }
+                self.here = address as usize;
+                Ok(())
+            }
+            ":byte" => {
+                let byte = if self.peek() == Some("{") {
+                    // the same range as a byte operand, negative numbers wrap
+                    let value = self.calc()?;
+                    if !(-128..=255).contains(&value) {
+                        return self.error(format!("{} doesn't fit in a byte", value));
+                    }
+                    value as u8
+                } else {
+                    let text = self.next()?;
+                    self.byte(&text)?
+                };
+                self.emit_byte(byte)
+            }
+            ";" | "return" => self.emit(0x00EE),
+            "clear" => self.emit(0x00E0),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "audio" => self.emit(0xF002),
+            "scroll-down" | "scroll-up" => {
+                let text = self.next()?;
+                let rows = self.nibble(&text)?;
+                let opcode = if token == "scroll-down" {
+                    0x00C0
+                } else {
+                    0x00D0
+                };
+                self.emit(opcode | rows)
+            }
+            "plane" => {
+                let text = self.next()?;
+                let planes = self.nibble(&text)?;
+                self.emit(0xF001 | planes << 8)
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let text = self.next()?;
+                let x = self.register(&text)?;
+                let opcode = match token.as_str() {
+                    "bcd" => 0xF033,
+                    "saveflags" => 0xF075,
+                    _ => 0xF085,
+                };
+                self.emit(opcode | x << 8)
+            }
+            "save" | "load" => {
+                let text = self.next()?;
+                let x = self.register(&text)?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let text = self.next()?;
+                    let y = self.register(&text)?;
+                    let opcode = if token == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(opcode | x << 8 | y << 4)
+                } else {
+                    let opcode = if token == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(opcode | x << 8)
+                }
+            }
+            "sprite" => {
+                let x_text = self.next()?;
+                let x = self.register(&x_text)?;
+                let y_text = self.next()?;
+                let y = self.register(&y_text)?;
+                let n_text = self.next()?;
+                let n = self.nibble(&n_text)?;
+                self.emit(0xD000 | x << 8 | y << 4 | n)
+            }
+            "jump" | "jump0" | "native" => {
+                let target = self.next()?;
+                let opcode = match token.as_str() {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                self.emit_address(opcode, &target)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let text = self.next()?;
+                let x = self.register(&text)?;
+                let opcode = match token.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(opcode | x << 8)
+            }
+            "i" => self.index_statement(),
+            "if" => self.if_statement(),
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    start: self.here as u16,
+                    exits: Vec::new(),
+                });
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_skip(condition, true)?;
+                let exit = self.here - PROGRAM_START;
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(exit),
+                    None => return self.error("while is outside of a loop".to_string()),
+                }
+                self.emit(0x1000)
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    self.emit(0x1000 | start)?;
+                    for exit in exits {
+                        self.patch_jump(exit)?;
+                    }
+                    Ok(())
+                }
+                _ => self.error("again without a loop".to_string()),
+            },
+            "else" => match self.blocks.pop() {
+                Some(Block::If {
+                    jump,
+                    has_else: false,
+                }) => {
+                    let end_jump = self.here - PROGRAM_START;
+                    self.emit(0x1000)?;
+                    self.patch_jump(jump)?;
+                    self.blocks.push(Block::If {
+                        jump: end_jump,
+                        has_else: true,
+                    });
+                    Ok(())
+                }
+                _ => self.error("else without an if ... begin".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => self.patch_jump(jump),
+                _ => self.error("end without an if ... begin".to_string()),
+            },
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token),
+            _ if is_name(&token) => self.emit_address(0x2000, &token),
+            _ => self.error(format!("{} isn't something Octo understands", token)),
+        }
+    }
+
+    // points the jump at `at` (relative to 0x200) to here
+    fn patch_jump(&mut self, at: usize) -> Result<(), OctoError> {
+        if self.here > 0xFFF {
+            return self.error("can't jump past 0xFFF".to_string());
+        }
+        self.rom[at] = 0x10 | (self.here >> 8) as u8;
+        self.rom[at + 1] = self.here as u8;
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), OctoError> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+        if op == ":=" {
+            match rhs.as_str() {
+                "key" => return self.emit(0xF00A | x << 8),
+                "delay" => return self.emit(0xF007 | x << 8),
+                "random" => {
+                    let mask_text = self.next()?;
+                    let mask = self.byte(&mask_text)? as u16;
+                    return self.emit(0xC000 | x << 8 | mask);
+                }
+                _ => (),
+            }
+        }
+
+        let opcode = match (op.as_str(), self.operand(&rhs)?) {
+            (":=", Operand::Register(y)) => 0x8000 | (y as u16) << 4,
+            (":=", Operand::Value(n)) => 0x6000 | n as u16,
+            ("+=", Operand::Register(y)) => 0x8004 | (y as u16) << 4,
+            ("+=", Operand::Value(n)) => 0x7000 | n as u16,
+            ("-=", Operand::Register(y)) => 0x8005 | (y as u16) << 4,
+            // there's no subtract immediate, but adding the two's complement wraps to the same
+            ("-=", Operand::Value(n)) => 0x7000 | n.wrapping_neg() as u16,
+            ("=-", Operand::Register(y)) => 0x8007 | (y as u16) << 4,
+            ("|=", Operand::Register(y)) => 0x8001 | (y as u16) << 4,
+            ("&=", Operand::Register(y)) => 0x8002 | (y as u16) << 4,
+            ("^=", Operand::Register(y)) => 0x8003 | (y as u16) << 4,
+            (">>=", Operand::Register(y)) => 0x8006 | (y as u16) << 4,
+            ("<<=", Operand::Register(y)) => 0x800E | (y as u16) << 4,
+            _ => return self.error(format!("can't {} {} to a register", op, rhs)),
+        };
+        self.emit(opcode | x << 8)
+    }
+
+    fn index_statement(&mut self) -> Result<(), OctoError> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+        match (op.as_str(), rhs.as_str()) {
+            ("+=", _) => {
+                let x = self.register(&rhs)?;
+                self.emit(0xF01E | x << 8)
+            }
+            (":=", "hex") | (":=", "bighex") => {
+                let text = self.next()?;
+                let x = self.register(&text)?;
+                let opcode = if rhs == "hex" { 0xF029 } else { 0xF030 };
+                self.emit(opcode | x << 8)
+            }
+            (":=", "long") => {
+                let target = self.next()?;
+                self.emit(0xF000)?;
+                match self.value(&target) {
+                    Some(address) if (0..=0xFFFF).contains(&address) => self.emit(address as u16),
+                    Some(address) => self.error(format!("{:#x} doesn't fit in 16 bits", address)),
+                    None if is_name(&target) => {
+                        self.fixups.push(Fixup {
+                            at: self.here - PROGRAM_START,
+                            kind: FixupKind::LongAddress,
+                            label: target,
+                            line: self.line,
+                        });
+                        self.emit(0x0000)
+                    }
+                    None => self.error(format!("{} isn't an address", target)),
+                }
+            }
+            (":=", _) => self.emit_address(0xA000, &rhs),
+            _ => self.error(format!("can't {} {} to i", op, rhs)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, OctoError> {
+        let lhs = self.next()?;
+        let x = self.register(&lhs)?;
+        let x = x as u8;
+        let op = self.next()?;
+        match op.as_str() {
+            "key" => return Ok(Condition::Key(x)),
+            "-key" => return Ok(Condition::NotKey(x)),
+            _ => (),
+        }
+        let rhs_text = self.next()?;
+        let rhs = self.operand(&rhs_text)?;
+        let condition = match op.as_str() {
+            "==" => Condition::Equal(x, rhs),
+            "!=" => Condition::NotEqual(x, rhs),
+            "<" | ">" | "<=" | ">=" => {
+                // There's no comparison instruction, so these subtract in vf and look at the
+                // flag it leaves behind (like Octo does), which clobbers vf.
+                // For < and >= vf is 1 when vx >= rhs, for > and <= it's 1 when rhs >= vx
+                let vx_first = op == "<" || op == ">=";
+                match (rhs, vx_first) {
+                    (Operand::Register(y), true) => {
+                        self.emit(0x8F00 | (x as u16) << 4)?;
+                        self.emit(0x8F05 | (y as u16) << 4)?;
+                    }
+                    (Operand::Register(y), false) => {
+                        self.emit(0x8F00 | (y as u16) << 4)?;
+                        self.emit(0x8F05 | (x as u16) << 4)?;
+                    }
+                    (Operand::Value(n), true) => {
+                        self.emit(0x6F00 | n as u16)?;
+                        self.emit(0x8F07 | (x as u16) << 4)?;
+                    }
+                    (Operand::Value(n), false) => {
+                        self.emit(0x6F00 | n as u16)?;
+                        self.emit(0x8F05 | (x as u16) << 4)?;
+                    }
+                }
+                if op == "<" || op == ">" {
+                    Condition::Equal(0xF, Operand::Value(0))
+                } else {
+                    Condition::NotEqual(0xF, Operand::Value(0))
+                }
+            }
+            _ => return self.error(format!("{} isn't a comparison", op)),
+        };
+        Ok(condition)
+    }
+
+    // emits the instruction that skips the next one when the condition is `when`
+    fn emit_skip(&mut self, condition: Condition, when: bool) -> Result<(), OctoError> {
+        let (condition, when) = match condition {
+            Condition::NotEqual(x, rhs) => (Condition::Equal(x, rhs), !when),
+            Condition::NotKey(x) => (Condition::Key(x), !when),
+            condition => (condition, when),
+        };
+        let opcode = match (condition, when) {
+            (Condition::Equal(x, Operand::Register(y)), true) => {
+                0x5000 | (x as u16) << 8 | (y as u16) << 4
+            }
+            (Condition::Equal(x, Operand::Register(y)), false) => {
+                0x9000 | (x as u16) << 8 | (y as u16) << 4
+            }
+            (Condition::Equal(x, Operand::Value(n)), true) => 0x3000 | (x as u16) << 8 | n as u16,
+            (Condition::Equal(x, Operand::Value(n)), false) => 0x4000 | (x as u16) << 8 | n as u16,
+            (Condition::Key(x), true) => 0xE09E | (x as u16) << 8,
+            (Condition::Key(x), false) => 0xE0A1 | (x as u16) << 8,
+            _ => unreachable!("not conditions were turned around above"),
+        };
+        self.emit(opcode)
+    }
+
+    fn if_statement(&mut self) -> Result<(), OctoError> {
+        let condition = self.condition()?;
+        let token = self.next()?;
+        match token.as_str() {
+            // skip the statement unless the condition holds
+            "then" => {
+                self.emit_skip(condition, false)?;
+                self.statement()
+            }
+            // skip the jump past the block when the condition holds
+            "begin" => {
+                self.emit_skip(condition, true)?;
+                self.blocks.push(Block::If {
+                    jump: self.here - PROGRAM_START,
+                    has_else: false,
+                });
+                self.emit(0x1000)
+            }
+            _ => self.error(format!("expected then or begin but found {}", token)),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), OctoError> {
+        let name = self.next()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            params.push(token);
+        }
+        let body = self.braced_tokens()?;
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    // everything up to the } matching a { that's just been read
+    fn braced_tokens(&mut self) -> Result<Vec<Token>, OctoError> {
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => return self.error("{ is missing its }".to_string()),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => (),
+            }
+            body.push(token);
+        }
+    }
+
+    // substitutes the arguments into the macro body and puts it in front of the rest of the
+    // program to be compiled next
+    fn expand_macro(&mut self, name: &str) -> Result<(), OctoError> {
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            return self.error(format!("{} expands forever", name));
+        }
+        let param_count = self.macros[name].params.len();
+        let mut args = Vec::new();
+        for _ in 0..param_count {
+            args.push(self.next()?);
+        }
+        let line = self.line;
+        let mac = &self.macros[name];
+        let expanded: Vec<Token> = mac
+            .body
+            .iter()
+            .map(|token| {
+                let text = match mac.params.iter().position(|param| *param == token.text) {
+                    Some(i) => args[i].clone(),
+                    None => token.text.clone(),
+                };
+                Token { text, line }
+            })
+            .collect();
+
+        // compile the expansion on its own, so the depth only counts nested macros
+        let rest = std::mem::replace(&mut self.tokens, expanded.into());
+        self.macro_depth += 1;
+        let result = self.statements();
+        self.macro_depth -= 1;
+        self.tokens = rest;
+        result
+    }
+
+    // :calc's { expression }, with the usual arithmetic and bitwise operators, parentheses,
+    // numbers, constants, labels that have already been defined and HERE (the current address)
+    fn calc(&mut self) -> Result<i64, OctoError> {
+        self.expect("{")?;
+        let tokens = self.braced_tokens()?;
+        let mut position = 0;
+        let value = self.calc_expression(&tokens, &mut position, 0)?;
+        if position != tokens.len() {
+            return self.error(format!("unexpected {} in :calc", tokens[position].text));
+        }
+        Ok(value)
+    }
+
+    fn calc_expression(
+        &self,
+        tokens: &[Token],
+        position: &mut usize,
+        min_precedence: u8,
+    ) -> Result<i64, OctoError> {
+        let mut lhs = self.calc_term(tokens, position)?;
+        while let Some(token) = tokens.get(*position) {
+            let precedence = match token.text.as_str() {
+                "|" => 1,
+                "^" => 2,
+                "&" => 3,
+                "<<" | ">>" => 4,
+                "+" | "-" => 5,
+                "*" | "/" | "%" => 6,
+                _ => break,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            *position += 1;
+            let rhs = self.calc_expression(tokens, position, precedence + 1)?;
+            let shift = u32::try_from(rhs).ok();
+            let value = match token.text.as_str() {
+                "|" => Some(lhs | rhs),
+                "^" => Some(lhs ^ rhs),
+                "&" => Some(lhs & rhs),
+                "<<" => shift.and_then(|shift| lhs.checked_shl(shift)),
+                ">>" => shift.and_then(|shift| lhs.checked_shr(shift)),
+                "+" => lhs.checked_add(rhs),
+                "-" => lhs.checked_sub(rhs),
+                "*" => lhs.checked_mul(rhs),
+                "/" | "%" if rhs == 0 => return self.error("division by zero".to_string()),
+                "/" => lhs.checked_div(rhs),
+                _ => lhs.checked_rem(rhs),
+            };
+            lhs = match value {
+                Some(value) => value,
+                None => {
+                    return self.error(format!(
+                        "{} {} {} is out of range in :calc",
+                        lhs, token.text, rhs
+                    ))
+                }
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn calc_term(&self, tokens: &[Token], position: &mut usize) -> Result<i64, OctoError> {
+        let token = match tokens.get(*position) {
+            Some(token) => token,
+            None => return self.error(":calc is missing a value".to_string()),
+        };
+        *position += 1;
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(tokens, position, 0)?;
+                match tokens.get(*position) {
+                    Some(token) if token.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => self.error("( is missing its )".to_string()),
+                }
+            }
+            "-" => {
+                let value = self.calc_term(tokens, position)?;
+                match value.checked_neg() {
+                    Some(value) => Ok(value),
+                    None => self.error(format!("-{} is out of range in :calc", value)),
+                }
+            }
+            "HERE" => Ok(self.here as i64),
+            text => match self.value(text) {
+                Some(value) => Ok(value),
+                None => self.error(format!("{} isn't known in :calc", text)),
+            },
+        }
+    }
+}
+
+// label and macro names, anything that could be a forward reference
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
@@ -0,0 +1,181 @@
+use serde_derive::{Deserialize, Serialize};
+
+// Interpreters over the years disagreed on how a handful of opcodes behave, and roms were
+// written against whichever one their author had. Each field picks one interpretation, the
+// defaults are what this emulator has always done.
+// https://github.com/Timendus/chip8-test-suite#quirks-test is a good rundown of them
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY and store the result in VX, rather than shifting VX in place
+    pub shift_uses_vy: bool,
+    // FX55/FX65 leave I pointing just past the last register stored/loaded
+    pub load_store_increments_i: bool,
+    // with load_store_increments_i, I only goes up by X, so it's left on the last register
+    // rather than past it. CHIP-48 had this off by one
+    pub load_store_increments_i_by_x: bool,
+    // BNNN jumps to XNN + VX rather than NNN + V0
+    pub jump_uses_vx: bool,
+    // sprites are cut off at the edges of the screen rather than wrapping around to the other side
+    pub clip_sprites: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    // XO-CHIP's 64K of memory rather than 4K, with F000 NNNN skipped over as the one 4 byte
+    // instruction it is there
+    pub xochip_memory: bool,
+}
+
+impl Quirks {
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+            xochip_memory: false,
+        }
+    }
+
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: true,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            xochip_memory: false,
+        }
+    }
+
+    pub fn superchip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            xochip_memory: false,
+        }
+    }
+
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            xochip_memory: true,
+        }
+    }
+
+    pub fn from_preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "vip" | "cosmac_vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" | "superchip" => Some(Quirks::superchip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+
+    // how much memory there is, in bytes
+    pub fn memory_size(&self) -> usize {
+        if self.xochip_memory {
+            0x10000
+        } else {
+            0x1000
+        }
+    }
+
+    // each quirk with whether it's on, by the name it has in the config
+    pub fn named(&self) -> [(&'static str, bool); 7] {
+        [
+            ("shift_uses_vy", self.shift_uses_vy),
+            ("load_store_increments_i", self.load_store_increments_i),
+            (
+                "load_store_increments_i_by_x",
+                self.load_store_increments_i_by_x,
+            ),
+            ("jump_uses_vx", self.jump_uses_vx),
+            ("clip_sprites", self.clip_sprites),
+            ("vf_reset", self.vf_reset),
+            ("xochip_memory", self.xochip_memory),
+        ]
+    }
+
+    // the names of the quirks that are on in one and off in the other
+    pub fn differences(&self, other: &Quirks) -> Vec<&'static str> {
+        self.named()
+            .iter()
+            .zip(other.named().iter())
+            .filter(|(ours, theirs)| ours.1 != theirs.1)
+            .map(|(ours, _)| ours.0)
+            .collect()
+    }
+
+    pub fn set_named(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let quirk = match name {
+            "shift_uses_vy" => &mut self.shift_uses_vy,
+            "load_store_increments_i" => &mut self.load_store_increments_i,
+            "load_store_increments_i_by_x" => &mut self.load_store_increments_i_by_x,
+            "jump_uses_vx" => &mut self.jump_uses_vx,
+            "clip_sprites" => &mut self.clip_sprites,
+            "vf_reset" => &mut self.vf_reset,
+            "xochip_memory" => &mut self.xochip_memory,
+            _ => return Err(format!("unknown quirk {}", name)),
+        };
+        *quirk = value;
+        Ok(())
+    }
+}
+
+// the [quirks] table in chip8_rust_config.toml, a preset with any individual quirks overridden
+#[derive(Debug, Default, Deserialize)]
+pub struct QuirksConfig {
+    pub preset: Option<String>,
+    pub shift_uses_vy: Option<bool>,
+    pub load_store_increments_i: Option<bool>,
+    pub load_store_increments_i_by_x: Option<bool>,
+    pub jump_uses_vx: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    pub vf_reset: Option<bool>,
+    pub xochip_memory: Option<bool>,
+}
+
+impl QuirksConfig {
+    pub fn to_quirks(&self) -> Result<Quirks, String> {
+        let mut quirks = match &self.preset {
+            Some(name) => Quirks::from_preset(name)
+                .ok_or_else(|| format!("unknown quirks preset {}", name))?,
+            None => Quirks::default(),
+        };
+        if let Some(value) = self.shift_uses_vy {
+            quirks.shift_uses_vy = value;
+        }
+        if let Some(value) = self.load_store_increments_i {
+            quirks.load_store_increments_i = value;
+        }
+        if let Some(value) = self.load_store_increments_i_by_x {
+            quirks.load_store_increments_i_by_x = value;
+        }
+        if let Some(value) = self.jump_uses_vx {
+            quirks.jump_uses_vx = value;
+        }
+        if let Some(value) = self.clip_sprites {
+            quirks.clip_sprites = value;
+        }
+        if let Some(value) = self.vf_reset {
+            quirks.vf_reset = value;
+        }
+        if let Some(value) = self.xochip_memory {
+            quirks.xochip_memory = value;
+        }
+        Ok(quirks)
+    }
+}
@@ -0,0 +1,269 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::audio::{Beep, PatternPlayer};
+use crate::display::{Display, HIRES_HEIGHT, HIRES_WIDTH, PALETTE};
+use crate::emulator::Emulator;
+
+// Records gameplay one emulated frame at a time, from the emulator's display rather than the
+// window, so it works the same headless. Any mix of an animated GIF, a Y4M video and a WAV of
+// the beep can be recorded at once. Frames are always the hires size, lores pixels are drawn
+// 2x2, so a rom switching between the two doesn't change the size of the recording
+pub const WAV_SAMPLE_RATE: u32 = 44100;
+const FRAMES_PER_SECOND: u64 = 60;
+const SAMPLES_PER_FRAME: u32 = WAV_SAMPLE_RATE / FRAMES_PER_SECOND as u32;
+const WAV_VOLUME: f32 = 0.3;
+// browsers slow down anything shorter than this to 1/10th of a second, so quicker frames are
+// dropped instead
+const MIN_GIF_DELAY: u64 = 2;
+
+pub struct Recorder {
+    scale: usize,
+    gif: Option<GifRecording>,
+    y4m: Option<BufWriter<File>>,
+    wav: Option<WavRecording>,
+    // frames captured so far
+    frames: u64,
+}
+
+struct GifRecording {
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: usize,
+    // the frame on screen now, with the frame it appeared on. It isn't written until the
+    // screen changes, so a still screen becomes one long gif frame
+    pending: Option<(Vec<u8>, u64)>,
+    // how long the frames written so far last altogether, in hundredths of a second
+    written: u64,
+}
+
+struct WavRecording {
+    writer: hound::WavWriter<BufWriter<File>>,
+    player: PatternPlayer,
+}
+
+impl Recorder {
+    // scale is how many times bigger than the hires display the video is
+    pub fn new(scale: usize) -> Self {
+        Recorder {
+            scale: scale.max(1),
+            gif: None,
+            y4m: None,
+            wav: None,
+            frames: 0,
+        }
+    }
+
+    // record_gif, record_y4m and record_wav should be called before the first frame is
+    // captured, so everything being recorded starts together
+    pub fn record_gif(&mut self, path: &str) -> io::Result<()> {
+        let (width, height) = self.size();
+        let palette: Vec<u8> = PALETTE.iter().flat_map(|rgba| rgba[..3].to_vec()).collect();
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder =
+            gif::Encoder::new(file, width as u16, height as u16, &palette).map_err(gif_error)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(gif_error)?;
+        self.gif = Some(GifRecording {
+            encoder,
+            width,
+            pending: None,
+            written: 0,
+        });
+        Ok(())
+    }
+
+    pub fn record_y4m(&mut self, path: &str) -> io::Result<()> {
+        let (width, height) = self.size();
+        let mut file = BufWriter::new(File::create(path)?);
+        // full resolution colour, since the palette's colours would bleed into each other
+        // with the chroma subsampled
+        writeln!(
+            file,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+            width, height, FRAMES_PER_SECOND
+        )?;
+        self.y4m = Some(file);
+        Ok(())
+    }
+
+    pub fn record_wav(&mut self, path: &str) -> io::Result<()> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: WAV_SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        self.wav = Some(WavRecording {
+            writer: hound::WavWriter::create(path, spec).map_err(wav_error)?,
+            player: PatternPlayer::with_beep(Arc::new(Beep::default_beep())),
+        });
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.gif.is_some() || self.y4m.is_some() || self.wav.is_some()
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // call once per emulated frame, after it's run
+    pub fn capture_frame(&mut self, emu: &Emulator) -> io::Result<()> {
+        let canvas = draw_canvas(&emu.display, self.scale);
+        if let Some(gif) = &mut self.gif {
+            gif.capture(canvas.clone(), self.frames)?;
+        }
+        if let Some(y4m) = &mut self.y4m {
+            write_y4m_frame(y4m, &canvas)?;
+        }
+        if let Some(wav) = &mut self.wav {
+            let beeping = emu.sound_counter.load(Ordering::Relaxed) != 0;
+            if !beeping {
+                wav.player.reset();
+            }
+            for _ in 0..SAMPLES_PER_FRAME {
+                let sample = if beeping {
+                    wav.player
+                        .next_sample(&emu.audio_state, WAV_SAMPLE_RATE as f64)
+                        * WAV_VOLUME
+                } else {
+                    0.0
+                };
+                wav.writer
+                    .write_sample((sample * i16::MAX as f32) as i16)
+                    .map_err(wav_error)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // writes out whatever's left and closes the files
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(gif) = self.gif {
+            gif.finish(self.frames)?;
+        }
+        if let Some(mut y4m) = self.y4m {
+            y4m.flush()?;
+        }
+        if let Some(wav) = self.wav {
+            wav.writer.finalize().map_err(wav_error)?;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (HIRES_WIDTH * self.scale, HIRES_HEIGHT * self.scale)
+    }
+}
+
+impl GifRecording {
+    fn capture(&mut self, canvas: Vec<u8>, frame: u64) -> io::Result<()> {
+        match self.pending.take() {
+            Some((pending, since)) if pending == canvas => self.pending = Some((pending, since)),
+            Some((pending, since)) => {
+                if centiseconds(frame).saturating_sub(self.written) < MIN_GIF_DELAY {
+                    // too quick to show, this frame takes its place
+                    self.pending = Some((canvas, since));
+                } else {
+                    self.write(&pending, frame)?;
+                    self.pending = Some((canvas, frame));
+                }
+            }
+            None => self.pending = Some((canvas, frame)),
+        }
+        Ok(())
+    }
+
+    fn finish(mut self, frames: u64) -> io::Result<()> {
+        if let Some((pending, _)) = self.pending.take() {
+            self.write(&pending, frames)?;
+        }
+        // into_inner writes the gif's trailer
+        self.encoder.into_inner()?.flush()
+    }
+
+    // writes a frame that lasts until `until`, working the delay out from the total so far so
+    // rounding errors don't add up over a long recording
+    fn write(&mut self, canvas: &[u8], until: u64) -> io::Result<()> {
+        let delay = centiseconds(until)
+            .saturating_sub(self.written)
+            .max(MIN_GIF_DELAY);
+        let frame = gif::Frame {
+            width: self.width as u16,
+            height: (canvas.len() / self.width) as u16,
+            delay: delay.min(u16::MAX as u64) as u16,
+            buffer: Cow::Borrowed(canvas),
+            ..gif::Frame::default()
+        };
+        self.encoder.write_frame(&frame).map_err(gif_error)?;
+        self.written += delay;
+        Ok(())
+    }
+}
+
+// when the frame starts, in hundredths of a second, rounded to the nearest
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND
+}
+
+// the display as palette indexes, scaled up to the recording's size
+fn draw_canvas(display: &Display, scale: usize) -> Vec<u8> {
+    let zoom = scale * HIRES_WIDTH / display.width();
+    let mut canvas = Vec::with_capacity(HIRES_WIDTH * HIRES_HEIGHT * scale * scale);
+    for y in 0..display.height() {
+        let mut row = Vec::with_capacity(HIRES_WIDTH * scale);
+        for x in 0..display.width() {
+            let planes = display.get_pixel_planes(x, y);
+            row.resize(row.len() + zoom, planes);
+        }
+        for _ in 0..zoom {
+            canvas.extend_from_slice(&row);
+        }
+    }
+    canvas
+}
+
+fn write_y4m_frame<W: Write>(writer: &mut W, canvas: &[u8]) -> io::Result<()> {
+    let colours: Vec<[u8; 3]> = PALETTE.iter().map(to_yuv).collect();
+    // Y, U and V each have a plane of their own
+    let mut planes = vec![Vec::new(), Vec::new(), Vec::new()];
+    for pixel in canvas {
+        for (plane, value) in planes.iter_mut().zip(&colours[*pixel as usize]) {
+            plane.push(*value);
+        }
+    }
+    writer.write_all(b"FRAME\n")?;
+    for plane in planes {
+        writer.write_all(&plane)?;
+    }
+    Ok(())
+}
+
+// BT.601 in the 16-235 range, which is what players assume Y4M is
+fn to_yuv(rgba: &[u8; 4]) -> [u8; 3] {
+    let (r, g, b) = (rgba[0] as f32, rgba[1] as f32, rgba[2] as f32);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let u = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let v = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    [y.round() as u8, u.round() as u8, v.round() as u8]
+}
+
+fn gif_error(error: gif::EncodingError) -> io::Error {
+    match error {
+        gif::EncodingError::Io(e) => e,
+        gif::EncodingError::Format(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+fn wav_error(error: hound::Error) -> io::Error {
+    match error {
+        hound::Error::IoError(e) => e,
+        other => io::Error::new(io::ErrorKind::InvalidData, other.to_string()),
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+
+use crate::emulator::Emulator;
+use crate::savestate::SaveState;
+
+// Keeps the last few seconds of emulator states so they can be played back in reverse.
+// A snapshot is taken every frame. The newest is kept whole, every older one is stored as a
+// delta against the snapshot that came after it, and as most of memory doesn't change from one
+// frame to the next the deltas are tiny
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    // oldest at the front, each one turns the snapshot after it back into itself
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    // capacity is how many frames can be rewound
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // the number of snapshots held, including the newest
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    pub fn record(&mut self, emulator: &Emulator) {
+        if self.capacity == 0 {
+            return;
+        }
+        let snapshot = emulator.snapshot().encode();
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&snapshot, &previous));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    // puts the emulator back to the newest snapshot and drops it, so calling this every frame
+    // plays the history backwards
+    // returns false once there's nothing left to rewind to
+    pub fn rewind(&mut self, emulator: &mut Emulator) -> bool {
+        let snapshot = match self.latest.take() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        self.latest = self
+            .deltas
+            .pop_back()
+            .map(|delta| apply_delta(&snapshot, &delta));
+        // we only ever hold snapshots we encoded ourselves
+        emulator.restore(SaveState::decode(&snapshot).unwrap());
+        true
+    }
+}
+
+const DELTA_FULL: u8 = 0;
+const DELTA_XOR: u8 = 1;
+
+// Describes how to get from `from` to `to`. When they're the same length (nearly always, they
+// only differ when the stack depth or screen resolution changes) it's the XOR of the two with
+// runs of unchanged bytes collapsed, as a series of (unchanged run length, changed run length,
+// changed bytes) with the lengths as LEB128 varints. Otherwise it's just a copy of `to`
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    if from.len() != to.len() {
+        delta.push(DELTA_FULL);
+        delta.extend_from_slice(to);
+        return delta;
+    }
+    delta.push(DELTA_XOR);
+    let mut i = 0;
+    while i < from.len() {
+        let unchanged_start = i;
+        while i < from.len() && from[i] == to[i] {
+            i += 1;
+        }
+        let changed_start = i;
+        while i < from.len() && from[i] != to[i] {
+            i += 1;
+        }
+        write_varint(&mut delta, changed_start - unchanged_start);
+        write_varint(&mut delta, i - changed_start);
+        delta.extend(
+            from[changed_start..i]
+                .iter()
+                .zip(&to[changed_start..i])
+                .map(|(a, b)| a ^ b),
+        );
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    if delta[0] == DELTA_FULL {
+        return delta[1..].to_vec();
+    }
+    let mut to = from.to_vec();
+    let mut position = 1;
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_varint(delta, &mut position);
+        let changed = read_varint(delta, &mut position);
+        for (byte, xor) in to[i..i + changed]
+            .iter_mut()
+            .zip(&delta[position..position + changed])
+        {
+            *byte ^= xor;
+        }
+        i += changed;
+        position += changed;
+    }
+    to
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
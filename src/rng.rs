@@ -0,0 +1,104 @@
+use serde_derive::{Deserialize, Serialize};
+
+// The random number generator behind CXNN. It's owned by the emulator and seeded explicitly so a
+// run can be reproduced exactly, and it's plain data so it can go into save states.
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum RngMode {
+    // xorshift64*, fast and plenty random for games
+    Xorshift,
+    // the algorithm the original COSMAC VIP interpreter used, see next_cosmac_vip_byte
+    CosmacVip,
+}
+
+impl RngMode {
+    // by the name it has in the config and on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xorshift" => Some(RngMode::Xorshift),
+            "vip" | "cosmac_vip" => Some(RngMode::CosmacVip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RngMode::Xorshift => "xorshift",
+            RngMode::CosmacVip => "vip",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Rng {
+    mode: RngMode,
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng::with_mode(RngMode::Xorshift, seed)
+    }
+
+    pub fn with_mode(mode: RngMode, seed: u64) -> Self {
+        let state = match mode {
+            // xorshift gets stuck on a zero state, splitmix spreads the seed out and
+            // never hands us zero for the small seeds people tend to pick
+            RngMode::Xorshift => splitmix64(seed) | 1,
+            RngMode::CosmacVip => seed & 0xFFFF,
+        };
+        Rng { mode, seed, state }
+    }
+
+    // seeded from the system's entropy, for when the user hasn't asked for a seed
+    pub fn from_entropy() -> Self {
+        Rng::new(rand::random())
+    }
+
+    pub fn mode(&self) -> RngMode {
+        self.mode
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // the VIP algorithm reads from memory, so it needs to see it
+    pub fn next_byte(&mut self, memory: &[u8]) -> u8 {
+        match self.mode {
+            RngMode::Xorshift => self.next_xorshift_byte(),
+            RngMode::CosmacVip => self.next_cosmac_vip_byte(memory),
+        }
+    }
+
+    fn next_xorshift_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        // the high bits of xorshift64* are the good ones
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    // The VIP interpreter kept a 16 bit counter in a register (R9). CXNN bumps the counter, then
+    // reads the byte at 0x0100 + the counter's low byte (the VIP's interpreter code lived there),
+    // adds the counter's high byte to it, and stores the sum back as the new high byte. The sum
+    // is the random number.
+    // We don't ship the VIP interpreter, so to get the exact numbers a VIP would produce its
+    // second page has to be loaded into 0x100-0x1FF (see Emulator::load_vip_interpreter_page)
+    fn next_cosmac_vip_byte(&mut self, memory: &[u8]) -> u8 {
+        let counter = (self.state as u16).wrapping_add(1);
+        let low = counter & 0x00FF;
+        let high = counter >> 8;
+        let value = memory[0x100 + low as usize].wrapping_add(high as u8);
+        self.state = ((value as u64) << 8) | low as u64;
+        value
+    }
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,82 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audio::AUDIO_PATTERN_LEN;
+use crate::display::Display;
+#[cfg(feature = "savestates")]
+use crate::emulator::{EmulatorError, MAX_STACK_DEPTH};
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+
+// Save state files start with a magic number and a format version, followed by the bincode
+// encoded SaveState. Bump the version whenever SaveState changes, old states are rejected
+// rather than loaded into the wrong fields
+#[cfg(feature = "savestates")]
+const MAGIC: &[u8; 4] = b"C8SS";
+pub const SAVE_STATE_VERSION: u16 = 4;
+#[cfg(feature = "savestates")]
+const HEADER_LEN: usize = 6;
+
+// Everything needed to put the machine back exactly how it was. Settings the frontend owns
+// (instructions per frame, the timer mode, the key states of the host keyboard) aren't included
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveState {
+    pub registers: [u8; 16],
+    pub address_register: u16,
+    pub memory_space: Vec<u8>,
+    pub program_counter: u16,
+    pub subroutine_return_pointers: Vec<u16>,
+    pub timer_counter: u8,
+    pub sound_counter: u8,
+    pub audio_pattern: [u8; AUDIO_PATTERN_LEN],
+    pub audio_pattern_loaded: bool,
+    pub pitch: u8,
+    pub display: Display,
+    pub quirks: Quirks,
+    pub rng: Rng,
+    pub rpl_flags: [u8; 16],
+    pub end_loop_reached: bool,
+    pub key_states_last_cycle: [bool; 16],
+    pub waiting_for_key: bool,
+    pub reg_waiting_for_key: u8,
+    pub frame_count: u64,
+    pub frame_instruction_count: u32,
+}
+
+impl SaveState {
+    #[cfg(feature = "savestates")]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.memory_space.len() + 1024);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+        // serializing plain data into a vec can't fail
+        bincode::serialize_into(&mut bytes, self).unwrap();
+        bytes
+    }
+
+    #[cfg(feature = "savestates")]
+    pub fn decode(bytes: &[u8]) -> Result<SaveState, EmulatorError> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(EmulatorError::InvalidSaveState);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != SAVE_STATE_VERSION {
+            return Err(EmulatorError::UnsupportedSaveStateVersion { version });
+        }
+        let state: SaveState = bincode::deserialize(&bytes[HEADER_LEN..])
+            .map_err(|_| EmulatorError::InvalidSaveState)?;
+        if !state.is_consistent() {
+            return Err(EmulatorError::InvalidSaveState);
+        }
+        Ok(state)
+    }
+
+    // a state can decode fine and still be nonsense, anything the emulator indexes with has to
+    // be checked before it's restored or it'll panic later on
+    #[cfg(feature = "savestates")]
+    fn is_consistent(&self) -> bool {
+        self.memory_space.len() == self.quirks.memory_size()
+            && self.subroutine_return_pointers.len() <= MAX_STACK_DEPTH
+            && self.reg_waiting_for_key < 16
+            && self.display.is_valid()
+    }
+}
@@ -1,17 +1,46 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
 use std::sync::atomic::Ordering;
-
-use crate::emulator::{Emulator, InstructionResult};
+use std::sync::Arc;
+
+use crate::assembler::{assemble, assemble_file};
+use crate::audio::{pattern_playback_rate, AudioState, Beep, PatternPlayer, DEFAULT_AUDIO_PATTERN};
+use crate::cli::{self, Limit, MachineOptions};
+use crate::condition::Condition;
+use crate::debugger::Debugger;
+use crate::disasm::{disassemble_rom, reachable_instructions, Platform};
+#[cfg(feature = "screenshots")]
+use crate::display::{SET_COLOUR, UNSET_COLOUR};
+use crate::emulator::{Emulator, EmulatorError, InstructionResult, Opcode};
+use crate::input::InputScript;
+#[cfg(feature = "tools")]
+use crate::keymap::{HostKey, KeyMap, KeysConfig};
+#[cfg(feature = "movies")]
+use crate::movie::Movie;
+use crate::octo::compile;
+use crate::quirks::Quirks;
+#[cfg(feature = "tools")]
+use crate::quirks::QuirksConfig;
+#[cfg(feature = "recording")]
+use crate::recording::{Recorder, WAV_SAMPLE_RATE};
+#[cfg(feature = "savestates")]
+use crate::rewind::Rewind;
+use crate::rng::{Rng, RngMode};
+use crate::trace::{read_binary_trace, TraceFilter, TraceFormat, Tracer};
+use crate::tracediff::{diff, parse_reference, DiffResult};
+use crate::watchpoint::{Access, Watchpoint, WatchpointHit};
 
 #[test]
 fn jump_test() {
     // tests 0x1NNN
     let mut emu = Emulator::new_headless();
-    let result = emu.execute_instruction(0x1200.into());
+    let result = emu.execute_instruction(0x1200.into()).unwrap();
 
     assert!(result == InstructionResult::Working);
     assert!(emu.program_counter == 0x200);
 
-    let result = emu.execute_instruction(0x1500.into());
+    let result = emu.execute_instruction(0x1500.into()).unwrap();
     assert!(result == InstructionResult::Working);
     assert!(emu.program_counter == 0x500);
 }
@@ -20,14 +49,14 @@ fn jump_test() {
 fn subroutine_test() {
     // tests 0x2NNN and 0x00EE
     let mut emu = Emulator::new_headless();
-    let result = emu.execute_instruction(0x2500.into());
+    let result = emu.execute_instruction(0x2500.into()).unwrap();
 
     assert!(result == InstructionResult::Working);
     assert!(emu.program_counter == 0x500);
     assert!(emu.subroutine_return_pointers.len() == 1);
     assert!(emu.subroutine_return_pointers[0] == 0x202);
 
-    let result = emu.execute_instruction(0x00EE.into());
+    let result = emu.execute_instruction(0x00EE.into()).unwrap();
 
     assert!(result == InstructionResult::Working);
     assert!(emu.program_counter == 0x202);
@@ -35,22 +64,22 @@ fn subroutine_test() {
 
     let mut emu = Emulator::new_headless();
 
-    emu.execute_instruction(0x2100.into());
-    emu.execute_instruction(0x2150.into());
-    emu.execute_instruction(0x2200.into());
-    emu.execute_instruction(0x2250.into());
-    emu.execute_instruction(0x2300.into());
-    emu.execute_instruction(0x2350.into());
-    emu.execute_instruction(0x2400.into());
-    emu.execute_instruction(0x2450.into());
-    emu.execute_instruction(0x2500.into());
-    emu.execute_instruction(0x2550.into());
+    emu.execute_instruction(0x2100.into()).unwrap();
+    emu.execute_instruction(0x2150.into()).unwrap();
+    emu.execute_instruction(0x2200.into()).unwrap();
+    emu.execute_instruction(0x2250.into()).unwrap();
+    emu.execute_instruction(0x2300.into()).unwrap();
+    emu.execute_instruction(0x2350.into()).unwrap();
+    emu.execute_instruction(0x2400.into()).unwrap();
+    emu.execute_instruction(0x2450.into()).unwrap();
+    emu.execute_instruction(0x2500.into()).unwrap();
+    emu.execute_instruction(0x2550.into()).unwrap();
 
     assert!(emu.program_counter == 0x550);
     assert!(emu.subroutine_return_pointers.len() == 10);
 
     for _ in 0..10 {
-        emu.execute_instruction(0x00EE.into());
+        emu.execute_instruction(0x00EE.into()).unwrap();
     }
 
     assert!(emu.program_counter == 0x202);
@@ -61,13 +90,13 @@ fn subroutine_test() {
 fn the_3xnn_test() {
     let mut emu = Emulator::new_headless();
     emu.registers[2] = 0xFF;
-    emu.execute_instruction(0x3215.into());
+    emu.execute_instruction(0x3215.into()).unwrap();
 
     assert!(emu.program_counter == 0x202);
 
     let mut emu = Emulator::new_headless();
     emu.registers[2] = 0x15;
-    emu.execute_instruction(0x3215.into());
+    emu.execute_instruction(0x3215.into()).unwrap();
 
     assert!(emu.program_counter == 0x204);
 }
@@ -76,13 +105,13 @@ fn the_3xnn_test() {
 fn the_4xnn_test() {
     let mut emu = Emulator::new_headless();
     emu.registers[2] = 0xFF;
-    emu.execute_instruction(0x4215.into());
+    emu.execute_instruction(0x4215.into()).unwrap();
 
     assert!(emu.program_counter == 0x204);
 
     let mut emu = Emulator::new_headless();
     emu.registers[2] = 0x15;
-    emu.execute_instruction(0x4215.into());
+    emu.execute_instruction(0x4215.into()).unwrap();
 
     assert!(emu.program_counter == 0x202);
 }
@@ -92,14 +121,14 @@ fn the_5xy0_test() {
     let mut emu = Emulator::new_headless();
     emu.registers[1] = 0x00;
     emu.registers[2] = 0xFF;
-    emu.execute_instruction(0x5120.into());
+    emu.execute_instruction(0x5120.into()).unwrap();
 
     assert!(emu.program_counter == 0x202);
 
     let mut emu = Emulator::new_headless();
     emu.registers[1] = 0x15;
     emu.registers[2] = 0x15;
-    emu.execute_instruction(0x5120.into());
+    emu.execute_instruction(0x5120.into()).unwrap();
 
     assert!(emu.program_counter == 0x204);
 }
@@ -107,7 +136,7 @@ fn the_5xy0_test() {
 #[test]
 fn the_6xnn_test() {
     let mut emu = Emulator::new_headless();
-    emu.execute_instruction(0x60FF.into());
+    emu.execute_instruction(0x60FF.into()).unwrap();
     assert!(emu.registers[0] == 0xFF);
 }
 
@@ -115,10 +144,10 @@ fn the_6xnn_test() {
 fn the_7xnn_test() {
     let mut emu = Emulator::new_headless();
 
-    emu.execute_instruction(0x7001.into());
+    emu.execute_instruction(0x7001.into()).unwrap();
     assert!(emu.registers[0] == 0x1);
 
-    emu.execute_instruction(0x70FF.into());
+    emu.execute_instruction(0x70FF.into()).unwrap();
     assert!(emu.registers[0] == 0x0);
 }
 
@@ -127,7 +156,7 @@ fn the_8xy0_test() {
     let mut emu = Emulator::new_headless();
     emu.registers[0] = 0xFF;
 
-    emu.execute_instruction(0x8100.into());
+    emu.execute_instruction(0x8100.into()).unwrap();
     assert!(emu.registers[1] == 0xFF);
 }
 
@@ -137,7 +166,7 @@ fn the_8xy1_test() {
     emu.registers[0] = 0x1E;
     emu.registers[1] = 0xF0;
 
-    emu.execute_instruction(0x8101.into());
+    emu.execute_instruction(0x8101.into()).unwrap();
     assert!(emu.registers[1] == 0xFE);
 }
 
@@ -147,7 +176,7 @@ fn the_8xy2_test() {
     emu.registers[0] = 0x1E;
     emu.registers[1] = 0xF0;
 
-    emu.execute_instruction(0x8102.into());
+    emu.execute_instruction(0x8102.into()).unwrap();
     assert!(emu.registers[1] == 0x10);
 }
 
@@ -157,7 +186,7 @@ fn the_8xy3_test() {
     emu.registers[0] = 0x1E;
     emu.registers[1] = 0xF0;
 
-    emu.execute_instruction(0x8103.into());
+    emu.execute_instruction(0x8103.into()).unwrap();
     assert!(emu.registers[1] == 0xEE);
 }
 
@@ -168,7 +197,7 @@ fn the_8xy4_test() {
     emu.registers[1] = 0x01;
 
     // x_reg_plus_y_reg
-    emu.execute_instruction(0x8014.into());
+    emu.execute_instruction(0x8014.into()).unwrap();
     assert!(emu.registers[0] == 0x01);
     assert!(emu.registers[0xF] == 0x00);
 
@@ -177,7 +206,7 @@ fn the_8xy4_test() {
     emu.registers[1] = 0xFF;
 
     // x_reg_plus_y_reg
-    emu.execute_instruction(0x8014.into());
+    emu.execute_instruction(0x8014.into()).unwrap();
     assert!(emu.registers[0] == 0x00);
     assert!(emu.registers[0xF] == 0x01);
 }
@@ -189,7 +218,7 @@ fn the_8xy5_test() {
     emu.registers[1] = 0x01;
 
     // x_reg_plus_y_reg
-    emu.execute_instruction(0x8015.into());
+    emu.execute_instruction(0x8015.into()).unwrap();
     assert!(emu.registers[0] == 0x02);
     assert!(emu.registers[0xF] == 0x01);
 
@@ -198,7 +227,7 @@ fn the_8xy5_test() {
     emu.registers[1] = 0x01;
 
     // x_reg_plus_y_reg
-    emu.execute_instruction(0x8015.into());
+    emu.execute_instruction(0x8015.into()).unwrap();
     assert!(emu.registers[0] == 0xFF);
     assert!(emu.registers[0xF] == 0x00);
 }
@@ -209,7 +238,7 @@ fn the_8xy6_test() {
     emu.registers[0] = 0xFF;
     emu.registers[1] = 0x0;
 
-    emu.execute_instruction(0x8016.into());
+    emu.execute_instruction(0x8016.into()).unwrap();
     assert!(emu.registers[0] == 0x7F);
     assert!(emu.registers[0xF] == 1);
     assert!(emu.registers[1] == 0x0);
@@ -218,7 +247,7 @@ fn the_8xy6_test() {
     emu.registers[0] = 0xFE;
     emu.registers[1] = 0x0;
 
-    emu.execute_instruction(0x8016.into());
+    emu.execute_instruction(0x8016.into()).unwrap();
     assert!(emu.registers[0] == 0x7F);
     assert!(emu.registers[0xF] == 0);
     assert!(emu.registers[1] == 0x0);
@@ -231,7 +260,7 @@ fn the_8xy7_test() {
     emu.registers[1] = 0x01;
 
     // x_reg_plus_y_reg
-    emu.execute_instruction(0x8107.into());
+    emu.execute_instruction(0x8107.into()).unwrap();
     assert!(emu.registers[1] == 0x02);
     assert!(emu.registers[0xF] == 0x01);
 
@@ -240,7 +269,7 @@ fn the_8xy7_test() {
     emu.registers[1] = 0x01;
 
     // x_reg_plus_y_reg
-    emu.execute_instruction(0x8107.into());
+    emu.execute_instruction(0x8107.into()).unwrap();
     assert!(emu.registers[1] == 0xFF);
     assert!(emu.registers[0xF] == 0x00);
 }
@@ -251,7 +280,7 @@ fn the_8xye_test() {
     emu.registers[0] = 0xFF;
     emu.registers[1] = 0x0;
 
-    emu.execute_instruction(0x801E.into());
+    emu.execute_instruction(0x801E.into()).unwrap();
     assert!(emu.registers[0] == 0xFE);
     assert!(emu.registers[0xF] == 1);
     assert!(emu.registers[1] == 0x0);
@@ -260,7 +289,7 @@ fn the_8xye_test() {
     emu.registers[0] = 0x7F;
     emu.registers[1] = 0x0;
 
-    emu.execute_instruction(0x801E.into());
+    emu.execute_instruction(0x801E.into()).unwrap();
     assert!(emu.registers[0] == 0xFE);
     assert!(emu.registers[0xF] == 0);
     assert!(emu.registers[1] == 0x0);
@@ -272,14 +301,14 @@ fn the_9xy0_test() {
     emu.registers[0] = 0xFF;
     emu.registers[1] = 0x0;
 
-    emu.execute_instruction(0x9010.into());
+    emu.execute_instruction(0x9010.into()).unwrap();
     assert!(emu.program_counter == 0x204);
 
     let mut emu = Emulator::new_headless();
     emu.registers[0] = 0xFF;
     emu.registers[1] = 0xFF;
 
-    emu.execute_instruction(0x9010.into());
+    emu.execute_instruction(0x9010.into()).unwrap();
     assert!(emu.program_counter == 0x202);
 }
 
@@ -287,7 +316,7 @@ fn the_9xy0_test() {
 fn the_annn_test() {
     let mut emu = Emulator::new_headless();
     assert!(emu.address_register == 0x0);
-    emu.execute_instruction(0xA250.into());
+    emu.execute_instruction(0xA250.into()).unwrap();
     assert!(emu.address_register == 0x250);
 }
 
@@ -297,49 +326,110 @@ fn the_bnnn_test() {
     assert!(emu.program_counter == 0x200);
     emu.registers[0] = 0x0;
 
-    emu.execute_instruction(0xB250.into());
+    emu.execute_instruction(0xB250.into()).unwrap();
     assert!(emu.program_counter == 0x250);
 
     let mut emu = Emulator::new_headless();
     assert!(emu.program_counter == 0x200);
     emu.registers[0] = 0x3;
 
-    emu.execute_instruction(0xB250.into());
+    emu.execute_instruction(0xB250.into()).unwrap();
     assert!(emu.program_counter == 0x253);
 }
 
 #[test]
 fn the_cxnn_test() {
-    //test rng (can sometimes fail)
+    //test rng, seeded so it doesn't happen to roll a 0
     let mut emu = Emulator::new_headless();
+    emu.rng = Rng::new(1);
     assert!(emu.registers[0] == 0x0);
-    emu.execute_instruction(0xC0FF.into());
+    emu.execute_instruction(0xC0FF.into()).unwrap();
     assert!(emu.registers[0] != 0);
 
     //test mask
     let mut emu = Emulator::new_headless();
     assert!(emu.registers[0] == 0x0);
-    emu.execute_instruction(0xC0AA.into());
+    emu.execute_instruction(0xC0AA.into()).unwrap();
     assert!(emu.registers[0] & 0x55 == 0);
 }
 
+#[test]
+fn cxnn_seeded_rng_is_reproducible_test() {
+    let mut first = Emulator::new_headless();
+    let mut second = Emulator::new_headless();
+    first.rng = Rng::new(0xC8);
+    second.rng = Rng::new(0xC8);
+    for _ in 0..32 {
+        first.execute_instruction(0xC0FF.into()).unwrap();
+        second.execute_instruction(0xC0FF.into()).unwrap();
+        assert!(first.registers[0] == second.registers[0]);
+    }
+    assert!(first.rng.seed() == 0xC8);
+
+    // a different seed gives a different sequence
+    let mut other = Emulator::new_headless();
+    other.rng = Rng::new(0xC9);
+    let mut differs = false;
+    for _ in 0..32 {
+        first.execute_instruction(0xC0FF.into()).unwrap();
+        other.execute_instruction(0xC0FF.into()).unwrap();
+        differs |= first.registers[0] != other.registers[0];
+    }
+    assert!(differs);
+}
+
+#[test]
+fn cxnn_cosmac_vip_rng_test() {
+    let mut emu = Emulator::new_headless();
+    // counter starts at 0x12FF, so the first call reads 0x100 with a high byte of 0x13
+    emu.rng = Rng::with_mode(RngMode::CosmacVip, 0x12FF);
+    let mut interpreter = vec![0_u8; 0x200];
+    interpreter[0x100] = 0x20;
+    interpreter[0x101] = 0x05;
+    assert!(!emu.has_vip_interpreter_page());
+    emu.load_vip_interpreter_page(&interpreter);
+    assert!(emu.has_vip_interpreter_page());
+    // the fonts in the first page are left alone
+    assert!(emu.memory_space[0x00] == 0xF0);
+
+    emu.execute_instruction(0xC0FF.into()).unwrap();
+    assert!(emu.registers[0] == 0x33);
+    // the sum becomes the new high byte
+    emu.execute_instruction(0xC1FF.into()).unwrap();
+    assert!(emu.registers[1] == 0x38);
+    emu.execute_instruction(0xC20F.into()).unwrap();
+    assert!(emu.registers[2] == 0x08);
+}
+
 #[test]
 fn the_fx07_test() {
     let mut emu = Emulator::new_headless();
     emu.timer_counter.store(30, Ordering::Release);
 
     emu.registers[3] = 0;
-    emu.execute_instruction(0xF307.into());
+    emu.execute_instruction(0xF307.into()).unwrap();
     assert!(emu.registers[3] == 30);
 }
 
+#[test]
+fn the_fx0a_test() {
+    let mut emu = Emulator::new_headless();
+    emu.execute_instruction(0xF50A.into()).unwrap();
+    assert!(emu.is_waiting_for_key());
+
+    emu.key_states[0xF] = true;
+    emu.execute_instruction(0xF50A.into()).unwrap();
+    assert!(!emu.is_waiting_for_key());
+    assert!(emu.registers[5] == 0xF);
+}
+
 #[test]
 fn the_f315_test() {
     let mut emu = Emulator::new_headless();
     assert!(emu.timer_counter.load(Ordering::Acquire) == 0);
 
     emu.registers[3] = 30;
-    emu.execute_instruction(0xF315.into());
+    emu.execute_instruction(0xF315.into()).unwrap();
     assert!(emu.timer_counter.load(Ordering::Acquire) == 30);
 }
 
@@ -349,7 +439,7 @@ fn the_fx18_test() {
     assert!(emu.sound_counter.load(Ordering::Acquire) == 0);
 
     emu.registers[3] = 30;
-    emu.execute_instruction(0xF318.into());
+    emu.execute_instruction(0xF318.into()).unwrap();
     assert!(emu.sound_counter.load(Ordering::Acquire) == 30);
 }
 
@@ -359,14 +449,14 @@ fn the_fx1e_test() {
     let mut emu = Emulator::new_headless();
     assert!(emu.address_register == 0);
     emu.registers[5] = 0xFF;
-    emu.execute_instruction(0xF51E.into());
+    emu.execute_instruction(0xF51E.into()).unwrap();
     assert!(emu.address_register == 0xFF);
 
     //overflowing add
     let mut emu = Emulator::new_headless();
     emu.address_register = 0xFFFF;
     emu.registers[5] = 0x1;
-    emu.execute_instruction(0xF51E.into());
+    emu.execute_instruction(0xF51E.into()).unwrap();
     assert!(emu.address_register == 0x0);
 }
 
@@ -375,7 +465,7 @@ fn the_fx33_test() {
     let mut emu = Emulator::new_headless();
     emu.address_register = 0x205;
     emu.registers[3] = 223;
-    emu.execute_instruction(0xF333.into());
+    emu.execute_instruction(0xF333.into()).unwrap();
     assert!(emu.memory_space[0x205] == 2);
     assert!(emu.memory_space[0x206] == 2);
     assert!(emu.memory_space[0x207] == 3);
@@ -383,7 +473,7 @@ fn the_fx33_test() {
     let mut emu = Emulator::new_headless();
     emu.address_register = 0x205;
     emu.registers[3] = 13;
-    emu.execute_instruction(0xF333.into());
+    emu.execute_instruction(0xF333.into()).unwrap();
     assert!(emu.memory_space[0x205] == 0);
     assert!(emu.memory_space[0x206] == 1);
     assert!(emu.memory_space[0x207] == 3);
@@ -391,7 +481,7 @@ fn the_fx33_test() {
     let mut emu = Emulator::new_headless();
     emu.address_register = 0x205;
     emu.registers[3] = 5;
-    emu.execute_instruction(0xF333.into());
+    emu.execute_instruction(0xF333.into()).unwrap();
     assert!(emu.memory_space[0x205] == 0);
     assert!(emu.memory_space[0x206] == 0);
     assert!(emu.memory_space[0x207] == 5);
@@ -404,7 +494,7 @@ fn the_fx55_test() {
     for i in 0..0xF {
         emu.registers[i] = i as u8;
     }
-    emu.execute_instruction(0xFF55.into());
+    emu.execute_instruction(0xFF55.into()).unwrap();
     for i in 0..0xF {
         assert!(emu.memory_space[0x500 + i] == i as u8);
     }
@@ -418,9 +508,1895 @@ fn the_fx65_test() {
     for i in 0..0xF {
         emu.memory_space[0x500 + i] = i as u8;
     }
-    emu.execute_instruction(0xFF65.into());
+    emu.execute_instruction(0xFF65.into()).unwrap();
     for i in 0..0xF {
         assert!(emu.registers[i] == i as u8);
     }
     assert!(emu.address_register == 0x500);
 }
+
+#[test]
+fn the_dxyn_test() {
+    let mut emu = Emulator::new_headless();
+    // the built in zero sprite lives at address 0
+    emu.address_register = 0x0;
+    emu.registers[0] = 2;
+    emu.registers[1] = 3;
+
+    let result = emu.execute_instruction(0xD015.into()).unwrap();
+    assert!(result == InstructionResult::RedrawRequested);
+    assert!(emu.registers[0xF] == 0);
+    // top row of the zero is 0xF0
+    assert!(emu.display.get_pixel(2, 3));
+    assert!(emu.display.get_pixel(5, 3));
+    assert!(!emu.display.get_pixel(6, 3));
+    // second row is 0x90
+    assert!(emu.display.get_pixel(2, 4));
+    assert!(!emu.display.get_pixel(3, 4));
+    assert!(emu.display.get_pixel(5, 4));
+
+    // drawing it again erases it and reports the collision
+    emu.execute_instruction(0xD015.into()).unwrap();
+    assert!(emu.registers[0xF] == 1);
+    for y in 3..8 {
+        for x in 2..10 {
+            assert!(!emu.display.get_pixel(x, y));
+        }
+    }
+}
+
+#[test]
+fn the_00e0_test() {
+    let mut emu = Emulator::new_headless();
+    emu.address_register = 0x0;
+    emu.execute_instruction(0xD005.into()).unwrap();
+    assert!(emu.display.get_pixel(0, 0));
+
+    let result = emu.execute_instruction(0x00E0.into()).unwrap();
+    assert!(result == InstructionResult::RedrawRequested);
+    assert!(!emu.display.get_pixel(0, 0));
+
+    // nothing left to collide with
+    emu.execute_instruction(0xD005.into()).unwrap();
+    assert!(emu.registers[0xF] == 0);
+}
+
+#[test]
+fn emulated_timers_test() {
+    let mut emu = Emulator::new_headless();
+    emu.instructions_per_frame = 4;
+
+    emu.registers[3] = 2;
+    emu.execute_instruction(0xF315.into()).unwrap();
+    emu.execute_instruction(0xF318.into()).unwrap();
+    emu.execute_instruction(0x6000.into()).unwrap();
+    assert!(emu.timer_counter.load(Ordering::Acquire) == 2);
+    assert!(emu.sound_counter.load(Ordering::Acquire) == 2);
+
+    // fourth instruction finishes the frame
+    emu.execute_instruction(0x6000.into()).unwrap();
+    assert!(emu.frame_count == 1);
+    assert!(emu.timer_counter.load(Ordering::Acquire) == 1);
+    assert!(emu.sound_counter.load(Ordering::Acquire) == 1);
+
+    for _ in 0..8 {
+        emu.execute_instruction(0x6000.into()).unwrap();
+    }
+    assert!(emu.frame_count == 3);
+    assert!(emu.timer_counter.load(Ordering::Acquire) == 0);
+    assert!(emu.sound_counter.load(Ordering::Acquire) == 0);
+}
+
+#[test]
+fn run_frame_test() {
+    let mut emu = Emulator::new_headless();
+    emu.instructions_per_frame = 3;
+    // 0x200: V0 += 1, then jump back to 0x200
+    emu.memory_space[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+
+    emu.run_frame().unwrap();
+    assert!(emu.frame_count == 1);
+    assert!(emu.registers[0] == 2);
+    assert!(emu.program_counter == 0x202);
+
+    emu.run_frame().unwrap();
+    assert!(emu.frame_count == 2);
+    assert!(emu.registers[0] == 3);
+}
+
+#[test]
+fn malformed_opcode_test() {
+    let mut emu = Emulator::new_headless();
+    let result = emu.execute_instruction(0x800F.into());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::MalformedOpcode {
+            addr: 0x200,
+            opcode: 0x800F
+        })
+    ));
+
+    let mut emu = Emulator::new_headless();
+    emu.memory_space[0x200..0x202].copy_from_slice(&[0xE1, 0x00]);
+    let result = emu.execute_next_instruction();
+    assert!(matches!(
+        result,
+        Err(EmulatorError::MalformedOpcode {
+            addr: 0x200,
+            opcode: 0xE100
+        })
+    ));
+}
+
+#[test]
+fn stack_errors_test() {
+    let mut emu = Emulator::new_headless();
+    let result = emu.execute_instruction(0x00EE.into());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::StackUnderflow { addr: 0x200 })
+    ));
+
+    let mut emu = Emulator::new_headless();
+    for _ in 0..16 {
+        emu.execute_instruction(0x2300.into()).unwrap();
+    }
+    let result = emu.execute_instruction(0x2300.into());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::StackOverflow { addr: 0x300 })
+    ));
+}
+
+#[test]
+fn memory_out_of_bounds_test() {
+    let mut emu = Emulator::new_headless();
+    emu.address_register = 0xFFFE;
+    let result = emu.execute_instruction(0xF333.into());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::MemoryOutOfBounds { addr: 0x10000 })
+    ));
+
+    let mut emu = Emulator::new_headless();
+    emu.address_register = 0xFFFA;
+    let result = emu.execute_instruction(0xFF55.into());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::MemoryOutOfBounds { addr: 0x10009 })
+    ));
+    let result = emu.execute_instruction(0xFF65.into());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::MemoryOutOfBounds { addr: 0x10009 })
+    ));
+
+    let mut emu = Emulator::new_headless();
+    emu.address_register = 0xFFFC;
+    let result = emu.execute_instruction(0xD01F.into());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::MemoryOutOfBounds { addr: 0x1000A })
+    ));
+
+    let mut emu = Emulator::new_headless();
+    emu.program_counter = 0xFFFE;
+    let result = emu.execute_instruction(0x6000.into());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::MemoryOutOfBounds { addr: 0x10000 })
+    ));
+}
+
+#[test]
+fn load_program_test() {
+    let rom_path = std::env::temp_dir().join("chip8_rust_load_program_test.ch8");
+    std::fs::write(&rom_path, [0x60, 0x2A, 0x12, 0x02]).unwrap();
+    let mut emu = Emulator::new_headless();
+    let bytes_read = emu.load_program(rom_path.to_str().unwrap()).unwrap();
+    assert!(bytes_read == 4);
+    assert!(emu.memory_space[0x200..0x204] == [0x60, 0x2A, 0x12, 0x02]);
+
+    // 4kb of memory, or XO-CHIP's 64kb, less what's below 0x200
+    let rom_path = std::env::temp_dir().join("chip8_rust_rom_too_large_test.ch8");
+    std::fs::write(&rom_path, vec![0xAA; 0x1000]).unwrap();
+    let mut emu = Emulator::new_headless();
+    let result = emu.load_program(rom_path.to_str().unwrap());
+    assert!(matches!(
+        result,
+        Err(EmulatorError::RomTooLarge {
+            size: 0x1000,
+            max: 0xE00
+        })
+    ));
+    let mut emu = Emulator::new_headless();
+    emu.quirks = Quirks::xochip();
+    assert!(emu.load_program(rom_path.to_str().unwrap()).unwrap() == 0x1000);
+    let result = emu.load_rom(&vec![0xAA; 0x10000]);
+    assert!(matches!(
+        result,
+        Err(EmulatorError::RomTooLarge {
+            size: 0x10000,
+            max: 0xFE00
+        })
+    ));
+
+    let mut emu = Emulator::new_headless();
+    let result = emu.load_program("this/rom/does/not/exist.ch8");
+    assert!(matches!(result, Err(EmulatorError::Io(_))));
+}
+
+#[test]
+fn vf_reset_quirk_test() {
+    for opcode in [0x8011, 0x8012, 0x8013] {
+        let mut emu = Emulator::new_headless();
+        emu.registers[0xF] = 0x5;
+        emu.execute_instruction(opcode.into()).unwrap();
+        assert!(emu.registers[0xF] == 0x5);
+
+        let mut emu = Emulator::new_headless();
+        emu.quirks.vf_reset = true;
+        emu.registers[0xF] = 0x5;
+        emu.execute_instruction(opcode.into()).unwrap();
+        assert!(emu.registers[0xF] == 0x0);
+    }
+}
+
+#[test]
+fn shift_quirk_test() {
+    let mut emu = Emulator::new_headless();
+    emu.quirks.shift_uses_vy = true;
+    emu.registers[0] = 0x0;
+    emu.registers[1] = 0x03;
+    emu.execute_instruction(0x8016.into()).unwrap();
+    assert!(emu.registers[0] == 0x01);
+    assert!(emu.registers[0xF] == 1);
+    assert!(emu.registers[1] == 0x03);
+
+    let mut emu = Emulator::new_headless();
+    emu.quirks.shift_uses_vy = true;
+    emu.registers[0] = 0x0;
+    emu.registers[1] = 0x81;
+    emu.execute_instruction(0x801E.into()).unwrap();
+    assert!(emu.registers[0] == 0x02);
+    assert!(emu.registers[0xF] == 1);
+    assert!(emu.registers[1] == 0x81);
+
+    // the flag wins when VF is also the destination
+    let mut emu = Emulator::new_headless();
+    emu.registers[0xF] = 0x02;
+    emu.execute_instruction(0x8FF6.into()).unwrap();
+    assert!(emu.registers[0xF] == 0);
+}
+
+#[test]
+fn load_store_quirk_test() {
+    let mut emu = Emulator::new_headless();
+    emu.quirks.load_store_increments_i = true;
+    emu.address_register = 0x500;
+    emu.execute_instruction(0xF355.into()).unwrap();
+    assert!(emu.address_register == 0x504);
+    emu.execute_instruction(0xF065.into()).unwrap();
+    assert!(emu.address_register == 0x505);
+
+    // CHIP-48 stops one short
+    let mut emu = Emulator::new_headless();
+    emu.quirks = Quirks::chip48();
+    emu.address_register = 0x500;
+    emu.execute_instruction(0xF355.into()).unwrap();
+    assert!(emu.address_register == 0x503);
+    emu.execute_instruction(0xF065.into()).unwrap();
+    assert!(emu.address_register == 0x503);
+    assert!(Quirks::chip48() != Quirks::superchip());
+}
+
+#[test]
+fn jump_quirk_test() {
+    let mut emu = Emulator::new_headless();
+    emu.quirks.jump_uses_vx = true;
+    emu.registers[0] = 0x1;
+    emu.registers[2] = 0x3;
+    emu.execute_instruction(0xB250.into()).unwrap();
+    assert!(emu.program_counter == 0x253);
+}
+
+#[test]
+fn sprite_wrap_and_clip_test() {
+    // the eight sprite is 0xF0 0x90 0xF0 0x90 0xF0
+    let mut emu = Emulator::new_headless();
+    emu.address_register = 8 * 5;
+    emu.registers[0] = 62;
+    emu.registers[1] = 30;
+    emu.execute_instruction(0xD015.into()).unwrap();
+    assert!(emu.display.get_pixel(62, 30));
+    assert!(emu.display.get_pixel(63, 30));
+    assert!(emu.display.get_pixel(0, 30));
+    assert!(emu.display.get_pixel(1, 30));
+    assert!(emu.display.get_pixel(62, 0));
+    assert!(emu.display.get_pixel(1, 2));
+    // nothing leaks onto the next row
+    assert!(!emu.display.get_pixel(0, 31));
+
+    let mut emu = Emulator::new_headless();
+    emu.quirks.clip_sprites = true;
+    emu.address_register = 8 * 5;
+    emu.registers[0] = 62;
+    emu.registers[1] = 30;
+    emu.execute_instruction(0xD015.into()).unwrap();
+    assert!(emu.display.get_pixel(62, 30));
+    assert!(emu.display.get_pixel(62, 31));
+    assert!(!emu.display.get_pixel(0, 30));
+    assert!(!emu.display.get_pixel(62, 0));
+
+    // the starting position wraps even when clipping
+    let mut emu = Emulator::new_headless();
+    emu.quirks.clip_sprites = true;
+    emu.address_register = 8 * 5;
+    emu.registers[0] = 64 + 2;
+    emu.registers[1] = 32 + 1;
+    emu.execute_instruction(0xD015.into()).unwrap();
+    assert!(emu.display.get_pixel(2, 1));
+}
+
+#[test]
+#[cfg(feature = "tools")]
+fn quirks_config_test() {
+    let config: QuirksConfig = toml::from_str("preset = \"vip\"\nclip_sprites = false").unwrap();
+    let quirks = config.to_quirks().unwrap();
+    assert!(quirks.vf_reset);
+    assert!(quirks.shift_uses_vy);
+    assert!(!quirks.clip_sprites);
+
+    let config: QuirksConfig = toml::from_str("").unwrap();
+    assert!(config.to_quirks().unwrap() == Quirks::default());
+
+    let config: QuirksConfig = toml::from_str("preset = \"nope\"").unwrap();
+    assert!(config.to_quirks().is_err());
+
+    assert!(Quirks::from_preset("schip") == Some(Quirks::superchip()));
+    assert!(Quirks::from_preset("xochip") == Some(Quirks::xochip()));
+    assert!(Quirks::from_preset("chip48") == Some(Quirks::chip48()));
+}
+
+#[test]
+fn hires_test() {
+    let mut emu = Emulator::new_headless();
+    assert!(emu.display.width() == 64);
+    assert!(emu.display.height() == 32);
+
+    let result = emu.execute_instruction(0x00FF.into()).unwrap();
+    assert!(result == InstructionResult::RedrawRequested);
+    assert!(emu.display.width() == 128);
+    assert!(emu.display.height() == 64);
+
+    // sprites can now be drawn on the bottom right of the bigger screen
+    emu.address_register = 0x0;
+    emu.registers[0] = 120;
+    emu.registers[1] = 59;
+    emu.execute_instruction(0xD015.into()).unwrap();
+    assert!(emu.display.get_pixel(120, 59));
+    assert!(emu.display.get_pixel(123, 63));
+
+    emu.execute_instruction(0x00FE.into()).unwrap();
+    assert!(emu.display.width() == 64);
+    assert!(emu.display.height() == 32);
+}
+
+#[test]
+fn the_dxy0_test() {
+    let mut emu = Emulator::new_headless();
+    emu.execute_instruction(0x00FF.into()).unwrap();
+    // a 16x16 sprite, left column and top row set
+    emu.memory_space[0x300] = 0xFF;
+    emu.memory_space[0x301] = 0xFF;
+    for row in 1..16 {
+        emu.memory_space[0x300 + row * 2] = 0x80;
+    }
+    emu.address_register = 0x300;
+    emu.registers[0] = 10;
+    emu.registers[1] = 20;
+    emu.execute_instruction(0xD010.into()).unwrap();
+    assert!(emu.registers[0xF] == 0);
+    assert!(emu.display.get_pixel(10, 20));
+    assert!(emu.display.get_pixel(25, 20));
+    assert!(!emu.display.get_pixel(26, 20));
+    assert!(emu.display.get_pixel(10, 35));
+    assert!(!emu.display.get_pixel(11, 35));
+    assert!(!emu.display.get_pixel(10, 36));
+
+    emu.execute_instruction(0xD010.into()).unwrap();
+    assert!(emu.registers[0xF] == 1);
+    assert!(!emu.display.get_pixel(10, 20));
+}
+
+#[test]
+fn scroll_test() {
+    let mut emu = Emulator::new_headless();
+    emu.execute_instruction(0x00FF.into()).unwrap();
+    // draw a single pixel with the top of the one sprite (0x20)
+    emu.memory_space[0x300] = 0x80;
+    emu.address_register = 0x300;
+    emu.registers[0] = 10;
+    emu.registers[1] = 10;
+    emu.execute_instruction(0xD011.into()).unwrap();
+
+    emu.execute_instruction(0x00C3.into()).unwrap();
+    assert!(!emu.display.get_pixel(10, 10));
+    assert!(emu.display.get_pixel(10, 13));
+
+    emu.execute_instruction(0x00FB.into()).unwrap();
+    assert!(!emu.display.get_pixel(10, 13));
+    assert!(emu.display.get_pixel(14, 13));
+
+    emu.execute_instruction(0x00FC.into()).unwrap();
+    emu.execute_instruction(0x00FC.into()).unwrap();
+    assert!(emu.display.get_pixel(6, 13));
+
+    // scrolled off the edge is gone for good
+    emu.execute_instruction(0x00FC.into()).unwrap();
+    emu.execute_instruction(0x00FC.into()).unwrap();
+    emu.execute_instruction(0x00FB.into()).unwrap();
+    emu.execute_instruction(0x00FB.into()).unwrap();
+    for x in 0..128 {
+        assert!(!emu.display.get_pixel(x, 13));
+    }
+}
+
+#[test]
+fn the_fx30_test() {
+    let mut emu = Emulator::new_headless();
+    emu.registers[4] = 0x8;
+    emu.execute_instruction(0xF430.into()).unwrap();
+    assert!(emu.address_register == 0x50 + 8 * 10);
+    assert!(
+        emu.memory_space[0x50..0x5A]
+            == [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF]
+    );
+}
+
+#[test]
+fn rpl_flags_test() {
+    let mut emu = Emulator::new_headless();
+    for i in 0..8 {
+        emu.registers[i] = i as u8 + 1;
+    }
+    emu.execute_instruction(0xF775.into()).unwrap();
+    assert!(emu.rpl_flags[..8] == [1, 2, 3, 4, 5, 6, 7, 8]);
+
+    emu.registers = [0; 16];
+    emu.execute_instruction(0xF385.into()).unwrap();
+    assert!(emu.registers[..5] == [1, 2, 3, 4, 0]);
+}
+
+#[test]
+fn the_00fd_test() {
+    let mut emu = Emulator::new_headless();
+    let result = emu.execute_instruction(0x00FD.into()).unwrap();
+    assert!(result == InstructionResult::Terminated);
+}
+
+#[test]
+fn xo_chip_memory_test() {
+    let mut emu = Emulator::new_headless();
+    assert!(emu.memory_space.len() == 0x1000);
+    // memory's sized for the quirks when the rom's loaded
+    emu.quirks = Quirks::xochip();
+    emu.load_rom(&[]).unwrap();
+    assert!(emu.memory_space.len() == 0x10000);
+
+    emu.memory_space[0x202..0x204].copy_from_slice(&[0xE0, 0x00]);
+    let result = emu.execute_instruction(0xF000.into()).unwrap();
+    assert!(result == InstructionResult::Working);
+    assert!(emu.address_register == 0xE000);
+    assert!(emu.program_counter == 0x204);
+
+    emu.registers[0] = 0x42;
+    emu.execute_instruction(0xF055.into()).unwrap();
+    assert!(emu.memory_space[0xE000] == 0x42);
+}
+
+#[test]
+fn skip_long_instruction_test() {
+    let xochip = || {
+        let mut emu = Emulator::new_headless();
+        emu.quirks = Quirks::xochip();
+        emu.load_rom(&[0x00, 0xE0, 0xF0, 0x00, 0x12, 0x34]).unwrap();
+        emu
+    };
+    let mut emu = xochip();
+    emu.execute_instruction(0x3000.into()).unwrap();
+    assert!(emu.program_counter == 0x206);
+
+    let mut emu = xochip();
+    emu.execute_instruction(0x4000.into()).unwrap();
+    assert!(emu.program_counter == 0x202);
+
+    let mut emu = xochip();
+    emu.memory_space[0x202..0x204].copy_from_slice(&[0x60, 0x00]);
+    emu.execute_instruction(0x3000.into()).unwrap();
+    assert!(emu.program_counter == 0x204);
+
+    // F000 is only 4 bytes long on XO-CHIP, anywhere else it's skipped like any other
+    let mut emu = Emulator::new_headless();
+    emu.memory_space[0x202..0x206].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+    emu.execute_instruction(0x3000.into()).unwrap();
+    assert!(emu.program_counter == 0x204);
+}
+
+#[test]
+fn the_5xy2_5xy3_test() {
+    let mut emu = Emulator::new_headless();
+    emu.address_register = 0x500;
+    for i in 0..16 {
+        emu.registers[i] = i as u8 * 2;
+    }
+    emu.execute_instruction(0x5242.into()).unwrap();
+    assert!(emu.memory_space[0x500..0x503] == [4, 6, 8]);
+    assert!(emu.address_register == 0x500);
+
+    // reversed range stores backwards
+    emu.execute_instruction(0x5422.into()).unwrap();
+    assert!(emu.memory_space[0x500..0x503] == [8, 6, 4]);
+
+    emu.registers = [0; 16];
+    emu.execute_instruction(0x5683.into()).unwrap();
+    assert!(emu.registers[6..9] == [8, 6, 4]);
+    assert!(emu.registers[5] == 0);
+    assert!(emu.registers[9] == 0);
+    assert!(emu.address_register == 0x500);
+}
+
+#[test]
+fn bitplanes_test() {
+    let mut emu = Emulator::new_headless();
+    emu.memory_space[0x300] = 0x80;
+    emu.memory_space[0x301] = 0xC0;
+    emu.address_register = 0x300;
+
+    // draw with both planes selected, the second plane gets the following byte
+    emu.execute_instruction(0xF301.into()).unwrap();
+    emu.execute_instruction(0xD011.into()).unwrap();
+    assert!(emu.display.get_pixel_planes(0, 0) == 0b11);
+    assert!(emu.display.get_pixel_planes(1, 0) == 0b10);
+    assert!(emu.registers[0xF] == 0);
+
+    // clearing only the first plane leaves the second alone
+    emu.execute_instruction(0xF101.into()).unwrap();
+    emu.execute_instruction(0x00E0.into()).unwrap();
+    assert!(emu.display.get_pixel_planes(0, 0) == 0b10);
+    assert!(emu.display.get_pixel_planes(1, 0) == 0b10);
+
+    // collisions are per plane
+    emu.execute_instruction(0xD011.into()).unwrap();
+    assert!(emu.registers[0xF] == 0);
+    emu.execute_instruction(0xF201.into()).unwrap();
+    emu.execute_instruction(0xD011.into()).unwrap();
+    assert!(emu.registers[0xF] == 1);
+    assert!(emu.display.get_pixel_planes(0, 0) == 0b01);
+
+    // with no planes selected nothing is drawn
+    emu.execute_instruction(0xF001.into()).unwrap();
+    emu.execute_instruction(0xD011.into()).unwrap();
+    assert!(emu.display.get_pixel_planes(0, 0) == 0b01);
+}
+
+#[test]
+fn the_00dn_test() {
+    let mut emu = Emulator::new_headless();
+    emu.memory_space[0x300] = 0x80;
+    emu.address_register = 0x300;
+    emu.registers[0] = 5;
+    emu.registers[1] = 10;
+    emu.execute_instruction(0xD011.into()).unwrap();
+
+    emu.execute_instruction(0x00D4.into()).unwrap();
+    assert!(!emu.display.get_pixel(5, 10));
+    assert!(emu.display.get_pixel(5, 6));
+
+    // only the selected planes scroll
+    emu.execute_instruction(0xF201.into()).unwrap();
+    emu.execute_instruction(0x00D4.into()).unwrap();
+    assert!(emu.display.get_pixel(5, 6));
+}
+
+#[test]
+fn the_f002_test() {
+    let mut emu = Emulator::new_headless();
+    assert!(emu.audio_state.pattern() == DEFAULT_AUDIO_PATTERN);
+    assert!(!emu.audio_state.is_pattern_loaded());
+
+    for i in 0..16 {
+        emu.memory_space[0x400 + i] = i as u8;
+    }
+    emu.address_register = 0x400;
+    emu.execute_instruction(0xF002.into()).unwrap();
+    assert!(emu.audio_state.pattern() == [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+    assert!(emu.audio_state.is_pattern_loaded());
+    assert!(emu.address_register == 0x400);
+}
+
+#[test]
+fn the_fx3a_test() {
+    let mut emu = Emulator::new_headless();
+    assert!(emu.audio_state.pitch() == 64);
+    emu.registers[2] = 112;
+    emu.execute_instruction(0xF23A.into()).unwrap();
+    assert!(emu.audio_state.pitch() == 112);
+
+    assert!((pattern_playback_rate(64) - 4000.0).abs() < 1e-9);
+    assert!((pattern_playback_rate(112) - 8000.0).abs() < 1e-9);
+    assert!((pattern_playback_rate(16) - 2000.0).abs() < 1e-9);
+}
+
+#[test]
+fn pattern_player_test() {
+    let state = AudioState::new();
+    // alternating bits
+    state.set_pattern(&[0xAA; 16]);
+    let mut player = PatternPlayer::new();
+
+    // at 4000 bits a second and 8000 samples a second each bit lasts 2 samples
+    let samples: Vec<f32> = (0..8).map(|_| player.next_sample(&state, 8000.0)).collect();
+    assert!(samples == [1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0]);
+
+    // the pattern loops after 128 bits
+    let mut player = PatternPlayer::new();
+    state.set_pattern(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let samples: Vec<f32> = (0..129)
+        .map(|_| player.next_sample(&state, 4000.0))
+        .collect();
+    assert!(samples[0] == 1.0);
+    assert!(samples[1..128].iter().all(|sample| *sample == -1.0));
+    assert!(samples[128] == 1.0);
+
+    // with a beep that plays until the program loads a pattern, looping at its own rate
+    let state = AudioState::new();
+    let beep = Arc::new(Beep::new(vec![0.5, -0.5, 0.25], 4000.0));
+    let mut player = PatternPlayer::with_beep(beep);
+    let samples: Vec<f32> = (0..8).map(|_| player.next_sample(&state, 8000.0)).collect();
+    assert!(samples == [0.5, 0.5, -0.5, -0.5, 0.25, 0.25, 0.5, 0.5]);
+    state.set_pattern(&[0xAA; 16]);
+    state.set_pattern_loaded(true);
+    assert!(player.next_sample(&state, 8000.0) == 1.0);
+}
+
+#[test]
+#[cfg(feature = "savestates")]
+fn save_state_round_trip_test() {
+    // a program that draws, calls a subroutine and then waits for a key
+    let mut emu = Emulator::new_headless();
+    emu.rng = Rng::new(42);
+    emu.memory_space[0x200..0x20C].copy_from_slice(&[
+        0xA0, 0x0A, // I = 0x00A, the 2 sprite
+        0xD0, 0x05, // draw it at V0, V0
+        0x22, 0x08, // call 0x208
+        0x00, 0x00, //
+        0xC3, 0xFF, // V3 = random
+        0xF5, 0x0A, // wait for a key into V5
+    ]);
+    for _ in 0..5 {
+        emu.execute_next_instruction().unwrap();
+    }
+    emu.timer_counter.store(20, Ordering::Relaxed);
+    emu.sound_counter.store(7, Ordering::Relaxed);
+    emu.audio_state.set_pitch(80);
+    emu.audio_state.set_pattern_loaded(true);
+    emu.display.flip_pixel(10, 10, 0b10);
+
+    let state_path = std::env::temp_dir().join("chip8_rust_save_state_test.state1");
+    let state_path = state_path.to_str().unwrap();
+    emu.save_state(state_path).unwrap();
+
+    let mut restored = Emulator::new_headless();
+    // same quirks, so nothing to warn about
+    assert!(restored.load_state(state_path).unwrap().is_empty());
+    assert!(restored.registers == emu.registers);
+    assert!(restored.address_register == 0x00A);
+    assert!(restored.memory_space == emu.memory_space);
+    assert!(restored.program_counter == 0x20A);
+    assert!(restored.subroutine_return_pointers == vec![0x206]);
+    assert!(restored.timer_counter.load(Ordering::Relaxed) == 20);
+    assert!(restored.sound_counter.load(Ordering::Relaxed) == 7);
+    assert!(restored.audio_state.pitch() == 80);
+    assert!(restored.audio_state.is_pattern_loaded());
+    assert!(restored.display.get_pixel_planes(10, 10) == 0b10);
+    assert!(restored.rng == emu.rng);
+
+    // both carry on identically, including still waiting for the key
+    emu.execute_next_instruction().unwrap();
+    restored.execute_next_instruction().unwrap();
+    assert!(restored.program_counter == 0x20A);
+    emu.key_states[0x7] = true;
+    restored.key_states[0x7] = true;
+    emu.execute_next_instruction().unwrap();
+    restored.execute_next_instruction().unwrap();
+    assert!(restored.registers[5] == 0x7);
+    assert!(restored.program_counter == emu.program_counter);
+
+    // the rng continues the same sequence
+    emu.execute_instruction(0xC4FF.into()).unwrap();
+    restored.execute_instruction(0xC4FF.into()).unwrap();
+    assert!(restored.registers[4] == emu.registers[4]);
+}
+
+#[test]
+#[cfg(feature = "savestates")]
+fn save_state_quirks_test() {
+    let state_path = std::env::temp_dir().join("chip8_rust_save_state_quirks_test.state1");
+    let state_path = state_path.to_str().unwrap();
+    let mut emu = Emulator::new_headless();
+    emu.quirks = Quirks::cosmac_vip();
+    emu.save_state(state_path).unwrap();
+
+    // the state's quirks win, and the ones that changed are reported
+    let mut restored = Emulator::new_headless();
+    restored.quirks = Quirks::superchip();
+    let changed = restored.load_state(state_path).unwrap();
+    assert!(restored.quirks == Quirks::cosmac_vip());
+    assert!(
+        changed
+            == [
+                "shift_uses_vy",
+                "load_store_increments_i",
+                "jump_uses_vx",
+                "vf_reset"
+            ]
+    );
+    assert!(Quirks::cosmac_vip()
+        .differences(&Quirks::cosmac_vip())
+        .is_empty());
+}
+
+#[test]
+#[cfg(feature = "savestates")]
+fn load_bad_save_state_test() {
+    let state_path = std::env::temp_dir().join("chip8_rust_bad_save_state_test.state1");
+    let state_path = state_path.to_str().unwrap();
+
+    std::fs::write(state_path, b"not a save state").unwrap();
+    let mut emu = Emulator::new_headless();
+    emu.registers[0] = 0x12;
+    let result = emu.load_state(state_path);
+    assert!(matches!(result, Err(EmulatorError::InvalidSaveState)));
+    // a failed load leaves the emulator alone
+    assert!(emu.registers[0] == 0x12);
+
+    // a save state from a future version is refused
+    let mut bytes = emu.snapshot().encode();
+    bytes[4] = 0xFF;
+    std::fs::write(state_path, &bytes).unwrap();
+    let result = emu.load_state(state_path);
+    assert!(matches!(
+        result,
+        Err(EmulatorError::UnsupportedSaveStateVersion { version: 0x00FF })
+    ));
+
+    // as is one that's been cut short
+    let bytes = emu.snapshot().encode();
+    std::fs::write(state_path, &bytes[..bytes.len() / 2]).unwrap();
+    let result = emu.load_state(state_path);
+    assert!(matches!(result, Err(EmulatorError::InvalidSaveState)));
+}
+
+#[test]
+#[cfg(feature = "savestates")]
+fn inconsistent_save_state_test() {
+    let state_path = std::env::temp_dir().join("chip8_rust_inconsistent_save_state_test.state1");
+    let state_path = state_path.to_str().unwrap();
+    let mut emu = Emulator::new();
+    emu.registers[0] = 0x12;
+
+    // decodes fine, but the memory has been cut short
+    let mut state = emu.snapshot();
+    state.memory_space.truncate(0x800);
+    std::fs::write(state_path, state.encode()).unwrap();
+    let result = emu.load_state(state_path);
+    assert!(matches!(result, Err(EmulatorError::InvalidSaveState)));
+    assert!(emu.memory_space.len() == 0x1000);
+
+    // or is the wrong size for the quirks
+    let mut state = emu.snapshot();
+    state.quirks = Quirks::xochip();
+    std::fs::write(state_path, state.encode()).unwrap();
+    let result = emu.load_state(state_path);
+    assert!(matches!(result, Err(EmulatorError::InvalidSaveState)));
+
+    // a pixel set in a plane that doesn't exist
+    let mut state = emu.snapshot();
+    state.display.flip_pixel(3, 4, 0b100);
+    std::fs::write(state_path, state.encode()).unwrap();
+    let result = emu.load_state(state_path);
+    assert!(matches!(result, Err(EmulatorError::InvalidSaveState)));
+
+    // a stack deeper than the emulator allows
+    let mut state = emu.snapshot();
+    state.subroutine_return_pointers = vec![0x200; 17];
+    std::fs::write(state_path, state.encode()).unwrap();
+    let result = emu.load_state(state_path);
+    assert!(matches!(result, Err(EmulatorError::InvalidSaveState)));
+    assert!(emu.registers[0] == 0x12);
+    assert!(!emu.display.get_pixel(3, 4));
+
+    // and an untouched state still loads
+    std::fs::write(state_path, emu.snapshot().encode()).unwrap();
+    assert!(emu.load_state(state_path).is_ok());
+}
+
+#[test]
+#[cfg(feature = "savestates")]
+fn rewind_test() {
+    // counts up in V0 and draws a digit each frame
+    let mut emu = Emulator::new_headless();
+    emu.instructions_per_frame = 3;
+    emu.memory_space[0x200..0x208].copy_from_slice(&[
+        0x70, 0x01, // V0 += 1
+        0xF0, 0x29, // I = digit sprite for V0
+        0xD1, 0x15, // draw it at V1, V1
+        0x12, 0x00, // jump to 0x200
+    ]);
+    let mut rewind = Rewind::new(4);
+    assert!(rewind.is_empty());
+
+    let mut history = Vec::new();
+    for _ in 0..6 {
+        emu.run_frame().unwrap();
+        rewind.record(&emu);
+        history.push((emu.registers, emu.program_counter, emu.display.clone()));
+    }
+    // only the last 4 frames are kept
+    assert!(rewind.len() == 4);
+
+    for (registers, program_counter, display) in history.iter().rev().take(4) {
+        assert!(rewind.rewind(&mut emu));
+        assert!(emu.registers == *registers);
+        assert!(emu.program_counter == *program_counter);
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                assert!(emu.display.get_pixel(x, y) == display.get_pixel(x, y));
+            }
+        }
+    }
+    assert!(!rewind.rewind(&mut emu));
+    assert!(rewind.is_empty());
+
+    // carrying on from a rewound state records from there
+    emu.run_frame().unwrap();
+    rewind.record(&emu);
+    assert!(rewind.len() == 1);
+}
+
+#[test]
+#[cfg(feature = "savestates")]
+fn rewind_across_resolution_change_test() {
+    let mut emu = Emulator::new_headless();
+    let mut rewind = Rewind::new(10);
+    emu.display.flip_pixel(3, 3, 0b01);
+    rewind.record(&emu);
+    // a different sized screen changes the snapshot size
+    emu.execute_instruction(0x00FF.into()).unwrap();
+    emu.display.flip_pixel(100, 50, 0b01);
+    rewind.record(&emu);
+
+    assert!(rewind.rewind(&mut emu));
+    assert!(emu.display.width() == 128);
+    assert!(emu.display.get_pixel(100, 50));
+    assert!(rewind.rewind(&mut emu));
+    assert!(emu.display.width() == 64);
+    assert!(emu.display.get_pixel(3, 3));
+}
+
+#[test]
+fn disassemble_opcode_test() {
+    let cases: [(u16, &str); 12] = [
+        (0x00E0, "CLS"),
+        (0x631F, "LD V3, 0x1F"),
+        (0xD015, "DRW V0, V1, 5"),
+        (0x5AB0, "SE VA, VB"),
+        (0x8AB6, "SHR VA, VB"),
+        (0x2ABC, "CALL 0xABC"),
+        (0xF455, "LD [I], V4"),
+        (0x00C4, "SCD 4"),
+        (0xF830, "LD HF, V8"),
+        (0x5232, "SAVE V2, V3"),
+        (0xF201, "PLANE 2"),
+        (0xF000, "LD I, LONG"),
+    ];
+    for (code, text) in cases.iter() {
+        assert!(Opcode::new(*code).disassemble().as_deref() == Some(*text));
+    }
+    // not instructions
+    for code in [0x5AB1, 0x8AB8, 0x9AB1, 0xE0FF, 0xF0FF, 0xF100] {
+        assert!(Opcode::new(code).disassemble().is_none());
+    }
+}
+
+#[test]
+fn disassemble_rom_test() {
+    let rom = [
+        0x00, 0xE0, // 0x200 CLS
+        0xA2, 0x14, // 0x202 LD I, sprite
+        0x22, 0x0C, // 0x204 CALL 0x20C
+        0x30, 0x01, // 0x206 SE V0, 1
+        0x12, 0x00, // 0x208 JP 0x200
+        0xB3, 0x00, // 0x20A JP V0, 0x300
+        0xD0, 0x15, // 0x20C DRW V0, V1, 5
+        0xF0, 0x00, 0x12, 0x34, // 0x20E LD I, LONG 0x1234
+        0x00, 0xEE, // 0x212 RET
+        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0x214 sprite
+    ];
+    let listing = disassemble_rom(&rom).unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert!(lines[0] == "label_200:");
+    assert!(lines[1] == "0x0200  00E0      CLS");
+    assert!(lines[3] == "0x0204  220C      CALL sub_20C");
+    assert!(lines[5] == "0x0208  1200      JP label_200");
+    assert!(lines.contains(&"sub_20C:"));
+    assert!(lines.contains(&"0x020E  F0001234  LD I, LONG 0x1234"));
+    // the skip at 0x206 can land on the computed jump, which is flagged
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("0x020A  B300      JP V0, 0x300")
+            && line.ends_with("; computed jump, not followed")));
+    // the sprite after the code is data, not instructions
+    assert!(lines.contains(&"0x0214  F0909090  DB 0xF0, 0x90, 0x90, 0x90"));
+    assert!(lines.contains(&"0x0218  F0        DB 0xF0"));
+}
+
+#[test]
+fn disassemble_rom_flags_unknown_opcodes_test() {
+    let rom = [0x60, 0x01, 0xE0, 0xFF, 0x00, 0xE0];
+    let listing = disassemble_rom(&rom).unwrap();
+    assert!(listing.contains("0x0202  E0FF      DW 0xE0FF"));
+    assert!(listing.contains("; unknown opcode"));
+    // tracing stops at the unknown opcode, so what follows is shown as data
+    assert!(listing.contains("0x0204  00E0      DB 0x00, 0xE0"));
+}
+
+#[test]
+fn disassemble_unaligned_jump_test() {
+    // an odd address after data still starts a row, so it gets its label
+    let rom = [0x12, 0x03, 0xAA, 0x00, 0xE0];
+    let listing = disassemble_rom(&rom).unwrap();
+    assert!(listing.contains("0x0200  1203      JP label_203"));
+    assert!(listing.contains("0x0202  AA        DB 0xAA"));
+    assert!(listing.contains("label_203:\n0x0203  00E0      CLS"));
+
+    // but the middle of an instruction doesn't, so the jump's flagged instead of pointing at a
+    // label that's nowhere in the listing
+    let rom = [0x60, 0x01, 0x12, 0x01];
+    let listing = disassemble_rom(&rom).unwrap();
+    assert!(!listing.contains("label_201"));
+    assert!(listing
+        .lines()
+        .any(|line| line.starts_with("0x0202  1201      JP 0x201")
+            && line.ends_with("; into the middle of an instruction")));
+}
+
+#[test]
+fn disassemble_oversized_rom_test() {
+    // the biggest rom that fits runs right up to 0xFFFF
+    let mut rom = vec![0x12, 0x00];
+    rom.resize(0x10000 - 0x200, 0xAB);
+    let listing = disassemble_rom(&rom).unwrap();
+    assert!(listing.ends_with("0xFFFE  ABAB      DB 0xAB, 0xAB\n"));
+
+    // one byte more can't be loaded, so it's refused rather than wrapping round
+    rom.push(0xAB);
+    let result = disassemble_rom(&rom);
+    assert!(matches!(
+        result,
+        Err(EmulatorError::RomTooLarge {
+            size: 0xFE01,
+            max: 0xFE00
+        })
+    ));
+}
+
+#[test]
+fn assemble_and_run_test() {
+    let source = "
+        ; counts V0 up to the limit, drawing the digit each time
+        limit = 3
+        start:
+            LD V0, 0
+        loop: ADD V0, 1
+            LD F, V0
+            DRW V1, V1, 5
+            SE V0, limit
+            JP loop
+            CALL finish
+            EXIT
+
+        finish:
+            LD I, data + 1
+            LD V5, [I]
+            RET
+        data:
+            db 0x00, 0x12, 0b1010, 0x34
+            dw 0xABCD
+            db -1
+    ";
+    let assembly = assemble(source).unwrap();
+    assert!(assembly.rom[..4] == [0x60, 0x00, 0x70, 0x01]);
+    // the data is at the end, after 11 two byte instructions
+    assert!(assembly.rom[22..] == [0x00, 0x12, 0x0A, 0x34, 0xAB, 0xCD, 0xFF]);
+
+    let mut emu = Emulator::new();
+    emu.load_rom(&assembly.rom).unwrap();
+    let mut instructions = 0;
+    while emu.execute_next_instruction().unwrap() != InstructionResult::Terminated {
+        instructions += 1;
+    }
+    // 1 to start, 5 twice round the loop, 4 the last time when JP is skipped, then 4 more
+    // (EXIT doesn't count)
+    assert!(instructions == 19);
+    assert!(emu.address_register == 0x217);
+    assert!(emu.registers[0..6] == [0x12, 0x0A, 0x34, 0xAB, 0xCD, 0xFF]);
+}
+
+#[test]
+fn assembler_round_trips_the_disassembler_test() {
+    for code in 0..=0xFFFF_u16 {
+        let text = match Opcode::new(code).disassemble() {
+            Some(text) => text,
+            None => continue,
+        };
+        let source = if code == 0xF000 {
+            "LD I, LONG 0x1234".to_string()
+        } else {
+            text
+        };
+        let rom = assemble(&source).unwrap().rom;
+        assert!(rom[..2] == code.to_be_bytes());
+    }
+}
+
+#[test]
+fn assembler_error_test() {
+    let error_at = |source: &str| {
+        let e = assemble(source).err().unwrap();
+        (e.location.line, e.message)
+    };
+    assert!(error_at("CLS\n\nFOO V0").0 == 3);
+    assert!(error_at("CLS\nJP nowhere").1 == "nowhere isn't a number, label or constant");
+    assert!(error_at("LD V0, 0x100").1 == "0x100 doesn't fit in a byte (0xff max)");
+    assert!(error_at("a: CLS\na: CLS") == (2, "a is already defined".to_string()));
+    assert!(error_at("V3: CLS").1 == "V3 is reserved and can't be used as a name");
+    assert!(error_at("DRW V0, V1").1 == "DRW V0, V1 isn't a valid instruction");
+    assert!(error_at("x = y\ny = x\nLD V0, x").0 == 3);
+    let e = assemble("include \"does/not/exist.asm\"").err().unwrap();
+    assert!(e.to_string().starts_with("<source>:1: could not include"));
+}
+
+#[test]
+fn assembler_include_and_source_map_test() {
+    let dir = std::env::temp_dir().join("chip8_rust_assembler_include_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("sprites.asm"),
+        "; the sprites\nsprite:\n    db 0xFF, 0x81\n    BAD\n",
+    )
+    .unwrap();
+    let main_path = dir.join("main.asm");
+    std::fs::write(&main_path, "CLS\nLD I, sprite\ninclude \"sprites.asm\"\n").unwrap();
+
+    let e = assemble_file(main_path.to_str().unwrap()).err().unwrap();
+    assert!(e.location.file.ends_with("sprites.asm"));
+    assert!(e.location.line == 4);
+
+    std::fs::write(
+        dir.join("sprites.asm"),
+        "; the sprites\nsprite:\n    db 0xFF, 0x81\n",
+    )
+    .unwrap();
+    let assembly = assemble_file(main_path.to_str().unwrap()).unwrap();
+    assert!(assembly.rom == [0x00, 0xE0, 0xA2, 0x04, 0xFF, 0x81]);
+    let location = assembly.location_of(0x202).unwrap();
+    assert!(location.file.ends_with("main.asm") && location.line == 2);
+    // the second byte of the db still maps to its line
+    let location = assembly.location_of(0x205).unwrap();
+    assert!(location.file.ends_with("sprites.asm") && location.line == 3);
+}
+
+#[test]
+fn octo_compile_and_run_test() {
+    let source = "
+        :const limit 5
+        :alias counter v3
+        :macro add-twice reg amount { reg += amount reg += amount }
+        :calc doubled { limit * 2 + 1 }
+
+        : main
+            counter := 0
+            v4 := 0
+            loop
+                counter += 1
+                if counter == 3 then v4 += 10
+                if counter > 3 begin
+                    add-twice v5 1
+                else
+                    v6 += 1
+                end
+                while counter != limit
+            again
+            v7 := doubled
+            v8 := 200
+            if v8 >= 200 then v9 := 1
+            if v8 < 200 then v9 := 2
+            i := digits
+            load v1
+            subroutine
+            exit
+
+        : subroutine
+            va := random 0
+            vb -= 1
+        ;
+
+        : digits 0x12 0b00110100
+    ";
+    let rom = compile(source).unwrap();
+    // main is first so there's no jump to it
+    assert!(rom[..2] == [0x63, 0x00]);
+
+    let mut emu = Emulator::new();
+    emu.load_rom(&rom).unwrap();
+    let mut instructions = 0;
+    while emu.execute_next_instruction().unwrap() != InstructionResult::Terminated {
+        instructions += 1;
+        assert!(instructions < 1000);
+    }
+    assert!(emu.registers[3] == 5);
+    assert!(emu.registers[4] == 10);
+    // counter was above 3 twice
+    assert!(emu.registers[5] == 4);
+    assert!(emu.registers[6] == 3);
+    assert!(emu.registers[7] == 11);
+    assert!(emu.registers[9] == 1);
+    assert!(emu.registers[0] == 0x12 && emu.registers[1] == 0x34);
+    assert!(emu.registers[0xA] == 0);
+    assert!(emu.registers[0xB] == 0xFF);
+}
+
+#[test]
+fn octo_instruction_encoding_test() {
+    let cases: [(&str, &[u8]); 16] = [
+        ("clear", &[0x00, 0xE0]),
+        ("sprite v1 v2 0", &[0xD1, 0x20]),
+        ("v1 := key", &[0xF1, 0x0A]),
+        ("v2 =- v3", &[0x82, 0x37]),
+        ("v2 >>= v3", &[0x82, 0x36]),
+        ("v4 -= 3", &[0x74, 0xFD]),
+        ("i := hex v5", &[0xF5, 0x29]),
+        ("i := bighex v5", &[0xF5, 0x30]),
+        ("i += v6", &[0xF6, 0x1E]),
+        ("delay := v7", &[0xF7, 0x15]),
+        ("buzzer := v7", &[0xF7, 0x18]),
+        ("save v2 - v5", &[0x52, 0x52]),
+        (
+            "hires scroll-down 3 scroll-left",
+            &[0x00, 0xFF, 0x00, 0xC3, 0x00, 0xFC],
+        ),
+        (
+            "plane 3 audio pitch := v1",
+            &[0xF3, 0x01, 0xF0, 0x02, 0xF1, 0x3A],
+        ),
+        ("saveflags v7 bcd v1", &[0xF7, 0x75, 0xF1, 0x33]),
+        ("if v1 -key then jump0 0x300", &[0xE1, 0x9E, 0xB3, 0x00]),
+    ];
+    for (source, expected) in cases.iter() {
+        let rom = compile(&format!(": main {}", source)).unwrap();
+        assert!(rom == *expected);
+    }
+
+    // a long load of a label past the 4k boundary
+    let rom = compile(": main i := long data :org 0x1000 : data 0xAA").unwrap();
+    assert!(rom[..4] == [0xF0, 0x00, 0x10, 0x00]);
+    assert!(rom[0x1000 - 0x200] == 0xAA);
+
+    // when main isn't first, 0x200 jumps to it
+    let rom = compile(": helper ; : main helper").unwrap();
+    assert!(rom == [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+}
+
+#[test]
+fn octo_error_test() {
+    let error_at = |source: &str| {
+        let e = compile(source).err().unwrap();
+        (e.line, e.message)
+    };
+    assert!(error_at(": main\n  v0 := 1\n  v0 := 256").0 == 3);
+    assert!(error_at(": main\n\n  missing").1 == "missing is never defined");
+    assert!(error_at(": helper ;").1 == "there's no : main to start from");
+    assert!(error_at(": main loop v0 += 1").1 == "loop is missing its again");
+    assert!(error_at(": main\nagain").1 == "again without a loop");
+    assert!(error_at(": main\nwhile v0 == 1").1 == "while is outside of a loop");
+    assert!(error_at(": main : main").1 == "main is already defined");
+    assert!(error_at(": main\n:macro forever { forever }\nforever")
+        .1
+        .ends_with("expands forever"));
+
+    // :calc reports overflow rather than panicking, on the line it's on
+    assert!(
+        error_at(": main\n:calc big { 1 << 70 }")
+            == (2, "1 << 70 is out of range in :calc".to_string())
+    );
+    assert!(error_at(": main :calc big { 0x7FFFFFFF * 0x7FFFFFFF * 4 }")
+        .1
+        .ends_with("is out of range in :calc"));
+    assert!(error_at(": main :calc big { 1 >> -1 }").1 == "1 >> -1 is out of range in :calc");
+    assert!(error_at(": main :calc nothing { 1 / 0 }").1 == "division by zero");
+    // and :byte checks its range like any other byte
+    assert!(error_at(": main :byte { 255 + 1 }").1 == "256 doesn't fit in a byte");
+    assert!(error_at(": main :byte { -129 }").1 == "-129 doesn't fit in a byte");
+    assert!(compile(": main :byte { -1 } :byte { 255 }")
+        .unwrap()
+        .ends_with(&[0xFF, 0xFF]));
+}
+
+#[test]
+fn debugger_test() {
+    let mut emu = Emulator::new();
+    // LD V0, 1; ADD V0, 1; ADD V0, 1; JP 0x206
+    emu.load_rom(&[0x60, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x06])
+        .unwrap();
+    let mut debugger = Debugger::new();
+
+    // starts paused, nothing runs
+    debugger.run_frame(&mut emu).unwrap();
+    assert!(emu.program_counter == 0x200);
+
+    debugger.execute_command("step 2", &mut emu);
+    debugger.run_frame(&mut emu).unwrap();
+    assert!(emu.program_counter == 0x204);
+    assert!(emu.registers[0] == 2);
+    assert!(debugger.is_paused());
+    assert!(debugger.take_messages() == ["0x0204  ADD V0, 0x01"]);
+
+    debugger.execute_command("b 0x206", &mut emu);
+    debugger.execute_command("c", &mut emu);
+    debugger.run_frame(&mut emu).unwrap();
+    assert!(emu.program_counter == 0x206);
+    assert!(emu.registers[0] == 3);
+    assert!(debugger.is_paused());
+
+    // continuing doesn't stop on the breakpoint it's sitting on
+    debugger.execute_command("continue", &mut emu);
+    let frame = emu.frame_count;
+    debugger.run_frame(&mut emu).unwrap();
+    assert!(emu.frame_count == frame);
+    assert!(debugger.is_paused());
+
+    debugger.execute_command("set v3 0x2A", &mut emu);
+    debugger.execute_command("set [0x300] 7", &mut emu);
+    debugger.execute_command("set I 0x300", &mut emu);
+    assert!(emu.registers[3] == 0x2A);
+    assert!(emu.address_register == 0x300);
+    assert!(debugger.execute_command("mem 0x300 2", &mut emu) == "0x0300  07 00");
+    assert!(debugger.execute_command("set v3 256", &mut emu) == "0x100 doesn't fit in a byte");
+    assert!(debugger.execute_command("bogus", &mut emu) == "unknown command bogus, try help");
+    assert!(debugger
+        .execute_command("list 1", &mut emu)
+        .contains("-> 0x0206  JP 0x206"));
+}
+
+#[test]
+fn watchpoint_test() {
+    let mut emu = Emulator::new();
+    // LD I, 0x300; LD V0, 7; LD [I], V0; LD V0, [I]; DRW V0, V0, 1; LD B, V0
+    emu.load_rom(&[
+        0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0xF0, 0x65, 0xD0, 0x01, 0xF0, 0x33,
+    ])
+    .unwrap();
+    emu.watchpoints
+        .push(Watchpoint::new(0x300, 0x301, Access::Write));
+    emu.watchpoints
+        .push(Watchpoint::new(0x300, 0x300, Access::Read));
+    emu.watchpoints
+        .push(Watchpoint::new(0x202, 0x202, Access::Execute));
+
+    assert!(emu.execute_next_instruction().unwrap() == InstructionResult::Working);
+    let hit = |access, addr, old_value, new_value, pc| WatchpointHit {
+        access,
+        addr,
+        old_value,
+        new_value,
+        pc,
+    };
+    assert!(
+        emu.execute_next_instruction().unwrap()
+            == InstructionResult::WatchpointHit(vec![hit(
+                Access::Execute,
+                0x202,
+                0x60,
+                0x60,
+                0x202
+            )])
+    );
+    // an execute stops before the instruction runs, running again carries on past it
+    assert!(emu.program_counter == 0x202 && emu.registers[0] == 0 && emu.cycle_count == 1);
+    assert!(emu.execute_next_instruction().unwrap() == InstructionResult::Working);
+    assert!(emu.registers[0] == 7);
+    assert!(
+        emu.execute_next_instruction().unwrap()
+            == InstructionResult::WatchpointHit(vec![hit(Access::Write, 0x300, 0, 7, 0x204)])
+    );
+    assert!(
+        emu.execute_next_instruction().unwrap()
+            == InstructionResult::WatchpointHit(vec![hit(Access::Read, 0x300, 7, 7, 0x206)])
+    );
+    // sprite data is read too
+    assert!(
+        emu.execute_next_instruction().unwrap()
+            == InstructionResult::WatchpointHit(vec![hit(Access::Read, 0x300, 7, 7, 0x208)])
+    );
+    // 7 is BCD 0, 0, 7, only the first two digits land in the watched range
+    assert!(
+        emu.execute_next_instruction().unwrap()
+            == InstructionResult::WatchpointHit(vec![
+                hit(Access::Write, 0x300, 7, 0, 0x20A),
+                hit(Access::Write, 0x301, 0, 0, 0x20A),
+            ])
+    );
+
+    // run_frame stops on the hit, part way through the frame
+    let mut emu = Emulator::new();
+    emu.load_rom(&[0xA3, 0x00, 0xF0, 0x55, 0x12, 0x04]).unwrap();
+    emu.watchpoints
+        .push(Watchpoint::new(0x300, 0x300, Access::Write));
+    match emu.run_frame().unwrap() {
+        InstructionResult::WatchpointHit(hits) => assert!(hits[0].pc == 0x202),
+        _ => panic!("expected a watchpoint hit"),
+    }
+    assert!(emu.program_counter == 0x204);
+    assert!(emu.frame_count == 0);
+}
+
+#[test]
+fn condition_test() {
+    let mut emu = Emulator::new();
+    emu.registers[3] = 0;
+    emu.registers[0xA] = 0x10;
+    emu.address_register = 0x300;
+    emu.memory_space[0x302] = 0x42;
+    let eval = |source: &str, emu: &Emulator| Condition::parse(source).unwrap().evaluate(emu, 3);
+
+    assert!(eval("V3 == 0 && I >= 0x300 && I < 0x340", &emu) == 1);
+    assert!(eval("[I+2]", &emu) == 0x42);
+    assert!(eval("[ i + 2 ] == 66", &emu) == 1);
+    assert!(eval("va + 1 - 2", &emu) == 0xF);
+    assert!(eval("V3 == 1 || HITS > 2", &emu) == 1);
+    assert!(eval("!(PC == 0x200)", &emu) == 0);
+    assert!(eval("VA & 0x18 | 1 ^ 3", &emu) == 0x12);
+    assert!(eval("SP + DT + ST", &emu) == 0);
+    assert!(eval("[0x10000]", &emu) == 0);
+
+    let error = |source: &str| Condition::parse(source).err().unwrap();
+    assert!(error("V3 ==") == "condition ends too early");
+    assert!(error("VG == 1") == "VG isn't a number or register");
+    assert!(error("(V3 == 1") == "condition ends too early");
+    assert!(error("V3 = 1") == "unexpected = in condition");
+    assert!(error("V3 1") == "unexpected 1 in condition");
+    assert!(error("V3 ≥ 2") == "unexpected ≥ in condition");
+}
+
+#[test]
+fn conditional_breakpoint_test() {
+    let mut emu = Emulator::new();
+    // LD V3, 3; loop: ADD V3, -1; JP loop
+    emu.load_rom(&[0x63, 0x03, 0x73, 0xFF, 0x12, 0x02]).unwrap();
+    let mut debugger = Debugger::new();
+    debugger
+        .load_breakpoints("# stops once V3 wraps\n0x204 if V3 == 0xFF # comment\n\n")
+        .unwrap();
+    debugger.set_paused(false);
+    debugger.run_frame(&mut emu).unwrap();
+    assert!(debugger.is_paused());
+    assert!(emu.program_counter == 0x204);
+    assert!(emu.registers[3] == 0xFF);
+    assert!(
+        debugger.execute_command("breakpoints", &mut emu) == "#1 0x0204 if V3 == 0xFF, hit 4 times"
+    );
+
+    // no address, checked before every instruction
+    debugger.execute_command("delete #1", &mut emu);
+    debugger.execute_command("break if HITS == 5", &mut emu);
+    debugger.execute_command("continue", &mut emu);
+    // the frame ends before the breakpoint is reached
+    debugger.run_frame(&mut emu).unwrap();
+    assert!(!debugger.is_paused());
+    debugger.run_frame(&mut emu).unwrap();
+    assert!(debugger.is_paused());
+    assert!(emu.program_counter == 0x202);
+    assert!(emu.registers[3] == 0xFD);
+
+    assert!(
+        debugger.load_breakpoints("0x200\nif V3 ==").err().unwrap()
+            == "line 2: condition ends too early"
+    );
+}
+
+// lets a test read back what a tracer wrote
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn trace_test() {
+    // LD V3, 3; loop: ADD V3, 1; LD I, 0x300; JP loop
+    let rom = [0x63, 0x03, 0x73, 0x01, 0xA3, 0x00, 0x12, 0x02];
+    let traced = |format, filter| {
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom).unwrap();
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), format);
+        tracer.filter = filter;
+        emu.tracer = Some(tracer);
+        for _ in 0..8 {
+            emu.execute_next_instruction().unwrap();
+        }
+        assert!(emu.cycle_count == 8);
+        let bytes = buffer.0.borrow().clone();
+        bytes
+    };
+
+    let text = String::from_utf8(traced(TraceFormat::Text, TraceFilter::default())).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines.len() == 8);
+    assert!(lines[0] == "0000000000  0200  6303  LD V3, 0x03            0000 00 00  V3=03");
+    assert!(lines[2] == "0000000002  0204  A300  LD I, 0x300            0300 00 00");
+
+    // only ADD V3, 1 from the third cycle up to the sixth
+    let filter = TraceFilter {
+        start_addr: 0x202,
+        end_addr: 0x203,
+        start_cycle: 2,
+        stop_cycle: Some(6),
+    };
+    let text = String::from_utf8(traced(TraceFormat::Text, filter.clone())).unwrap();
+    assert!(text == "0000000004  0202  7301  ADD V3, 0x01           0300 00 00  V3=05\n");
+
+    let entries = read_binary_trace(&traced(TraceFormat::Binary, TraceFilter::default())).unwrap();
+    assert!(entries.len() == 8);
+    assert!(
+        entries
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect::<Vec<String>>()
+            == lines
+    );
+    let entries = read_binary_trace(&traced(TraceFormat::Binary, filter)).unwrap();
+    assert!(
+        entries.len() == 1 && entries[0].cycle == 4 && entries[0].changed_registers == [(3, 5)]
+    );
+
+    let binary = traced(TraceFormat::Binary, TraceFilter::default());
+    assert!(
+        read_binary_trace(&binary[..binary.len() - 1])
+            .err()
+            .unwrap()
+            == "the trace is cut short"
+    );
+    assert!(read_binary_trace(b"trace").err().unwrap() == "not a binary trace");
+}
+
+#[test]
+fn input_script_test() {
+    let inputs =
+        InputScript::parse("# jump\n10 press 5\n12 press a # and fire\n20 release 5").unwrap();
+    assert!(inputs.last_frame() == Some(20));
+    assert!(inputs.key_states_at(9) == [false; 16]);
+    let held = inputs.key_states_at(15);
+    assert!(held[5] && held[0xA]);
+    let released = inputs.key_states_at(20);
+    assert!(!released[5] && released[0xA]);
+
+    assert!(
+        InputScript::parse("10 press 16").err().unwrap() == "line 1: 16 isn't a key, keys are 0-F"
+    );
+    assert!(
+        InputScript::parse("\nsoon press 1").err().unwrap() == "line 2: soon isn't a frame number"
+    );
+    assert!(InputScript::parse("1 tap 1").err().unwrap() == "line 1: unknown action tap");
+}
+
+#[test]
+fn trace_diff_test() {
+    // LD V3, 3; wait for a key into V0; ADD V3, 1; EXIT
+    let rom = [0x63, 0x03, 0xF0, 0x0A, 0x73, 0x01, 0x00, 0xFD];
+    let zeros = "00 00 00 00 00 00 00 00 00 00 00 00";
+    let reference = format!(
+        "# PC op V0-VF I DT ST\n\
+         0200 6303 00 00 00 00 {zeros} 0000 00 00\n\
+         0x0202 F00A 00 00 00 03 {zeros} 0000\n\
+         0204 7301 07 00 00 03 {zeros} 0000\n\
+         0206 00FD 07 00 00 04 {zeros} 0000 00 00\n",
+        zeros = zeros
+    );
+    let reference = parse_reference(&reference).unwrap();
+    assert!(reference.len() == 4 && reference[1].line == 3 && reference[1].state.pc == 0x202);
+    let inputs = InputScript::parse("3 press 7").unwrap();
+    let run = |inputs: &InputScript, reference| {
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom).unwrap();
+        diff(&mut emu, reference, inputs)
+    };
+
+    match run(&inputs, &reference) {
+        DiffResult::Matched { steps } => assert!(steps == 4),
+        DiffResult::Diverged(divergence) => panic!("{}", divergence),
+    }
+
+    // a key that's never pressed
+    match run(&InputScript::default(), &reference) {
+        DiffResult::Diverged(divergence) => {
+            assert!(divergence.step == 2 && divergence.actual.is_none());
+            assert!(divergence.stopped.unwrap() == "waiting for a key the inputs never press");
+        }
+        _ => panic!("expected a divergence"),
+    }
+
+    // the reference thinks ADD V3, 1 gives 5
+    let mut wrong = reference.clone();
+    wrong[3].state.registers[3] = 5;
+    match run(&inputs, &wrong) {
+        DiffResult::Diverged(divergence) => {
+            let report = divergence.to_string();
+            assert!(divergence.step == 3 && divergence.history.len() == 3);
+            assert!(report.starts_with("diverged at step 3 (reference line 5)"));
+            assert!(report.contains("  differs   V3\n"));
+            assert!(report.contains("  step 2        0204 7301  07 00 00 03"));
+        }
+        _ => panic!("expected a divergence"),
+    }
+
+    // the reference goes on past the end of the rom
+    let mut longer = reference.clone();
+    longer.push(reference[3].clone());
+    match run(&inputs, &longer) {
+        DiffResult::Diverged(divergence) => {
+            assert!(divergence.stopped.unwrap() == "the rom exited")
+        }
+        _ => panic!("expected a divergence"),
+    }
+
+    assert!(parse_reference("0200 6303").err().unwrap()
+        == "line 1: expected PC, opcode, V0-VF, I and optionally DT and ST, but found 2 numbers");
+}
+
+#[test]
+fn screen_dump_test() {
+    let mut emu = Emulator::new();
+    // LD V0, 5; LD F, V0; DRW V0, V0, 5, the 5 sprite at 5, 5
+    emu.load_rom(&[0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05]).unwrap();
+    for _ in 0..3 {
+        emu.execute_next_instruction().unwrap();
+    }
+
+    let ascii = emu.display.to_ascii();
+    let rows: Vec<&str> = ascii.lines().collect();
+    assert!(rows.len() == 32 && rows[0].len() == 64);
+    assert!(rows[5].starts_with(".....####....."));
+    assert!(rows[6].starts_with(".....#........"));
+    emu.display.flip_pixel(0, 0, 0b10);
+    emu.display.flip_pixel(5, 5, 0b10);
+    assert!(emu.display.to_ascii().starts_with('+'));
+    assert!(emu
+        .display
+        .to_ascii()
+        .lines()
+        .nth(5)
+        .unwrap()
+        .starts_with(".....@###"));
+
+    let pbm = emu.display.to_pbm();
+    let lines: Vec<&str> = pbm.lines().collect();
+    assert!(lines[0] == "P1" && lines[1] == "64 32");
+    assert!(lines[2 + 5].starts_with("0 0 0 0 0 1 1 1 1 0"));
+
+    #[cfg(feature = "screenshots")]
+    {
+        let mut png = Vec::new();
+        emu.display.write_png(&mut png, 2).unwrap();
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert!(info.width == 128 && info.height == 64);
+        // the pixel at 6, 5 is doubled into 12-13, 10-11
+        let at = |x: usize, y: usize| &data[(x + y * 128) * 4..(x + y * 128) * 4 + 4];
+        assert!(at(12, 11) == SET_COLOUR && at(13, 10) == SET_COLOUR);
+        assert!(at(12, 12) == UNSET_COLOUR);
+    }
+}
+
+#[test]
+#[cfg(feature = "screenshots")]
+fn screenshot_test() {
+    let mut emu = Emulator::new();
+    emu.display.flip_pixel(1, 0, 0b01);
+    let path = std::env::temp_dir().join("chip8_rust_screenshot_test.png");
+    let path = path.to_str().unwrap();
+    emu.save_screenshot(path, 3).unwrap();
+
+    let file = std::fs::File::open(path).unwrap();
+    let mut reader = png::Decoder::new(file).read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert!(info.width == 64 * 3 && info.height == 32 * 3);
+    assert!(data[..4] == UNSET_COLOUR && data[3 * 4..4 * 4] == SET_COLOUR);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+#[cfg(feature = "recording")]
+fn recording_test() {
+    let dir = std::env::temp_dir();
+    let path = |extension: &str| {
+        let path = dir.join(format!("chip8_rust_recording_test.{}", extension));
+        path.to_str().unwrap().to_string()
+    };
+    let mut emu = Emulator::new();
+    let mut recorder = Recorder::new(2);
+    recorder.record_gif(&path("gif")).unwrap();
+    recorder.record_y4m(&path("y4m")).unwrap();
+    recorder.record_wav(&path("wav")).unwrap();
+    assert!(recorder.is_recording());
+
+    // three silent frames of an empty screen, then three beeping ones with a pixel set
+    for _ in 0..3 {
+        recorder.capture_frame(&emu).unwrap();
+    }
+    emu.display.flip_pixel(0, 0, 0b01);
+    emu.sound_counter.store(10, Ordering::Relaxed);
+    for _ in 0..3 {
+        recorder.capture_frame(&emu).unwrap();
+    }
+    assert!(recorder.frames() == 6);
+    recorder.finish().unwrap();
+
+    // the same screen is held in one gif frame, each lasting 3/60ths of a second
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(std::fs::File::open(path("gif")).unwrap())
+        .unwrap();
+    assert!(decoder.width() == 256 && decoder.height() == 128);
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        frames.push((frame.delay, frame.buffer.to_vec()));
+    }
+    assert!(frames.len() == 2);
+    assert!(frames[0].0 == 5 && frames[1].0 == 5);
+    // lores pixels are 2x2 in the hires sized recording, so 4x4 at scale 2
+    assert!(frames[0].1.iter().all(|pixel| *pixel == 0));
+    assert!(frames[1].1[..5] == [1, 1, 1, 1, 0] && frames[1].1[256 * 3 + 3] == 1);
+    assert!(frames[1].1[256 * 4] == 0);
+
+    let y4m = std::fs::read(path("y4m")).unwrap();
+    let header = b"YUV4MPEG2 W256 H128 F60:1 Ip A1:1 C444\n";
+    assert!(y4m.starts_with(header));
+    assert!(y4m.len() == header.len() + 6 * (6 + 256 * 128 * 3));
+
+    let mut reader = hound::WavReader::open(path("wav")).unwrap();
+    assert!(reader.spec().sample_rate == WAV_SAMPLE_RATE);
+    let samples: Vec<i16> = reader.samples().map(|sample| sample.unwrap()).collect();
+    let per_frame = WAV_SAMPLE_RATE as usize / 60;
+    assert!(samples.len() == 6 * per_frame);
+    assert!(samples[..3 * per_frame].iter().all(|sample| *sample == 0));
+    // the beep is beep2.wav, which starts at 0 and has the odd sample close enough to round to it
+    let beeping = &samples[3 * per_frame..];
+    assert!(beeping.iter().filter(|sample| **sample == 0).count() < 10);
+
+    for extension in &["gif", "y4m", "wav"] {
+        std::fs::remove_file(path(extension)).unwrap();
+    }
+}
+
+#[test]
+#[cfg(feature = "movies")]
+fn movie_test() {
+    // adds a random number to V2 every instruction the 5 key is held for
+    let source = "
+        LD V0, 5
+    loop:
+        SKNP V0
+        JP add
+        JP loop
+    add:
+        RND V1, 0xFF
+        ADD V2, V1
+        JP loop
+    ";
+    let rom = assemble(source).unwrap().rom;
+    // the VIP rng reads the interpreter page, so it only replays the same with it loaded
+    let interpreter_path = std::env::temp_dir().join("chip8_rust_movie_test_interpreter.bin");
+    let interpreter: Vec<u8> = (0..0x200).map(|i| (i * 7 + 3) as u8).collect();
+    std::fs::write(&interpreter_path, &interpreter).unwrap();
+    let machine = MachineOptions {
+        instructions_per_frame: 7,
+        quirks: Quirks::cosmac_vip(),
+        seed: None,
+        rng_mode: RngMode::CosmacVip,
+        vip_interpreter: Some(interpreter_path.to_str().unwrap().to_string()),
+    };
+    let mut emu = Emulator::new();
+    machine.configure(&mut emu, 99).unwrap();
+    emu.load_rom(&rom).unwrap();
+
+    let mut movie = Movie::new(&emu, &machine, "adder.ch8", &rom);
+    for frame in 0..20 {
+        emu.key_states[5] = (3..8).contains(&frame) || frame >= 15;
+        movie.record_frame(&emu);
+        emu.run_frame().unwrap();
+    }
+    assert!(movie.frames == 20);
+    assert!(movie.key_states_at(3)[5] && !movie.key_states_at(8)[5]);
+    assert!(emu.registers[2] != 0);
+
+    let text = movie.to_string();
+    assert!(text.contains("quirks                  shift_uses_vy load_store_increments_i"));
+    assert!(text.contains("vip_interpreter         "));
+    let loaded = Movie::parse(&text).unwrap();
+    assert!(loaded.rom == "adder.ch8" && loaded.frames == 20);
+    // every option comes back, with the seed that was picked
+    assert!(
+        loaded.machine.named()
+            == MachineOptions {
+                seed: Some(99),
+                ..machine
+            }
+            .named()
+    );
+    assert!(loaded.inputs.to_string() == movie.inputs.to_string());
+    assert!(loaded.check_rom(&rom).is_ok() && loaded.check_rom(&[0x00, 0xE0]).is_err());
+
+    // played back from scratch it ends up in exactly the same place
+    let mut replay = Emulator::new();
+    loaded.apply(&mut replay).unwrap();
+    assert!(replay.has_vip_interpreter_page() && replay.instructions_per_frame == 7);
+    replay.load_rom(&rom).unwrap();
+    while !loaded.is_finished(replay.frame_count) {
+        replay.key_states = loaded.key_states_at(replay.frame_count);
+        replay.run_frame().unwrap();
+    }
+    assert!(replay.registers == emu.registers && replay.cycle_count == emu.cycle_count);
+
+    assert!(Movie::parse("rom a.ch8\n").unwrap_err() == "the movie has no rom_crc32 line");
+    assert!(Movie::parse("rom a.ch8\nrom_crc32 0\n").unwrap_err() == "the movie has no seed line");
+    assert!(Movie::parse("quirks wobbly\n").unwrap_err() == "line 1: unknown quirk wobbly");
+
+    // a movie can't replay without the interpreter it was recorded with
+    std::fs::remove_file(&interpreter_path).unwrap();
+    assert!(loaded.apply(&mut Emulator::new()).is_err());
+}
+
+#[test]
+#[cfg(feature = "tools")]
+fn key_map_test() {
+    let config: KeysConfig = toml::from_str(
+        r#"
+        4 = ["Q", "Left"]
+        c = ["scancode:0x05", "scancode:30"]
+
+        [roms."Pong.ch8"]
+        1 = ["up"]
+        "#,
+    )
+    .unwrap();
+    let key_map = config.to_key_map("Pong.ch8").unwrap();
+    assert!(
+        key_map.bindings(0x4)
+            == [
+                HostKey::Named("Q".to_string()),
+                HostKey::Named("Left".to_string())
+            ]
+    );
+    assert!(key_map.bindings(0xC) == [HostKey::Scancode(0x05), HostKey::Scancode(0x1E)]);
+    assert!(key_map.bindings(0x1) == [HostKey::Named("up".to_string())]);
+    // anything not mentioned keeps its QWERTY binding
+    assert!(key_map.bindings(0x5) == KeyMap::qwerty().bindings(0x5));
+    assert!(
+        config.to_key_map("Tetris.ch8").unwrap().bindings(0x1) == KeyMap::qwerty().bindings(0x1)
+    );
+
+    // names follow the layout, so an AZERTY A (where QWERTY has Q) is still Q, and holding
+    // either of a key's bindings holds it down
+    let held = [
+        (0x1E, Some("Q".to_string())),
+        (0x0C8, Some("Up".to_string())),
+    ];
+    let key_states = key_map.key_states(&held);
+    assert!(key_states[0x4] && key_states[0xC] && key_states[0x1]);
+    // 0x1E is still 7's scancode too
+    assert!(key_states[0x7] && key_states.iter().filter(|held| **held).count() == 4);
+    assert!(key_map.key_states(&[(0x010, None)]) == [false; 16]);
+
+    let bad: KeysConfig = toml::from_str("10 = [\"A\"]").unwrap();
+    assert!(bad.to_key_map("").unwrap_err() == "10 isn't a key, keys are 0-F");
+    assert!(HostKey::parse("scancode:zz").is_err());
+}
+
+#[test]
+fn platform_test() {
+    assert!(Opcode::new(0xD125).platform() == Platform::Chip8);
+    assert!(Opcode::new(0x00FF).platform() == Platform::SuperChip);
+    assert!(Opcode::new(0xD120).platform() == Platform::SuperChip);
+    assert!(Opcode::new(0xF375).platform() == Platform::SuperChip);
+    assert!(Opcode::new(0x5122).platform() == Platform::XoChip);
+    assert!(Opcode::new(0xF000).platform() == Platform::XoChip);
+    assert!(Opcode::new(0xF201).platform() == Platform::XoChip);
+    assert!(Platform::Chip8 < Platform::SuperChip && Platform::SuperChip < Platform::XoChip);
+
+    // the sprite after the jump is never reached
+    let rom = [0x00, 0xFF, 0x12, 0x00, 0xF0, 0x00];
+    let instructions = reachable_instructions(&rom);
+    assert!(instructions.len() == 2 && instructions[&0x202] == 0x1200);
+    assert!(!instructions.contains_key(&0x204));
+}
+
+#[test]
+fn cli_test() {
+    let args: Vec<String> = ["game.ch8", "--debug", "--ipf", "20", "--rng", "vip"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let (positional, options) = cli::split_args(&args, &["--debug"]).unwrap();
+    assert!(positional == ["game.ch8"]);
+    assert!(options.len() == 3 && options[0] == ("--debug".to_string(), String::new()));
+    assert!(cli::split_args(&args[..3], &["--debug"]).is_err());
+
+    let mut machine = MachineOptions::default();
+    for (option, value) in &options[1..] {
+        assert!(machine.apply_option(option, value) == Ok(true));
+    }
+    assert!(machine.apply_option("--frames", "5") == Ok(false));
+    assert!(machine.apply_option("--rng", "dice").is_err());
+    // a frame has to run something, and big numbers aren't cut down to fit
+    assert!(machine.apply_option("--ipf", "0").is_err());
+    assert!(machine.apply_option("--ipf", "4294967296").is_err());
+    assert!(machine.apply_option("--ipf", "-1").is_err());
+    assert!(cli::parse_instructions_per_frame("4294967295") == Ok(u32::MAX));
+    assert!(Limit::from_option("--frames", "5") == Ok(Some(Limit::Frames(5))));
+    assert!(Limit::from_option("--instructions", "7") == Ok(Some(Limit::Instructions(7))));
+    assert!(Limit::from_option("--ipf", "5") == Ok(None));
+    assert!(Limit::from_option("--frames", "lots").is_err());
+    assert!(Limit::default() == Limit::Frames(60));
+    assert!(machine.instructions_per_frame == 20 && machine.rng_mode == RngMode::CosmacVip);
+
+    // the seed option wins over the caller's default
+    let mut emu = Emulator::new();
+    machine.configure(&mut emu, 5).unwrap();
+    assert!(emu.rng.seed() == 5 && emu.instructions_per_frame == 20);
+    assert!(cli::vip_rng_warning(&emu).is_some());
+    machine.apply_option("--seed", "9").unwrap();
+    machine.configure(&mut emu, 5).unwrap();
+    assert!(emu.rng.seed() == 9);
+
+    // ADD V0, 1; JP 0x200, a frame is 20 instructions
+    emu.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    let mut frames = 0;
+    cli::run_headless(&mut emu, Limit::Frames(3), &InputScript::default(), |_| {
+        frames += 1;
+        Ok(())
+    })
+    .unwrap();
+    assert!(frames == 3 && emu.cycle_count == 60 && emu.registers[0] == 30);
+    cli::run_headless(
+        &mut emu,
+        Limit::Instructions(65),
+        &InputScript::default(),
+        |_| Ok(()),
+    )
+    .unwrap();
+    assert!(emu.cycle_count == 65);
+}
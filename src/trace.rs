@@ -0,0 +1,269 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use serde_derive::Deserialize;
+
+use crate::emulator::Opcode;
+
+// Traces are written one entry per executed instruction, either as text:
+//
+//     cycle       PC    op    disassembly            I    DT ST  registers changed
+//     0000000042  0204  7301  ADD V3, 0x01           0300 00 00  V3=04
+//
+// or in a compact binary format for long runs, which chip8_rust can read back. The binary
+// format is MAGIC and BINARY_VERSION, then for each entry: the cycles since the last entry as
+// a LEB128 varint, PC, opcode and I as big endian u16s, DT and ST, a big endian u16 with a bit
+// set for each register the instruction changed (bit 0 for V0), and the new value of each of
+// those registers in order
+const MAGIC: &[u8; 4] = b"C8TR";
+const BINARY_VERSION: u8 = 1;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+// which instructions make it into the trace, by the address they're at and the cycle they run
+// on. Both ranges include their start, the address range includes its end and the cycles stop
+// just before stop_cycle
+#[derive(Clone, Debug)]
+pub struct TraceFilter {
+    pub start_addr: u16,
+    pub end_addr: u16,
+    pub start_cycle: u64,
+    pub stop_cycle: Option<u64>,
+}
+
+impl TraceFilter {
+    pub fn includes(&self, cycle: u64, pc: u16) -> bool {
+        pc >= self.start_addr
+            && pc <= self.end_addr
+            && cycle >= self.start_cycle
+            && !matches!(self.stop_cycle, Some(stop) if cycle >= stop)
+    }
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        TraceFilter {
+            start_addr: 0,
+            end_addr: 0xFFFF,
+            start_cycle: 0,
+            stop_cycle: None,
+        }
+    }
+}
+
+// one executed instruction, with the state it left behind
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceEntry {
+    // instructions executed before this one
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    // (register, new value) for each register the instruction changed
+    pub changed_registers: Vec<(u8, u8)>,
+    pub address_register: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceEntry {
+    pub fn disassembly(&self) -> String {
+        Opcode::new(self.opcode)
+            .disassemble()
+            .unwrap_or_else(|| format!("DW 0x{:04X}", self.opcode))
+    }
+}
+
+// an entry as a line of a text trace, without the newline
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:010}  {:04X}  {:04X}  {:<22} {:04X} {:02X} {:02X}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            self.disassembly(),
+            self.address_register,
+            self.delay_timer,
+            self.sound_timer
+        )?;
+        if !self.changed_registers.is_empty() {
+            write!(f, " ")?;
+        }
+        for (register, value) in &self.changed_registers {
+            write!(f, " V{:X}={:02X}", register, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Give one to Emulator::tracer and every instruction the filter lets through is written out
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    pub filter: TraceFilter,
+    // the cycle of the last entry written, binary entries only hold the cycles since it
+    last_cycle: Option<u64>,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>, format: TraceFormat) -> Self {
+        Tracer {
+            writer,
+            format,
+            filter: TraceFilter::default(),
+            last_cycle: None,
+        }
+    }
+
+    pub fn create(path: &str, format: TraceFormat) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Tracer::new(Box::new(file), format))
+    }
+
+    pub fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", entry)?,
+            TraceFormat::Binary => {
+                let mut bytes = Vec::new();
+                let since = match self.last_cycle {
+                    Some(last) => entry.cycle - last,
+                    None => {
+                        bytes.extend_from_slice(MAGIC);
+                        bytes.push(BINARY_VERSION);
+                        entry.cycle
+                    }
+                };
+                write_varint(&mut bytes, since);
+                bytes.extend_from_slice(&entry.pc.to_be_bytes());
+                bytes.extend_from_slice(&entry.opcode.to_be_bytes());
+                bytes.extend_from_slice(&entry.address_register.to_be_bytes());
+                bytes.push(entry.delay_timer);
+                bytes.push(entry.sound_timer);
+                let mask = entry
+                    .changed_registers
+                    .iter()
+                    .fold(0_u16, |mask, (register, _)| mask | 1 << register);
+                bytes.extend_from_slice(&mask.to_be_bytes());
+                bytes.extend(entry.changed_registers.iter().map(|(_, value)| value));
+                self.writer.write_all(&bytes)?;
+            }
+        }
+        self.last_cycle = Some(entry.cycle);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// reads back a trace written in the binary format
+pub fn read_binary_trace(bytes: &[u8]) -> Result<Vec<TraceEntry>, String> {
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+    if bytes.len() < 5 || &bytes[..4] != MAGIC {
+        return Err("not a binary trace".to_string());
+    }
+    if bytes[4] != BINARY_VERSION {
+        return Err(format!(
+            "binary trace is version {}, only version {} is supported",
+            bytes[4], BINARY_VERSION
+        ));
+    }
+    let truncated = || "the trace is cut short".to_string();
+    let mut position = 5;
+    let take = |len: usize, position: &mut usize| {
+        let taken = bytes
+            .get(*position..*position + len)
+            .ok_or_else(truncated)?;
+        *position += len;
+        Ok::<&[u8], String>(taken)
+    };
+    let word = |taken: &[u8]| (taken[0] as u16) << 8 | taken[1] as u16;
+
+    let mut entries = Vec::new();
+    let mut cycle = 0;
+    while position < bytes.len() {
+        cycle += read_varint(bytes, &mut position).ok_or_else(truncated)?;
+        let fixed = take(10, &mut position)?;
+        let mask = word(&fixed[8..]);
+        let values = take(mask.count_ones() as usize, &mut position)?;
+        let changed_registers = (0..16_u8)
+            .filter(|register| mask & 1 << register != 0)
+            .zip(values.iter().copied())
+            .collect();
+        entries.push(TraceEntry {
+            cycle,
+            pc: word(&fixed[0..]),
+            opcode: word(&fixed[2..]),
+            changed_registers,
+            address_register: word(&fixed[4..]),
+            delay_timer: fixed[6],
+            sound_timer: fixed[7],
+        });
+    }
+    Ok(entries)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*position)?;
+        *position += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
+
+// the [trace] table in chip8_rust_config.toml
+#[derive(Debug, Deserialize)]
+pub struct TraceConfig {
+    pub file: String,
+    pub format: Option<String>,
+    pub start_address: Option<u16>,
+    pub end_address: Option<u16>,
+    pub start_cycle: Option<u64>,
+    pub stop_cycle: Option<u64>,
+}
+
+impl TraceConfig {
+    pub fn to_tracer(&self) -> Result<Tracer, String> {
+        let format = match self.format.as_deref() {
+            Some("text") | None => TraceFormat::Text,
+            Some("binary") => TraceFormat::Binary,
+            Some(other) => return Err(format!("unknown trace format {}", other)),
+        };
+        let mut tracer = Tracer::create(&self.file, format)
+            .map_err(|e| format!("couldn't create {}: {}", self.file, e))?;
+        let defaults = TraceFilter::default();
+        tracer.filter = TraceFilter {
+            start_addr: self.start_address.unwrap_or(defaults.start_addr),
+            end_addr: self.end_address.unwrap_or(defaults.end_addr),
+            start_cycle: self.start_cycle.unwrap_or(defaults.start_cycle),
+            stop_cycle: self.stop_cycle,
+        };
+        Ok(tracer)
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::Ordering;
+
+use crate::disasm::disassemble_at;
+use crate::emulator::{Emulator, InstructionResult};
+use crate::input::InputScript;
+
+// Compares a run of the emulator against a trace from another emulator, to find the first
+// instruction where they disagree. Reference traces are text, one line per instruction with the
+// machine state just before the instruction runs, as hex numbers separated by whitespace:
+//
+//     # PC  opcode V0 V1 V2 V3 V4 V5 V6 V7 V8 V9 VA VB VC VD VE VF I    DT ST
+//     0200  6303   00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 00 00
+//
+// DT and ST are optional, when they're left off the timers aren't compared. Numbers can have a
+// 0x prefix, and # starts a comment
+#[derive(Clone, PartialEq, Debug)]
+pub struct StepState {
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub address_register: u16,
+    // (DT, ST)
+    pub timers: Option<(u8, u8)>,
+}
+
+impl StepState {
+    pub fn of(emu: &Emulator) -> Self {
+        let pc = emu.program_counter as usize;
+        let opcode = match emu.memory_space.get(pc..pc + 2) {
+            Some(bytes) => (bytes[0] as u16) << 8 | bytes[1] as u16,
+            None => 0,
+        };
+        StepState {
+            pc: emu.program_counter,
+            opcode,
+            registers: emu.registers,
+            address_register: emu.address_register,
+            timers: Some((
+                emu.timer_counter.load(Ordering::Relaxed),
+                emu.sound_counter.load(Ordering::Relaxed),
+            )),
+        }
+    }
+
+    // the names of everything that's different, the timers only count if both have them
+    fn differences(&self, other: &StepState) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.pc != other.pc {
+            differences.push("PC".to_string());
+        }
+        if self.opcode != other.opcode {
+            differences.push("opcode".to_string());
+        }
+        for (i, (a, b)) in self.registers.iter().zip(&other.registers).enumerate() {
+            if a != b {
+                differences.push(format!("V{:X}", i));
+            }
+        }
+        if self.address_register != other.address_register {
+            differences.push("I".to_string());
+        }
+        if let (Some((dt, st)), Some((other_dt, other_st))) = (self.timers, other.timers) {
+            if dt != other_dt {
+                differences.push("DT".to_string());
+            }
+            if st != other_st {
+                differences.push("ST".to_string());
+            }
+        }
+        differences
+    }
+}
+
+// in the same layout as a reference line
+impl fmt::Display for StepState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X} {:04X} ", self.pc, self.opcode)?;
+        for register in &self.registers {
+            write!(f, " {:02X}", register)?;
+        }
+        write!(f, " {:04X}", self.address_register)?;
+        if let Some((dt, st)) = self.timers {
+            write!(f, " {:02X} {:02X}", dt, st)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReferenceStep {
+    // where it is in the reference file
+    pub line: usize,
+    pub state: StepState,
+}
+
+pub fn parse_reference(source: &str) -> Result<Vec<ReferenceStep>, String> {
+    let mut steps = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let text = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", i + 1, message);
+        if words.len() != 19 && words.len() != 21 {
+            return Err(error(format!(
+                "expected PC, opcode, V0-VF, I and optionally DT and ST, but found {} numbers",
+                words.len()
+            )));
+        }
+        let mut numbers = Vec::new();
+        for word in &words {
+            let digits = word.strip_prefix("0x").unwrap_or(word);
+            let number = u16::from_str_radix(digits, 16)
+                .map_err(|_| error(format!("{} isn't a hex number", word)))?;
+            numbers.push(number);
+        }
+        let byte = |index: usize| {
+            u8::try_from(numbers[index])
+                .map_err(|_| error(format!("{} doesn't fit in a byte", words[index])))
+        };
+        let mut registers = [0_u8; 16];
+        for (register, value) in registers.iter_mut().enumerate() {
+            *value = byte(2 + register)?;
+        }
+        let timers = if numbers.len() == 21 {
+            Some((byte(19)?, byte(20)?))
+        } else {
+            None
+        };
+        steps.push(ReferenceStep {
+            line: i + 1,
+            state: StepState {
+                pc: numbers[0],
+                opcode: numbers[1],
+                registers,
+                address_register: numbers[18],
+                timers,
+            },
+        });
+    }
+    Ok(steps)
+}
+
+// how many of the instructions before a divergence are shown with it
+const CONTEXT_STEPS: usize = 8;
+
+pub enum DiffResult {
+    // every step of the reference matched
+    Matched { steps: usize },
+    Diverged(Divergence),
+}
+
+pub struct Divergence {
+    // counting from 0, the instruction that disagreed
+    pub step: usize,
+    pub expected: ReferenceStep,
+    // None when our run stopped before getting here, with the reason in `stopped`
+    pub actual: Option<StepState>,
+    pub stopped: Option<String>,
+    // the instructions leading up to it, with their disassembly, oldest first
+    pub history: Vec<(StepState, String)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "diverged at step {} (reference line {})",
+            self.step, self.expected.line
+        )?;
+        writeln!(
+            f,
+            "            PC   op    V0 V1 V2 V3 V4 V5 V6 V7 V8 V9 VA VB VC VD VE VF I    DT ST"
+        )?;
+        writeln!(f, "  expected  {}", self.expected.state)?;
+        match &self.actual {
+            Some(actual) => {
+                writeln!(f, "  actual    {}", actual)?;
+                writeln!(
+                    f,
+                    "  differs   {}",
+                    self.expected.state.differences(actual).join(", ")
+                )?;
+            }
+            None => writeln!(
+                f,
+                "  actual    {}",
+                self.stopped.as_deref().unwrap_or("stopped")
+            )?,
+        }
+        if !self.history.is_empty() {
+            writeln!(f, "leading up to it:")?;
+            let first = self.step - self.history.len();
+            for (i, (state, text)) in self.history.iter().enumerate() {
+                writeln!(f, "  step {:<8} {}  {}", first + i, state, text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs the emulator alongside the reference, one instruction per step, with keys pressed as
+// the inputs say, until they disagree or the reference runs out
+pub fn diff(emu: &mut Emulator, reference: &[ReferenceStep], inputs: &InputScript) -> DiffResult {
+    let mut history: VecDeque<(StepState, String)> = VecDeque::new();
+    let mut stopped = None;
+    for (step, expected) in reference.iter().enumerate() {
+        let actual = StepState::of(emu);
+        if stopped.is_some() || !expected.state.differences(&actual).is_empty() {
+            return DiffResult::Diverged(Divergence {
+                step,
+                expected: expected.clone(),
+                actual: if stopped.is_some() {
+                    None
+                } else {
+                    Some(actual)
+                },
+                stopped,
+                history: history.into_iter().collect(),
+            });
+        }
+        let (text, _) = disassemble_at(&emu.memory_space, emu.program_counter as usize);
+        history.push_back((actual, text));
+        if history.len() > CONTEXT_STEPS {
+            history.pop_front();
+        }
+        stopped = run_step(emu, inputs);
+    }
+    DiffResult::Matched {
+        steps: reference.len(),
+    }
+}
+
+// runs one instruction, returning why if the emulator can't carry on
+fn run_step(emu: &mut Emulator, inputs: &InputScript) -> Option<String> {
+    // cycles spent waiting on a key don't execute anything, so they aren't steps
+    loop {
+        emu.key_states = inputs.key_states_at(emu.frame_count);
+        match emu.execute_next_instruction() {
+            Ok(InstructionResult::Terminated) => return Some("the rom exited".to_string()),
+            Ok(_) => (),
+            Err(e) => return Some(format!("stopped, {}", e)),
+        }
+        if !emu.is_waiting_for_key() {
+            return None;
+        }
+        if emu.frame_count > inputs.last_frame().unwrap_or(0) {
+            return Some("waiting for a key the inputs never press".to_string());
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    // the instruction at the address is about to run
+    Execute,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+// Traps one kind of access to the addresses from start to end inclusive. Watching an address
+// for more than one kind of access takes a watchpoint for each
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub access: Access,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, access: Access) -> Self {
+        Watchpoint { start, end, access }
+    }
+
+    pub fn matches(&self, addr: usize, access: Access) -> bool {
+        self.access == access && addr >= self.start as usize && addr <= self.end as usize
+    }
+}
+
+// A watched address being accessed by an instruction. Reads and executes leave the value as it
+// was, so for those old_value and new_value are the same
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WatchpointHit {
+    pub access: Access,
+    pub addr: u16,
+    pub old_value: u8,
+    pub new_value: u8,
+    // the instruction that made the access
+    pub pc: u16,
+}
+
+impl fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Write => write!(
+                f,
+                "write to {:#06x} by {:#06x}: {:#04x} -> {:#04x}",
+                self.addr, self.pc, self.old_value, self.new_value
+            ),
+            _ => write!(
+                f,
+                "{} of {:#06x} by {:#06x}: {:#04x}",
+                self.access, self.addr, self.pc, self.old_value
+            ),
+        }
+    }
+}